@@ -0,0 +1,77 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tokio::time::MissedTickBehavior;
+
+use crate::cipher::private_key_path;
+
+/// 运行期间检查密钥年龄的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// 私钥文件的修改时间作为密钥的创建时间，轮换密钥(删除key目录后重启)会重新生成文件
+pub fn key_created(root_path: &Path) -> io::Result<SystemTime> {
+    std::fs::metadata(private_key_path(root_path))?.modified()
+}
+
+/// 密钥超过max_age时返回已使用的时间，修改时间在未来(时钟调整)时视为没有超过
+fn expired(created: SystemTime, now: SystemTime, max_age: Duration) -> Option<Duration> {
+    match now.duration_since(created) {
+        Ok(age) if age > max_age => Some(age),
+        _ => None,
+    }
+}
+
+/// 检查密钥年龄，超过max_age时输出警告并返回已使用的时间
+pub fn check(root_path: &Path, max_age: Duration) -> io::Result<Option<Duration>> {
+    let created = key_created(root_path)?;
+    let age = expired(created, SystemTime::now(), max_age);
+    if let Some(age) = age {
+        log::warn!(
+            "密钥已使用{}天，超过--key-max-age-days={}，请轮换密钥(删除key目录后重启会重新生成)",
+            age.as_secs() / 86400,
+            max_age.as_secs() / 86400
+        );
+    }
+    Ok(age)
+}
+
+/// 每天检查一次密钥年龄，超过时输出警告
+pub async fn watch(root_path: PathBuf, max_age: Duration) {
+    let mut interval =
+        tokio::time::interval_at(tokio::time::Instant::now() + CHECK_INTERVAL, CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = check(&root_path, max_age) {
+            log::warn!("获取密钥创建时间失败 {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    #[test]
+    fn old_key_file() {
+        let root = std::env::temp_dir().join(format!("vnts_key_age_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("key")).unwrap();
+        assert!(check(&root, DAY).is_err());
+        let file = std::fs::File::create(private_key_path(&root)).unwrap();
+        assert_eq!(check(&root, DAY).unwrap(), None);
+        // 模拟100天前生成的密钥
+        file.set_modified(SystemTime::now() - 100 * DAY).unwrap();
+        drop(file);
+        let age = check(&root, 90 * DAY).unwrap().unwrap();
+        assert!(age >= 100 * DAY);
+        assert_eq!(check(&root, 365 * DAY).unwrap(), None);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let now = SystemTime::now();
+        assert_eq!(expired(now + DAY, now, DAY), None);
+        assert_eq!(expired(now - 2 * DAY, now, DAY), Some(2 * DAY));
+    }
+}
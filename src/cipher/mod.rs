@@ -1,13 +1,15 @@
 #[cfg(not(feature = "ring-cipher"))]
 mod aes_gcm_cipher;
 mod finger;
+pub mod key_age;
 #[cfg(feature = "ring-cipher")]
 mod ring_aes_gcm_cipher;
 mod rsa_cipher;
+pub mod self_test;
 
 #[cfg(not(feature = "ring-cipher"))]
 pub use aes_gcm_cipher::Aes256GcmCipher;
 pub use finger::Finger;
 #[cfg(feature = "ring-cipher")]
 pub use ring_aes_gcm_cipher::Aes256GcmCipher;
-pub use rsa_cipher::RsaCipher;
+pub use rsa_cipher::{private_key_path, RsaCipher};
@@ -1,5 +1,5 @@
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use crate::protocol::body::RsaSecretBody;
@@ -9,6 +9,11 @@ use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use sha2::Digest;
 
+/// 私钥文件的路径，不存在时启动会生成
+pub fn private_key_path(root_path: &Path) -> PathBuf {
+    root_path.join("key/private_key.pem")
+}
+
 #[derive(Clone)]
 pub struct RsaCipher {
     inner: Arc<Inner>,
@@ -22,7 +27,7 @@ struct Inner {
 
 impl RsaCipher {
     pub fn new(root_path: PathBuf) -> io::Result<Self> {
-        let priv_key_path = root_path.join("key/private_key.pem");
+        let priv_key_path = private_key_path(&root_path);
         let pub_key_path = root_path.join("key/public_key.pem");
         let private_key = if priv_key_path.exists() {
             let key = std::fs::read_to_string(priv_key_path)?;
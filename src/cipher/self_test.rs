@@ -0,0 +1,130 @@
+use std::io;
+use std::net::Ipv4Addr;
+
+use rand::RngCore;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{PublicKey, RsaPublicKey};
+use sha2::Digest;
+
+use crate::cipher::{Aes256GcmCipher, Finger, RsaCipher};
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::{NetPacket, Protocol, HEAD_LEN, MAX_TTL};
+
+const PAYLOAD: &[u8] = b"vnts self test";
+
+fn error(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
+
+fn packet(buf: Vec<u8>) -> io::Result<NetPacket<Vec<u8>>> {
+    let mut packet = NetPacket::new_encrypt(buf)?;
+    packet.set_default_version();
+    packet.set_protocol(Protocol::Service);
+    packet.set_source(Ipv4Addr::new(10, 26, 0, 2));
+    packet.set_destination(Ipv4Addr::new(10, 26, 0, 1));
+    packet.first_set_ttl(MAX_TTL);
+    Ok(packet)
+}
+
+/// 随机密钥加密再解密，确认数据还原，且被篡改的数据包无法解密
+pub fn aes_round_trip() -> io::Result<()> {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    let cipher = Aes256GcmCipher::new(key, Finger::new("self-test"));
+    let mut packet = packet(vec![0u8; HEAD_LEN + PAYLOAD.len() + ENCRYPTION_RESERVED])?;
+    packet.set_payload(PAYLOAD)?;
+    cipher.encrypt_ipv4(&mut packet)?;
+    if !packet.is_encrypt() || packet.payload().starts_with(PAYLOAD) {
+        return Err(error("aes-gcm encrypt did not change payload".into()));
+    }
+    let mut tampered = NetPacket::new(packet.buffer().to_vec())?;
+    tampered.payload_mut()[0] ^= 1;
+    if cipher.decrypt_ipv4(&mut tampered).is_ok() {
+        return Err(error("aes-gcm accepted tampered packet".into()));
+    }
+    cipher
+        .decrypt_ipv4(&mut packet)
+        .map_err(|e| error(format!("aes-gcm decrypt failed {}", e)))?;
+    if packet.payload() != PAYLOAD {
+        return Err(error("aes-gcm round trip mismatch".into()));
+    }
+    Ok(())
+}
+
+/// 按客户端握手的格式用公钥加密
+fn rsa_packet(public_key_der: &[u8]) -> io::Result<NetPacket<Vec<u8>>> {
+    let public_key = RsaPublicKey::from_public_key_der(public_key_der)
+        .map_err(|e| error(format!("rsa public key error {}", e)))?;
+    let packet = packet(vec![0u8; HEAD_LEN + ENCRYPTION_RESERVED])?;
+    // data + random(16) + finger(16)
+    let mut body = PAYLOAD.to_vec();
+    let mut random = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut random);
+    body.extend_from_slice(&random);
+    let mut nonce_raw = [0; 12];
+    nonce_raw[0..4].copy_from_slice(&packet.source().octets());
+    nonce_raw[4..8].copy_from_slice(&packet.destination().octets());
+    nonce_raw[8] = packet.protocol().into();
+    nonce_raw[9] = packet.transport_protocol();
+    nonce_raw[10] = packet.is_gateway() as u8;
+    nonce_raw[11] = packet.source_ttl();
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(&body);
+    hasher.update(nonce_raw);
+    let finger: [u8; 32] = hasher.finalize().into();
+    body.extend_from_slice(&finger[16..]);
+    let secret = public_key
+        .encrypt(
+            &mut rand::thread_rng(),
+            rsa::PaddingScheme::PKCS1v15Encrypt,
+            &body,
+        )
+        .map_err(|e| error(format!("rsa encrypt failed {}", e)))?;
+    let mut buf = packet.buffer()[..HEAD_LEN].to_vec();
+    buf.extend_from_slice(&secret);
+    NetPacket::new(buf)
+}
+
+/// 用公钥加密再用加载的私钥解密，确认密钥对匹配
+pub fn rsa_round_trip(rsa: &RsaCipher) -> io::Result<()> {
+    let packet = rsa_packet(rsa.public_key())?;
+    let secret_body = rsa
+        .decrypt(&packet)
+        .map_err(|e| error(format!("rsa decrypt failed {}", e)))?;
+    if secret_body.data() != PAYLOAD {
+        return Err(error("rsa round trip mismatch".into()));
+    }
+    Ok(())
+}
+
+/// 启动时的加解密自检，任何一项失败都返回错误
+pub fn run(rsa: Option<&RsaCipher>) -> io::Result<()> {
+    aes_round_trip()?;
+    if let Some(rsa) = rsa {
+        rsa_round_trip(rsa)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        aes_round_trip().unwrap();
+        let path = std::env::temp_dir().join(format!("vnts_self_test_{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        let rsa = RsaCipher::new(path.clone()).unwrap();
+        run(Some(&rsa)).unwrap();
+        // 用其他公钥加密的数据无法解密
+        let other = std::env::temp_dir().join(format!("vnts_self_test_{}_b", std::process::id()));
+        std::fs::create_dir_all(&other).unwrap();
+        let other_rsa = RsaCipher::new(other.clone()).unwrap();
+        assert!(rsa
+            .decrypt(&rsa_packet(other_rsa.public_key()).unwrap())
+            .is_err());
+        std::fs::remove_dir_all(&path).unwrap();
+        std::fs::remove_dir_all(&other).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+use crate::ConfigInfo;
+
+/// 配置变更记录，每次启动追加一行json
+#[derive(Debug, Serialize, Deserialize)]
+struct ConfigRecord {
+    timestamp: String,
+    // 整体配置的hash
+    hash: String,
+    // 和上一条记录相比变化的字段
+    changed_fields: Vec<String>,
+    // 各字段值的hash，用于下次启动时比较，不保存明文(包含密码)
+    fields: BTreeMap<String, String>,
+}
+
+/// 包含密码和token的字段，用每个安装随机生成的密钥计算hmac，记录文件泄露时不能用字典反推
+const SECRET_FIELDS: [&str; 4] = ["white_token", "token_meta", "udp_metrics_token", "password"];
+/// hmac密钥长度
+const KEY_LEN: usize = 32;
+
+fn value<T: Debug>(v: &T) -> String {
+    format!("{:?}", v)
+}
+
+/// 参与计算hash的配置字段，集合类型排序后输出，保证相同的配置得到相同的结果
+fn config_fields(config: &ConfigInfo) -> BTreeMap<&'static str, String> {
+    let white_token = config.white_token.as_ref().map(|set| set.sorted());
+    let mut group_routes: Vec<String> = config.group_routes.iter().map(|v| v.to_string()).collect();
+    group_routes.sort();
+    let mut fields = BTreeMap::new();
+    fields.insert("port", value(&config.port));
+    fields.insert("announce_addr", value(&config.announce_addr));
+    fields.insert("white_token", value(&white_token));
+    fields.insert("token_meta", value(&config.token_meta));
+    fields.insert("auth_webhook", value(&config.auth_webhook));
+    fields.insert("auth_webhook_timeout", value(&config.auth_webhook_timeout));
+    fields.insert(
+        "auth_webhook_fallback",
+        value(&config.auth_webhook_fallback),
+    );
+    fields.insert("client_allow_cidr", value(&config.client_allow_cidr));
+    fields.insert("block_client_version", value(&config.block_client_version));
+    fields.insert("reserved_group", value(&config.reserved_group));
+    fields.insert("field_limits", value(&config.field_limits));
+    fields.insert("group_routes", value(&group_routes));
+    fields.insert("gateway_client", value(&config.gateway_client));
+    fields.insert("no_broadcast_group", value(&config.no_broadcast_group));
+    fields.insert("name_policy", value(&config.name_policy));
+    fields.insert("gateway", value(&config.gateway));
+    fields.insert("netmask", value(&config.netmask));
+    fields.insert("alloc_strategy", value(&config.alloc_strategy));
+    fields.insert("duplicate_device", value(&config.duplicate_device));
+    fields.insert("on_exhaustion", value(&config.on_exhaustion));
+    fields.insert("subnet_overlap", value(&config.subnet_overlap));
+    fields.insert("offline_packet", value(&config.offline_packet));
+    fields.insert("offline_grace", value(&config.offline_grace));
+    fields.insert("max_total_clients", value(&config.max_total_clients));
+    fields.insert(
+        "max_pending_registrations",
+        value(&config.max_pending_registrations),
+    );
+    fields.insert("mem_limit_mb", value(&config.mem_limit_mb));
+    fields.insert("load_limit", value(&config.load_limit));
+    fields.insert("slow_join", value(&config.slow_join));
+    fields.insert("count_offline", value(&config.count_offline));
+    fields.insert("min_lease_secs", value(&config.min_lease_secs));
+    fields.insert("max_lease_secs", value(&config.max_lease_secs));
+    fields.insert("check_finger", value(&config.check_finger));
+    fields.insert("anti_spoof", value(&config.anti_spoof));
+    fields.insert("tcp_write_timeout", value(&config.tcp_write_timeout));
+    fields.insert("tcp_max_frame", value(&config.tcp_max_frame));
+    fields.insert("cipher_session_ttl", value(&config.cipher_session_ttl));
+    fields.insert("max_session_age", value(&config.max_session_age));
+    fields.insert("empty_group_evict", value(&config.empty_group_evict));
+    fields.insert("udp_send_retries", value(&config.udp_send_retries));
+    fields.insert("udp_recv_buffer", value(&config.udp_recv_buffer));
+    fields.insert("udp_send_buffer", value(&config.udp_send_buffer));
+    fields.insert("nat_test_port", value(&config.nat_test_port));
+    fields.insert("context_refresh", value(&config.context_refresh));
+    fields.insert("cache_shards", value(&config.cache_shards));
+    fields.insert("broadcast_rate", value(&config.broadcast_rate));
+    fields.insert("udp_metrics_token", value(&config.udp_metrics_token));
+    fields.insert("handshake_hint_rate", value(&config.handshake_hint_rate));
+    fields.insert("ban_threshold", value(&config.ban_threshold));
+    fields.insert("ban_duration", value(&config.ban_duration));
+    fields.insert("membership_log", value(&config.membership_log));
+    fields.insert("membership_coalesce", value(&config.membership_coalesce));
+    fields.insert("event_socket", value(&config.event_socket));
+    fields.insert("summary_interval", value(&config.summary_interval));
+    fields.insert("group_bytes_log", value(&config.group_bytes_log));
+    #[cfg(feature = "web")]
+    {
+        fields.insert("username", value(&config.username));
+        fields.insert("password", value(&config.password));
+        fields.insert("web_lock_timeout", value(&config.web_lock_timeout));
+        fields.insert("web_api_only", value(&config.web_api_only));
+        fields.insert("web_static_dir", value(&config.web_static_dir));
+        fields.insert("web_access_log", value(&config.web_access_log));
+        fields.insert("log_tail_max_lines", value(&config.log_tail_max_lines));
+        fields.insert("logs_max_kb", value(&config.logs_max_kb));
+    }
+    #[cfg(feature = "geoip")]
+    fields.insert("geoip_db", value(&config.geoip_db));
+    fields
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(data);
+    hasher
+        .finalize()
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+fn hmac_sha256_hex(key: &[u8], data: &[u8]) -> String {
+    const BLOCK_LEN: usize = 64;
+    let mut block = [0u8; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        block[..32].copy_from_slice(&sha2::Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = sha2::Sha256::new();
+    inner.update(block.map(|v| v ^ 0x36));
+    inner.update(data);
+    let mut outer = sha2::Sha256::new();
+    outer.update(block.map(|v| v ^ 0x5c));
+    outer.update(inner.finalize());
+    outer
+        .finalize()
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+/// 读取hmac密钥，不存在或无效时重新生成，重新生成后密码和token字段会被记为变化一次
+fn load_key(path: &Path) -> io::Result<Vec<u8>> {
+    if let Ok(text) = std::fs::read_to_string(path) {
+        let text = text.trim();
+        if text.len() == KEY_LEN * 2 {
+            let key: Option<Vec<u8>> = (0..KEY_LEN)
+                .map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok())
+                .collect();
+            if let Some(key) = key {
+                return Ok(key);
+            }
+        }
+    }
+    let key: Vec<u8> = (0..KEY_LEN).map(|_| rand::random::<u8>()).collect();
+    let hex: String = key.iter().map(|v| format!("{:02x}", v)).collect();
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    writeln!(options.open(path)?, "{}", hex)?;
+    Ok(key)
+}
+
+fn build_record(config: &ConfigInfo, last: Option<&ConfigRecord>, key: &[u8]) -> ConfigRecord {
+    let mut all = String::new();
+    let mut fields = BTreeMap::new();
+    for (name, value) in config_fields(config) {
+        let value = if SECRET_FIELDS.contains(&name) {
+            hmac_sha256_hex(key, value.as_bytes())
+        } else {
+            value
+        };
+        all.push_str(name);
+        all.push('=');
+        all.push_str(&value);
+        all.push('\n');
+        fields.insert(name.to_string(), sha256_hex(value.as_bytes()));
+    }
+    let changed_fields = match last {
+        Some(last) => {
+            let mut changed: Vec<String> = fields
+                .iter()
+                .filter(|(name, hash)| last.fields.get(*name) != Some(*hash))
+                .map(|(name, _)| name.clone())
+                .collect();
+            // 新版本去掉的字段
+            changed.extend(
+                last.fields
+                    .keys()
+                    .filter(|name| !fields.contains_key(*name))
+                    .cloned(),
+            );
+            changed
+        }
+        None => Vec::new(),
+    };
+    ConfigRecord {
+        timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        hash: sha256_hex(all.as_bytes()),
+        changed_fields,
+        fields,
+    }
+}
+
+/// 计算当前配置的hash并追加到变更记录文件，返回hash，
+/// 密码和token字段的hmac密钥保存在同目录下扩展名为key的文件中
+pub fn record(path: &Path, config: &ConfigInfo) -> io::Result<String> {
+    let key = load_key(&path.with_extension("key"))?;
+    let last = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| {
+            text.lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .map(|line| line.to_string())
+        })
+        .and_then(|line| serde_json::from_str::<ConfigRecord>(&line).ok());
+    let record = build_record(config, last.as_ref(), &key);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let line = serde_json::to_string(&record).map_err(io::Error::other)?;
+    writeln!(file, "{}", line)?;
+    if !record.changed_fields.is_empty() {
+        log::info!("配置已变化 changed_fields={:?}", record.changed_fields);
+    }
+    Ok(record.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConfigInfo {
+        ConfigInfo {
+            port: 29872,
+            announce_addr: None,
+            white_token: Some(crate::util::SharedTokenSet::new(
+                vec!["b".to_string(), "a".to_string()],
+                false,
+            )),
+            token_meta: Default::default(),
+            auth_webhook: None,
+            auth_webhook_timeout: std::time::Duration::from_secs(1),
+            auth_webhook_fallback: crate::AuthFallback::Deny,
+            client_allow_cidr: None,
+            block_client_version: vec![],
+            reserved_group: vec![],
+            field_limits: crate::FieldLimits {
+                device_id: 128,
+                name: 128,
+                version: 64,
+                oversized: crate::OversizedField::Reject,
+            },
+            group_routes: vec![],
+            gateway_client: Default::default(),
+            name_policy: Default::default(),
+            no_broadcast_group: Default::default(),
+            gateway: "10.26.0.1".parse().unwrap(),
+            broadcast: "10.26.0.255".parse().unwrap(),
+            netmask: "255.255.255.0".parse().unwrap(),
+            alloc_strategy: crate::AllocStrategy::Sequential,
+            duplicate_device: crate::DuplicateDevice::Takeover,
+            on_exhaustion: crate::OnExhaustion::Reject,
+            subnet_overlap: crate::SubnetOverlap::Warn,
+            offline_packet: crate::OfflinePacket::Drop,
+            offline_grace: std::time::Duration::from_secs(10),
+            max_total_clients: None,
+            max_pending_registrations: None,
+            mem_limit_mb: None,
+            load_limit: None,
+            slow_join: std::time::Duration::from_secs(1),
+            count_offline: crate::CountOffline::Yes,
+            min_lease_secs: 600,
+            max_lease_secs: 3600,
+            check_finger: false,
+            anti_spoof: true,
+            tcp_write_timeout: std::time::Duration::from_secs(30),
+            tcp_max_frame: 65536,
+            cipher_session_ttl: std::time::Duration::from_secs(120),
+            max_session_age: None,
+            empty_group_evict: None,
+            udp_send_retries: 2,
+            udp_recv_buffer: None,
+            udp_send_buffer: None,
+            nat_test_port: None,
+            context_refresh: None,
+            cache_shards: 1,
+            broadcast_rate: None,
+            udp_metrics_token: None,
+            handshake_hint_rate: 20,
+            ban_threshold: None,
+            ban_duration: std::time::Duration::from_secs(600),
+            membership_log: false,
+            membership_coalesce: std::time::Duration::ZERO,
+            event_socket: None,
+            summary_interval: None,
+            group_bytes_log: None,
+            config_hash: String::new(),
+            #[cfg(feature = "web")]
+            username: "admin".into(),
+            #[cfg(feature = "web")]
+            password: "admin".into(),
+            #[cfg(feature = "web")]
+            web_lock_timeout: std::time::Duration::from_secs(1),
+            #[cfg(feature = "web")]
+            web_api_only: false,
+            #[cfg(feature = "web")]
+            web_static_dir: None,
+            #[cfg(feature = "web")]
+            web_access_log: false,
+            #[cfg(feature = "web")]
+            log_tail_max_lines: 1000,
+            #[cfg(feature = "web")]
+            logs_max_kb: 256,
+            #[cfg(feature = "web")]
+            log_file: None,
+            #[cfg(feature = "geoip")]
+            geoip_db: None,
+        }
+    }
+
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    #[test]
+    fn changed_fields() {
+        let first = build_record(&config(), None, &KEY);
+        assert!(first.changed_fields.is_empty());
+        // 集合的顺序不影响hash
+        let same = build_record(&config(), Some(&first), &KEY);
+        assert_eq!(same.hash, first.hash);
+        assert!(same.changed_fields.is_empty());
+        let mut changed = config();
+        changed.port = 29873;
+        changed.check_finger = true;
+        changed.max_pending_registrations = Some(64);
+        let record = build_record(&changed, Some(&first), &KEY);
+        assert_ne!(record.hash, first.hash);
+        assert_eq!(
+            record.changed_fields,
+            vec!["check_finger", "max_pending_registrations", "port"]
+        );
+        assert!(!serde_json::to_string(&record).unwrap().contains("29873"));
+    }
+
+    #[test]
+    fn secret_fields_keyed() {
+        // RFC 4231 测试用例2
+        assert_eq!(
+            hmac_sha256_hex(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let config = config();
+        let record = build_record(&config, None, &KEY);
+        let plain = value(&config.white_token.as_ref().map(|set| set.sorted()));
+        // 不保存可以用字典反推的hash
+        assert_ne!(record.fields["white_token"], sha256_hex(plain.as_bytes()));
+        let other = build_record(&config, None, &[8; KEY_LEN]);
+        assert_ne!(other.fields["white_token"], record.fields["white_token"]);
+        assert_ne!(other.hash, record.hash);
+        assert_eq!(other.fields["port"], record.fields["port"]);
+        let mut changed = config;
+        changed.udp_metrics_token = Some("secret".into());
+        let changed = build_record(&changed, Some(&record), &KEY);
+        assert_eq!(changed.changed_fields, vec!["udp_metrics_token"]);
+    }
+
+    #[test]
+    fn key_file() {
+        let path = std::env::temp_dir().join(format!("vnts_config_{}.key", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key = load_key(&path).unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(load_key(&path).unwrap(), key);
+        std::fs::write(&path, "invalid").unwrap();
+        assert_ne!(load_key(&path).unwrap(), key);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
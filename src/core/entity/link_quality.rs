@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// 参与估算的心跳数
+const WINDOW: usize = 16;
+/// 心跳间隔超过这个值则重新开始估算，客户端时间戳是u16毫秒，65秒会回绕
+const MAX_GAP_MS: i64 = 60_000;
+
+/// 根据心跳估算的链路质量
+///
+/// 心跳里带有客户端的毫秒时间戳，服务端只能看到单向的数据，
+/// 所以用到达间隔与发送间隔之差估算排队时延(乘2近似往返)，用客户端时间跳过的心跳数估算丢包，
+/// 用到达间隔与发送间隔之差的绝对值的平均估算抖动
+#[derive(Default)]
+pub struct LinkQuality {
+    // 上一次心跳的到达时间和客户端时间戳
+    last: Option<(Instant, u16)>,
+    // 累计的(到达间隔-发送间隔)
+    offset: i64,
+    // 最近的心跳 (offset, 发送间隔, 丢失数, 到达间隔-发送间隔)
+    samples: VecDeque<(i64, i64, u32, i64)>,
+    rtt_ms: u32,
+}
+
+impl LinkQuality {
+    pub fn on_heartbeat(&mut self, now: Instant, client_time: u16) {
+        let last = self.last.replace((now, client_time));
+        let (last_at, last_time) = match last {
+            Some(v) => v,
+            None => return,
+        };
+        let client_delta = client_time.wrapping_sub(last_time) as i64;
+        let server_delta = now.saturating_duration_since(last_at).as_millis() as i64;
+        if client_delta == 0 || server_delta > MAX_GAP_MS {
+            // 重复的心跳或间隔太久，之前的数据没有参考价值
+            self.offset = 0;
+            self.samples.clear();
+            return;
+        }
+        self.offset += server_delta - client_delta;
+        let interval = self
+            .samples
+            .iter()
+            .map(|(_, delta, _, _)| *delta)
+            .chain(Some(client_delta))
+            .min()
+            .unwrap();
+        let missed = ((client_delta + interval / 2) / interval - 1) as u32;
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((
+            self.offset,
+            client_delta,
+            missed,
+            server_delta - client_delta,
+        ));
+        let min_offset = self
+            .samples
+            .iter()
+            .map(|(offset, _, _, _)| *offset)
+            .min()
+            .unwrap();
+        let rtt = ((self.offset - min_offset) * 2) as u32;
+        self.rtt_ms = (self.rtt_ms * 7 + rtt) / 8;
+    }
+}
+
+// 目前只有web后台展示
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+impl LinkQuality {
+    pub fn rtt_ms(&self) -> u32 {
+        self.rtt_ms
+    }
+    pub fn loss_pct(&self) -> f32 {
+        let missed: u32 = self.samples.iter().map(|(_, _, missed, _)| *missed).sum();
+        if missed == 0 {
+            return 0.0;
+        }
+        missed as f32 * 100.0 / (missed as usize + self.samples.len()) as f32
+    }
+    pub fn jitter_ms(&self) -> u32 {
+        if self.samples.is_empty() {
+            return 0;
+        }
+        let sum: i64 = self.samples.iter().map(|(_, _, _, diff)| diff.abs()).sum();
+        (sum / self.samples.len() as i64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn feed(quality: &mut LinkQuality, start: Instant, beats: &[(u64, u16)]) {
+        for (arrive, time) in beats {
+            quality.on_heartbeat(start + Duration::from_millis(*arrive), *time);
+        }
+    }
+
+    #[test]
+    fn steady_link() {
+        let mut quality = LinkQuality::default();
+        let start = Instant::now();
+        let beats: Vec<(u64, u16)> = (0..10).map(|i| (i * 5000, (i * 5000) as u16)).collect();
+        feed(&mut quality, start, &beats);
+        assert_eq!(quality.rtt_ms(), 0);
+        assert_eq!(quality.loss_pct(), 0.0);
+        assert_eq!(quality.jitter_ms(), 0);
+    }
+
+    #[test]
+    fn missed_heartbeat() {
+        let mut quality = LinkQuality::default();
+        let start = Instant::now();
+        // 第3个心跳丢失
+        feed(
+            &mut quality,
+            start,
+            &[(0, 0), (5000, 5000), (15000, 15000), (20000, 20000)],
+        );
+        assert_eq!(quality.loss_pct(), 25.0);
+    }
+
+    #[test]
+    fn queuing_delay() {
+        let mut quality = LinkQuality::default();
+        let start = Instant::now();
+        // 客户端时间戳回绕，后两个心跳晚到100ms
+        feed(
+            &mut quality,
+            start,
+            &[(0, 65000), (5000, 4464), (10100, 9464), (15100, 14464)],
+        );
+        assert!(quality.rtt_ms() > 0);
+        assert_eq!(quality.loss_pct(), 0.0);
+    }
+
+    #[test]
+    fn jitter() {
+        let mut quality = LinkQuality::default();
+        let start = Instant::now();
+        // 到达间隔交替偏差+40ms/-40ms
+        feed(
+            &mut quality,
+            start,
+            &[
+                (0, 0),
+                (5040, 5000),
+                (10000, 10000),
+                (15040, 15000),
+                (20000, 20000),
+            ],
+        );
+        assert_eq!(quality.jitter_ms(), 40);
+        assert_eq!(quality.loss_pct(), 0.0);
+    }
+}
@@ -1,8 +1,17 @@
 use chrono::{DateTime, Local};
-use std::collections::HashMap;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
 
+use crate::util::{Cidr, RateLimiter, TokenMeta};
+
+mod link_quality;
+pub use link_quality::LinkQuality;
+
 /// 网段信息
 #[derive(Default)]
 pub struct NetworkInfo {
@@ -18,9 +27,42 @@ pub struct NetworkInfo {
     pub epoch: u64,
     // 网段下的客户端列表 ip->ClientInfo
     pub clients: HashMap<u32, ClientInfo>,
+    // 暂停组内转发，客户端保持在线
+    pub paused: bool,
+    // 暂停期间丢弃的数据包数
+    pub paused_drop: AtomicU64,
+    // 暂停新客户端加入，已注册的客户端可以继续注册和转发
+    pub join_paused: bool,
+    // 是否转发广播和组播，--no-broadcast-group指定的组网为false
+    pub allow_broadcast: bool,
+    // 禁止广播而丢弃的数据包数
+    pub broadcast_drop: AtomicU64,
+    // 组内客户端发出并由服务端转发的字节数(含广播和组网间转发)，客户端离开不影响，组网回收后重新计数
+    pub forward_bytes: AtomicU64,
+    // 组网标签，用于批量操作，组网回收时一起清理
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub tags: HashSet<String>,
+    // --gateway-client指定的网关客户端注册后的ip
+    pub gateway_client: Option<u32>,
+    // --token-meta配置的客户信息，没有配置时为空
+    pub meta: Option<Arc<TokenMeta>>,
 }
 
 impl NetworkInfo {
+    /// 组内转发已暂停则计数并返回true
+    pub fn check_paused(&self) -> bool {
+        if self.paused {
+            self.paused_drop.fetch_add(1, Ordering::Relaxed);
+        }
+        self.paused
+    }
+    /// 组网禁止了广播和组播则计数并返回true
+    pub fn check_broadcast_disabled(&self) -> bool {
+        if !self.allow_broadcast {
+            self.broadcast_drop.fetch_add(1, Ordering::Relaxed);
+        }
+        !self.allow_broadcast
+    }
     pub fn new(network_ip: u32, mask_ip: u32, gateway_ip: u32) -> Self {
         Self {
             network_ip,
@@ -28,10 +70,189 @@ impl NetworkInfo {
             gateway_ip,
             epoch: 0,
             clients: Default::default(),
+            paused: false,
+            paused_drop: AtomicU64::new(0),
+            join_paused: false,
+            allow_broadcast: true,
+            broadcast_drop: AtomicU64::new(0),
+            forward_bytes: AtomicU64::new(0),
+            tags: Default::default(),
+            gateway_client: None,
+            meta: None,
         }
     }
 }
 
+/// web后台登录会话
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub struct AuthSession {
+    pub username: String,
+    pub create_time: DateTime<Local>,
+    // 最后一次使用的时间戳
+    pub last_active: AtomicI64,
+}
+
+/// 转发时udp发送的统计
+#[derive(Default)]
+pub struct UdpSendStats {
+    // 发送缓冲区暂时满，放入重试的数据包数
+    pub requeued: AtomicU64,
+    // 最终丢弃的数据包数
+    pub dropped: AtomicU64,
+}
+
+/// 转发的数据包统计，广播时每个接收方各计一次
+#[derive(Default)]
+pub struct ForwardStats {
+    pub packets: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+/// 发往掉线客户端的数据包的处理统计
+#[derive(Default)]
+pub struct OfflinePacketStats {
+    // 直接丢弃的数据包数
+    pub dropped: AtomicU64,
+    // 放入队列的数据包数
+    pub queued: AtomicU64,
+    // 客户端重新上线后补发的数据包数
+    pub flushed: AtomicU64,
+    // 队列满或超过宽限时间而丢弃的数据包数
+    pub expired: AtomicU64,
+    // 回复了不可达通知的数据包数
+    pub rejected: AtomicU64,
+}
+
+/// 注册耗时直方图各桶的上限(毫秒)，最后还有一个+Inf
+pub const JOIN_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// 注册过程的阶段，rsa和aes在加密握手时统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinPhase {
+    Rsa,
+    Aes,
+    // 版本、白名单和auth webhook校验
+    Token,
+    // 等待组网写锁
+    LockWait,
+    // 分配ip
+    Alloc,
+    // 整个注册请求
+    Total,
+}
+
+impl JoinPhase {
+    pub const ALL: [JoinPhase; 6] = [
+        JoinPhase::Rsa,
+        JoinPhase::Aes,
+        JoinPhase::Token,
+        JoinPhase::LockWait,
+        JoinPhase::Alloc,
+        JoinPhase::Total,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JoinPhase::Rsa => "rsa",
+            JoinPhase::Aes => "aes",
+            JoinPhase::Token => "token",
+            JoinPhase::LockWait => "lock_wait",
+            JoinPhase::Alloc => "alloc",
+            JoinPhase::Total => "total",
+        }
+    }
+}
+
+/// 注册各阶段耗时的直方图
+#[derive(Default)]
+pub struct JoinHistogram {
+    // 各阶段落在每个桶的次数(不累加)，最后一个为+Inf
+    buckets: [[AtomicU64; JOIN_BUCKETS_MS.len() + 1]; JoinPhase::ALL.len()],
+    sum_us: [AtomicU64; JoinPhase::ALL.len()],
+}
+
+impl JoinHistogram {
+    pub fn observe(&self, phase: JoinPhase, duration: Duration) {
+        let index = JOIN_BUCKETS_MS
+            .iter()
+            .position(|ms| duration <= Duration::from_millis(*ms))
+            .unwrap_or(JOIN_BUCKETS_MS.len());
+        self.buckets[phase as usize][index].fetch_add(1, Ordering::Relaxed);
+        self.sum_us[phase as usize].fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+    pub fn snapshot(&self) -> Vec<PhaseHistogram> {
+        JoinPhase::ALL
+            .iter()
+            .map(|phase| {
+                let mut count = 0;
+                let buckets = self.buckets[*phase as usize]
+                    .iter()
+                    .map(|v| {
+                        count += v.load(Ordering::Relaxed);
+                        count
+                    })
+                    .collect();
+                PhaseHistogram {
+                    phase: phase.as_str(),
+                    buckets,
+                    sum_us: self.sum_us[*phase as usize].load(Ordering::Relaxed),
+                    count,
+                }
+            })
+            .collect()
+    }
+}
+
+/// 一个阶段的耗时分布，buckets按JOIN_BUCKETS_MS累加，最后一个为+Inf
+#[derive(Debug, Default, serde::Serialize)]
+pub struct PhaseHistogram {
+    pub phase: &'static str,
+    pub buckets: Vec<u64>,
+    pub sum_us: u64,
+    pub count: u64,
+}
+
+/// 运行指标快照
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Metrics {
+    // 组网数
+    pub group_num: u64,
+    // 客户端数
+    pub client_num: u64,
+    // 在线客户端数
+    pub online_num: u64,
+    // 组内转发暂停期间丢弃的数据包数
+    pub paused_drop: u64,
+    // 转发的数据包数和字节数
+    pub forward_packets: u64,
+    pub forward_bytes: u64,
+    // udp发送重试的数据包数
+    pub udp_requeued: u64,
+    // udp发送丢弃的数据包数
+    pub udp_dropped: u64,
+    // 组网间转发的数据包数
+    pub route_forwarded: u64,
+    // 组网间转发丢弃的数据包数
+    pub route_dropped: u64,
+    // 发往掉线客户端的数据包，按处理结果计数
+    pub offline_dropped: u64,
+    pub offline_queued: u64,
+    pub offline_flushed: u64,
+    pub offline_expired: u64,
+    pub offline_rejected: u64,
+    // 版本在--block-client-version中而拒绝的注册数
+    pub version_blocked: u64,
+    // 处理中的注册超过--max-pending-registrations而拒绝的注册数
+    pub registration_shed: u64,
+    // --anti-spoof丢弃的源虚拟ip伪造的数据包数
+    pub spoof_dropped: u64,
+    // 过期任务通道满需要等待的次数
+    pub expire_send_blocked: u64,
+    // 过期任务已退出导致投递失败的次数
+    pub expire_send_failed: u64,
+    // 注册各阶段的耗时
+    pub join_timing: Vec<PhaseHistogram>,
+}
+
 /// 客户端信息
 pub struct ClientInfo {
     // 设备ID
@@ -55,6 +276,85 @@ pub struct ClientInfo {
     pub client_status: Option<ClientStatusInfo>,
     pub last_join_time: DateTime<Local>,
     pub timestamp: i64,
+    // 掉线时间
+    pub offline_time: i64,
+    // 心跳估算的链路质量，只在心跳时更新，持有读锁即可修改
+    pub link_quality: Mutex<LinkQuality>,
+    // 掉线期间暂存的数据包(入队时间,数据)，重新上线时补发
+    pub offline_queue: Mutex<VecDeque<(Instant, Vec<u8>)>>,
+    // 广播限速，开启--broadcast-rate时注册设置
+    pub broadcast_limiter: Option<RateLimiter>,
+    // 超过广播限速而丢弃的数据包数
+    pub broadcast_dropped: AtomicU64,
+    // 注册时上报的和虚拟网段重叠的本地网段
+    pub subnet_overlaps: Vec<Cidr>,
+    // 经服务端中继发给同组网其他客户端的字节数(不含广播)
+    pub relayed_bytes: AtomicU64,
+    pub role: ClientRole,
+    // 客户端上报的PMTUD测得的有效MTU，没有上报时为None，重新注册时清空
+    pub effective_mtu: Option<u32>,
+    // 注册时协商了压缩，服务端解压该客户端发来的压缩包，发给该客户端时压缩
+    pub compress: bool,
+    // 经过压缩的数据量，重新注册时清空
+    pub compress_stats: CompressStats,
+    // 被管理员移到其他组网，下一次心跳时回应注册响应通知客户端新的地址
+    pub reassigned: AtomicBool,
+}
+
+/// 压缩前后的字节数，包含收发两个方向
+#[derive(Default)]
+pub struct CompressStats {
+    pub raw_bytes: AtomicU64,
+    pub compressed_bytes: AtomicU64,
+}
+
+impl CompressStats {
+    pub fn add(&self, raw: usize, compressed: usize) {
+        self.raw_bytes.fetch_add(raw as u64, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+    }
+    /// 压缩后和压缩前的比值，没有压缩过数据时为None
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn ratio(&self) -> Option<f32> {
+        let raw = self.raw_bytes.load(Ordering::Relaxed);
+        if raw == 0 {
+            return None;
+        }
+        Some(self.compressed_bytes.load(Ordering::Relaxed) as f32 / raw as f32)
+    }
+}
+
+/// 客户端在组网中的角色
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ClientRole {
+    #[default]
+    Normal,
+    // --gateway-client指定的网关客户端，转发目标不在虚拟网段内的数据包
+    Gateway,
+}
+
+impl ClientRole {
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientRole::Normal => "normal",
+            ClientRole::Gateway => "gateway",
+        }
+    }
+}
+
+impl ClientInfo {
+    /// 广播是否在限速内，超过时计数并返回false
+    pub fn allow_broadcast(&self) -> bool {
+        if let Some(limiter) = &self.broadcast_limiter {
+            if !limiter.check() {
+                self.broadcast_dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+        true
+    }
 }
 
 impl Default for ClientInfo {
@@ -72,6 +372,18 @@ impl Default for ClientInfo {
             client_status: None,
             last_join_time: Local::now(),
             timestamp: 0,
+            offline_time: 0,
+            link_quality: Default::default(),
+            offline_queue: Default::default(),
+            broadcast_limiter: None,
+            broadcast_dropped: AtomicU64::new(0),
+            subnet_overlaps: Vec::new(),
+            relayed_bytes: AtomicU64::new(0),
+            role: ClientRole::Normal,
+            effective_mtu: None,
+            compress: false,
+            compress_stats: Default::default(),
+            reassigned: AtomicBool::new(false),
         }
     }
 }
@@ -2,4 +2,7 @@ mod entity;
 mod server;
 mod service;
 mod store;
+#[cfg(feature = "web")]
+pub use server::check_web_assets;
 pub use server::start;
+pub use service::event_socket::EventSocket;
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+use crate::core::store::cache::AppCache;
+
+/// 距上次输出的增量，计数比上次小说明组网被回收后重新创建，从0开始计算
+fn delta(total: u64, last: Option<u64>) -> u64 {
+    match last {
+        Some(last) if total >= last => total - last,
+        _ => total,
+    }
+}
+
+/// 定时输出各组网转发的字节数，bytes为距上次输出的增量，没有新流量的组网不输出；
+/// 拿不到读锁的组网跳过，增量计入下次输出
+pub async fn start(cache: AppCache, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last: HashMap<String, u64> = HashMap::new();
+    loop {
+        ticker.tick().await;
+        let mut current = HashMap::with_capacity(last.len());
+        for (group, network_info) in cache.virtual_network.key_values() {
+            let total = match network_info.try_read() {
+                Some(guard) => guard.forward_bytes.load(Ordering::Relaxed),
+                None => {
+                    if let Some(total) = last.remove(&group) {
+                        current.insert(group, total);
+                    }
+                    continue;
+                }
+            };
+            let bytes = delta(total, last.get(&group).copied());
+            if bytes > 0 {
+                log::info!(
+                    "组网流量 group={},bytes={},bytes_per_sec={},total={}",
+                    group,
+                    bytes,
+                    bytes / interval.as_secs().max(1),
+                    total
+                );
+            }
+            current.insert(group, total);
+        }
+        // 已回收的组网不再保留
+        last = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_delta() {
+        assert_eq!(delta(1500, None), 1500);
+        assert_eq!(delta(1500, Some(1000)), 500);
+        assert_eq!(delta(1500, Some(1500)), 0);
+        // 组网回收后重新创建
+        assert_eq!(delta(200, Some(1500)), 200);
+    }
+}
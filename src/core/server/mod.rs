@@ -4,36 +4,80 @@ use std::sync::Arc;
 use tokio::net::{TcpListener, UdpSocket};
 
 use crate::cipher::RsaCipher;
+use crate::core::service::event_socket::EventSocket;
+use crate::core::service::pressure;
 use crate::core::service::PacketHandler;
-use crate::core::store::cache::AppCache;
+use crate::core::store::cache::{AppCache, CacheConfig};
 use crate::ConfigInfo;
 
+mod group_bytes;
+mod nat_test;
+mod summary;
 mod tcp;
 mod udp;
 #[cfg(feature = "web")]
 mod web;
 
+#[cfg(feature = "web")]
+pub use web::check_assets as check_web_assets;
+
 pub async fn start(
     udp: std::net::UdpSocket,
     tcp: std::net::TcpListener,
-    #[cfg(feature = "web")] http: Option<std::net::TcpListener>,
+    nat_test: Option<std::net::UdpSocket>,
+    #[cfg(feature = "web")] http: Vec<(std::net::TcpListener, crate::util::WebCapability)>,
+    events: EventSocket,
     config: ConfigInfo,
     rsa_cipher: Option<RsaCipher>,
 ) -> io::Result<()> {
     let udp = Arc::new(UdpSocket::from_std(udp)?);
-    let cache = AppCache::new();
+    let cache = AppCache::new(
+        config.membership_log,
+        config.membership_coalesce,
+        events,
+        CacheConfig::from(&config),
+    );
+    for route in &config.group_routes {
+        cache.group_routes.add(route.clone());
+    }
     let handler = PacketHandler::new(
         cache.clone(),
         config.clone(),
         rsa_cipher.clone(),
         udp.clone(),
     );
-    let tcp_handle = tokio::spawn(tcp::start(TcpListener::from_std(tcp)?, handler.clone()));
+    if let Some(nat_test) = nat_test {
+        let port = nat_test.local_addr()?.port();
+        tokio::spawn(nat_test::start(
+            UdpSocket::from_std(nat_test)?,
+            port,
+            config.gateway,
+        ));
+    }
+    if let Some(interval) = config.summary_interval {
+        tokio::spawn(summary::start(cache.clone(), interval));
+    }
+    if config.mem_limit_mb.is_some() || config.load_limit.is_some() {
+        let limits = pressure::Limits {
+            mem_mb: config.mem_limit_mb,
+            load: config.load_limit,
+        };
+        tokio::spawn(pressure::start(limits, cache.resource_pressure.clone()));
+    }
+    if let Some(interval) = config.group_bytes_log {
+        tokio::spawn(group_bytes::start(cache.clone(), interval));
+    }
+    let tcp_handle = tokio::spawn(tcp::start(
+        TcpListener::from_std(tcp)?,
+        handler.clone(),
+        config.tcp_write_timeout,
+        config.tcp_max_frame,
+    ));
     let udp_handle = tokio::spawn(udp::start(udp, handler.clone()));
     #[cfg(not(feature = "web"))]
     let _ = tokio::try_join!(tcp_handle, udp_handle);
     #[cfg(feature = "web")]
-    if let Some(http) = http {
+    if !http.is_empty() {
         if let Err(e) = web::start(http, cache, config).await {
             log::error!("{:?}", e);
         }
@@ -0,0 +1,40 @@
+use tokio::net::UdpSocket;
+
+use crate::core::service::server::nat_test_response;
+use crate::protocol::{control_packet, NetPacket, Protocol, MAX_TTL};
+
+/// NAT测试端口，只回应发给服务端的未加密NAT测试请求，其他数据包直接丢弃
+pub async fn start(udp: UdpSocket, port: u16, gateway: std::net::Ipv4Addr) {
+    let mut buf = [0u8; 1500];
+    loop {
+        let (len, addr) = match udp.recv_from(&mut buf).await {
+            Ok(rs) => rs,
+            Err(e) => {
+                log::error!("nat test {:?}", e);
+                continue;
+            }
+        };
+        let request = match NetPacket::new(&buf[..len]) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+        if request.is_encrypt()
+            || !request.is_gateway()
+            || request.protocol() != Protocol::Control
+            || control_packet::Protocol::from(request.transport_protocol())
+                != control_packet::Protocol::NatTestRequest
+        {
+            continue;
+        }
+        if let Some(mut packet) = nat_test_response(&request, addr, port) {
+            packet.set_default_version();
+            packet.set_destination(request.source());
+            packet.set_source(gateway);
+            packet.first_set_ttl(MAX_TTL);
+            packet.set_gateway_flag(true);
+            if let Err(e) = udp.send_to(packet.buffer(), addr).await {
+                log::warn!("nat test {:?} {}", e, addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+use crate::core::store::cache::AppCache;
+
+/// 累计计数，用于计算两次概况之间的增量
+#[derive(Default, Clone, Copy)]
+struct Counters {
+    forward_packets: u64,
+    forward_bytes: u64,
+    dropped: u64,
+}
+
+impl Counters {
+    fn read(cache: &AppCache, paused_drop: u64) -> Self {
+        let offline = &cache.offline_stats;
+        let dropped = cache.udp_send_stats.dropped.load(Ordering::Relaxed)
+            + offline.dropped.load(Ordering::Relaxed)
+            + offline.expired.load(Ordering::Relaxed)
+            + offline.rejected.load(Ordering::Relaxed)
+            + paused_drop;
+        Self {
+            forward_packets: cache.forward_stats.packets.load(Ordering::Relaxed),
+            forward_bytes: cache.forward_stats.bytes.load(Ordering::Relaxed),
+            dropped,
+        }
+    }
+    fn delta(&self, last: &Counters) -> Counters {
+        Counters {
+            forward_packets: self.forward_packets.saturating_sub(last.forward_packets),
+            forward_bytes: self.forward_bytes.saturating_sub(last.forward_bytes),
+            dropped: self.dropped.saturating_sub(last.dropped),
+        }
+    }
+}
+
+/// 定时输出运行概况，转发和丢弃为距上次概况的增量；
+/// 只读取原子计数，统计在线数时拿不到读锁的组网直接跳过，不阻塞转发和注册
+pub async fn start(cache: AppCache, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // 第一次tick立即完成
+    ticker.tick().await;
+    let mut last = Counters::read(&cache, 0);
+    loop {
+        ticker.tick().await;
+        let mut groups = 0;
+        let mut online = 0;
+        let mut paused_drop = 0;
+        for (_, network_info) in cache.virtual_network.key_values() {
+            groups += 1;
+            if let Some(guard) = network_info.try_read() {
+                online += guard.clients.values().filter(|v| v.online).count();
+                paused_drop += guard.paused_drop.load(Ordering::Relaxed);
+            }
+        }
+        let now = Counters::read(&cache, paused_drop);
+        let delta = now.delta(&last);
+        last = now;
+        log::info!(
+            "运行概况 groups={},clients={},online={},forward_packets={},forward_bytes={},dropped={},ip_session={},addr_session={},cipher_session={}",
+            groups,
+            cache.client_count.load(Ordering::Relaxed),
+            online,
+            delta.forward_packets,
+            delta.forward_bytes,
+            delta.dropped,
+            cache.ip_session.size(),
+            cache.addr_session.size(),
+            cache.cipher_session.size()
+        );
+    }
+}
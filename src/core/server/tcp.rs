@@ -2,56 +2,95 @@ use crate::core::service::PacketHandler;
 use crate::protocol::NetPacket;
 use std::io;
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::tcp::OwnedReadHalf;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 
-pub async fn start(tcp: TcpListener, handler: PacketHandler) {
-    if let Err(e) = accept(tcp, handler).await {
+/// 连续多少帧格式错误时断开连接
+const MAX_BAD_FRAMES: u32 = 16;
+
+pub async fn start(
+    tcp: TcpListener,
+    handler: PacketHandler,
+    write_timeout: Duration,
+    max_frame: usize,
+) {
+    if let Err(e) = accept(tcp, handler, write_timeout, max_frame).await {
         log::error!("accept {:?}", e);
     }
 }
 
-async fn accept(tcp: TcpListener, handler: PacketHandler) -> io::Result<()> {
+async fn accept(
+    tcp: TcpListener,
+    handler: PacketHandler,
+    write_timeout: Duration,
+    max_frame: usize,
+) -> io::Result<()> {
     loop {
         let (stream, addr) = tcp.accept().await?;
         let _ = stream.set_nodelay(true);
-        stream_handle(stream, addr, handler.clone()).await;
+        stream_handle(stream, addr, handler.clone(), write_timeout, max_frame).await;
     }
 }
 
-async fn stream_handle(stream: TcpStream, addr: SocketAddr, handler: PacketHandler) {
-    let (r, mut w) = stream.into_split();
+async fn stream_handle(
+    stream: TcpStream,
+    addr: SocketAddr,
+    handler: PacketHandler,
+    write_timeout: Duration,
+    max_frame: usize,
+) {
+    let (r, w) = stream.into_split();
 
-    let (sender, mut receiver) = channel::<Vec<u8>>(100);
+    let (sender, receiver) = channel::<Vec<u8>>(100);
+    tokio::spawn(tcp_write(w, addr, receiver, write_timeout));
     tokio::spawn(async move {
-        while let Some(data) = receiver.recv().await {
-            let len = data.len();
-            if let Err(e) = w
-                .write_all(&[
-                    (len >> 24) as u8,
-                    (len >> 16) as u8,
-                    (len >> 8) as u8,
-                    len as u8,
-                ])
-                .await
-            {
+        if let Err(e) = tcp_read(r, addr, sender, handler, max_frame).await {
+            log::warn!("tcp_read {:?}", e)
+        }
+    });
+}
+
+async fn tcp_write<W: AsyncWrite + Unpin>(
+    mut w: W,
+    addr: SocketAddr,
+    mut receiver: Receiver<Vec<u8>>,
+    write_timeout: Duration,
+) {
+    while let Some(data) = receiver.recv().await {
+        if data.is_empty() {
+            // 空数据表示服务端主动断开连接
+            log::info!("服务端主动断开连接:{:?}", addr);
+            break;
+        }
+        let len = data.len();
+        let head = [
+            (len >> 24) as u8,
+            (len >> 16) as u8,
+            (len >> 8) as u8,
+            len as u8,
+        ];
+        // 对端不接收数据时写操作会一直阻塞，超时则断开连接
+        let rs = tokio::time::timeout(write_timeout, async {
+            w.write_all(&head).await?;
+            w.write_all(&data).await
+        })
+        .await;
+        match rs {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
                 log::info!("发送失败,链接终止:{:?},{:?}", addr, e);
                 break;
             }
-            if let Err(e) = w.write_all(&data).await {
-                log::info!("发送失败,链接终止:{:?},{:?}", addr, e);
+            Err(_) => {
+                log::info!("发送超时,链接终止:{:?}", addr);
                 break;
             }
         }
-        let _ = w.shutdown().await;
-    });
-    tokio::spawn(async move {
-        if let Err(e) = tcp_read(r, addr, sender, handler).await {
-            log::warn!("tcp_read {:?}", e)
-        }
-    });
+    }
+    let _ = w.shutdown().await;
 }
 
 async fn tcp_read(
@@ -59,24 +98,17 @@ async fn tcp_read(
     addr: SocketAddr,
     sender: Sender<Vec<u8>>,
     handler: PacketHandler,
+    max_frame: usize,
 ) -> io::Result<()> {
-    let mut head = [0; 4];
-    let mut buf = [0; 65536];
+    // 按配置的最大帧长分配，连接数多时可以减少内存占用
+    let mut buf = vec![0; max_frame];
     let sender = Some(sender);
+    let mut bad_frames = 0;
     loop {
-        read.read_exact(&mut head).await?;
-        let len = ((head[0] as usize) << 24)
-            | ((head[1] as usize) << 16)
-            | ((head[2] as usize) << 8)
-            | head[3] as usize;
-        if len < 12 || len > buf.len() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "length overflow",
-            ));
-        }
-        read.read_exact(&mut buf[..len]).await?;
-        let packet = NetPacket::new0(len, &mut buf)?;
+        let packet = match read_packet(&mut read, &mut buf, addr, &mut bad_frames).await? {
+            Some(packet) => packet,
+            None => continue,
+        };
         if let Some(rs) = handler.handle(packet, addr, &sender).await {
             if sender
                 .as_ref()
@@ -90,3 +122,150 @@ async fn tcp_read(
         }
     }
 }
+
+/// 读取下一个数据包，帧长度错误时无法继续分帧，返回错误断开连接；
+/// 帧完整但数据包格式错误时只跳过这一帧，返回None，bad_frames记录连续的格式错误帧数，
+/// 达到MAX_BAD_FRAMES时断开连接，避免对端持续发送错误帧刷日志
+async fn read_packet<'a, R: AsyncRead + Unpin>(
+    read: &mut R,
+    buf: &'a mut [u8],
+    addr: SocketAddr,
+    bad_frames: &mut u32,
+) -> io::Result<Option<NetPacket<&'a mut [u8]>>> {
+    let len = read_frame(read, buf).await?;
+    match NetPacket::new0(len, buf) {
+        Ok(packet) => {
+            *bad_frames = 0;
+            Ok(Some(packet))
+        }
+        Err(e) => {
+            *bad_frames += 1;
+            log::debug!(
+                "数据包格式错误，跳过该帧 addr={},len={},e={:?}",
+                addr,
+                len,
+                e
+            );
+            if *bad_frames >= MAX_BAD_FRAMES {
+                log::warn!("连续{}帧格式错误，断开连接 addr={}", bad_frames, addr);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "too many malformed frames",
+                ));
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// 读取一个数据帧到buf，返回帧长度，超过buf长度的帧直接拒绝
+async fn read_frame<R: AsyncRead + Unpin>(read: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut head = [0; 4];
+    read.read_exact(&mut head).await?;
+    let len = u32::from_be_bytes(head) as usize;
+    if len > buf.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("length overflow len={},max={}", len, buf.len()),
+        ));
+    }
+    read.read_exact(&mut buf[..len]).await?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn read_frame_max_len() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let mut buf = vec![0; 1500];
+        client.write_all(&1500u32.to_be_bytes()).await.unwrap();
+        client.write_all(&[1; 1500]).await.unwrap();
+        assert_eq!(read_frame(&mut server, &mut buf).await.unwrap(), 1500);
+        client.write_all(&1501u32.to_be_bytes()).await.unwrap();
+        let err = read_frame(&mut server, &mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn skip_malformed_frame() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let mut buf = vec![0; 1500];
+        let addr: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let mut bad_frames = 0;
+        // 正常帧、过短的帧、正常帧
+        for len in [12u32, 4, 20] {
+            client.write_all(&len.to_be_bytes()).await.unwrap();
+            client.write_all(&vec![1; len as usize]).await.unwrap();
+        }
+        let packet = read_packet(&mut server, &mut buf, addr, &mut bad_frames)
+            .await
+            .unwrap();
+        assert_eq!(packet.unwrap().data_len(), 12);
+        assert!(read_packet(&mut server, &mut buf, addr, &mut bad_frames)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(bad_frames, 1);
+        let packet = read_packet(&mut server, &mut buf, addr, &mut bad_frames)
+            .await
+            .unwrap();
+        assert_eq!(packet.unwrap().data_len(), 20);
+        assert_eq!(bad_frames, 0);
+        // 帧长度超过上限无法继续分帧
+        client.write_all(&1501u32.to_be_bytes()).await.unwrap();
+        assert!(read_packet(&mut server, &mut buf, addr, &mut bad_frames)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn disconnect_on_repeated_malformed() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let mut buf = vec![0; 1500];
+        let addr: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let mut bad_frames = 0;
+        // 空帧，只有长度头
+        for _ in 0..MAX_BAD_FRAMES {
+            client.write_all(&0u32.to_be_bytes()).await.unwrap();
+        }
+        for _ in 1..MAX_BAD_FRAMES {
+            assert!(read_packet(&mut server, &mut buf, addr, &mut bad_frames)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        let err = read_packet(&mut server, &mut buf, addr, &mut bad_frames)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn write_timeout_non_reading_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // 对端建立连接后不读取任何数据
+        let _peer = TcpStream::connect(addr).await.unwrap();
+        let (stream, peer_addr) = listener.accept().await.unwrap();
+        let (_r, w) = stream.into_split();
+        let (sender, receiver) = channel::<Vec<u8>>(100);
+        let start = Instant::now();
+        let writer = tokio::spawn(tcp_write(
+            w,
+            peer_addr,
+            receiver,
+            Duration::from_millis(200),
+        ));
+        // 填满双方的缓冲区，直到写任务超时退出
+        while sender.send(vec![0u8; 65536]).await.is_ok() {}
+        tokio::time::timeout(Duration::from_secs(10), writer)
+            .await
+            .expect("writer blocked")
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
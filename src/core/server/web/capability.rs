@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use crate::util::WebCapability;
+
+/// readonly可以访问的接口，只查询不修改
+const READ_ONLY_APIS: [&str; 16] = [
+    "/group_list",
+    "/server_info",
+    "/config",
+    "/status",
+    "/group_info",
+    "/group_utilization",
+    "/relay_stats",
+    "/all_clients",
+    "/client_p2p",
+    "/find_device",
+    "/group_routes",
+    "/preview_registration",
+    "/groups_by_tag",
+    "/dash",
+    "/metrics",
+    "/metrics.json",
+];
+
+const METRICS_APIS: [&str; 2] = ["/metrics", "/metrics.json"];
+
+/// 监听的权限是否允许访问path，都可以登录；
+/// 需要认证的接口按权限过滤，页面和静态文件admin和readonly可以访问
+pub fn allowed(capability: WebCapability, path: &str, api_set: &HashSet<String>) -> bool {
+    if path == "/login" {
+        return true;
+    }
+    match capability {
+        WebCapability::Admin => true,
+        WebCapability::ReadOnly => !api_set.contains(path) || READ_ONLY_APIS.contains(&path),
+        WebCapability::Metrics => METRICS_APIS.contains(&path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capability_paths() {
+        let api_set: HashSet<String> = ["/group_info", "/set_tokens", "/metrics", "/dash"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        for path in [
+            "/login",
+            "/group_info",
+            "/set_tokens",
+            "/metrics",
+            "/index.html",
+        ] {
+            assert!(allowed(WebCapability::Admin, path, &api_set), "{}", path);
+        }
+        assert!(allowed(WebCapability::ReadOnly, "/group_info", &api_set));
+        assert!(allowed(WebCapability::ReadOnly, "/dash", &api_set));
+        assert!(allowed(WebCapability::ReadOnly, "/index.html", &api_set));
+        assert!(!allowed(WebCapability::ReadOnly, "/set_tokens", &api_set));
+        assert!(allowed(WebCapability::Metrics, "/login", &api_set));
+        assert!(allowed(WebCapability::Metrics, "/metrics", &api_set));
+        assert!(!allowed(WebCapability::Metrics, "/group_info", &api_set));
+        assert!(!allowed(WebCapability::Metrics, "/index.html", &api_set));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt::Write;
+
+use crate::core::server::web::vo::GroupSummary;
+
+/// 服务端渲染的状态页，不依赖前端资源
+pub fn render(list: &[GroupSummary]) -> String {
+    let total: usize = list.iter().map(|v| v.total).sum();
+    let online: usize = list.iter().map(|v| v.online).sum();
+    let mut html = String::with_capacity(1024 + list.len() * 128);
+    html.push_str(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>vnts</title>\
+        <style>body{font-family:sans-serif;margin:2em}table{border-collapse:collapse}\
+        td,th{border:1px solid #ccc;padding:4px 12px;text-align:left}</style></head><body>",
+    );
+    let _ = write!(
+        html,
+        "<h3>vnts {}</h3><p>组网数: {} 客户端: {} 在线: {}</p>",
+        crate::VNT_VERSION,
+        list.len(),
+        total,
+        online
+    );
+    html.push_str("<table><tr><th>组网</th><th>在线</th><th>总数</th><th>状态</th></tr>");
+    for item in list {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&item.group),
+            item.online,
+            item.total,
+            if item.paused {
+                "暂停转发"
+            } else {
+                "正常"
+            }
+        );
+    }
+    html.push_str("</table></body></html>");
+    html
+}
+
+/// 组网名称来自客户端，需要转义
+fn escape(s: &str) -> String {
+    let mut rs = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => rs.push_str("&lt;"),
+            '>' => rs.push_str("&gt;"),
+            '&' => rs.push_str("&amp;"),
+            '"' => rs.push_str("&quot;"),
+            '\'' => rs.push_str("&#39;"),
+            c => rs.push(c),
+        }
+    }
+    rs
+}
@@ -0,0 +1,93 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use thiserror::Error;
+
+use crate::core::server::web::vo::ResponseMessage;
+
+/// web接口的错误，同时决定http状态码和响应体中的code
+#[derive(Error, Debug)]
+pub enum WebError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("{0}")]
+    RateLimited(String),
+    /// 功能没有开启，和账号密码错误区分开
+    #[error("{0}")]
+    Disabled(String),
+    /// 来源ip因认证失败过多被临时封禁
+    #[error("ip banned")]
+    Banned,
+    /// 和已有的数据冲突
+    #[error("{0}")]
+    Conflict(String),
+    /// 获取组网读锁超时
+    #[error("busy")]
+    Busy,
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<crate::error::Error> for WebError {
+    fn from(e: crate::error::Error) -> Self {
+        if e.is_client_fault() {
+            WebError::BadRequest(e.to_string())
+        } else {
+            WebError::Internal(e.to_string())
+        }
+    }
+}
+
+impl ResponseError for WebError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebError::NotFound(_) => StatusCode::NOT_FOUND,
+            WebError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            WebError::Unauthorized => StatusCode::UNAUTHORIZED,
+            WebError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            WebError::Disabled(_) => StatusCode::FORBIDDEN,
+            WebError::Banned => StatusCode::FORBIDDEN,
+            WebError::Conflict(_) => StatusCode::CONFLICT,
+            WebError::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            WebError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ResponseMessage::error(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_code() {
+        assert_eq!(
+            WebError::NotFound("no group found".into()).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            WebError::Disabled("web management disabled".into()).status_code(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(WebError::Banned.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            WebError::Busy.status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            WebError::from(crate::error::Error::TokenError).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let e = WebError::from(crate::error::Error::Channel(crossbeam::channel::RecvError));
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            e.error_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
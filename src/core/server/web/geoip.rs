@@ -0,0 +1,89 @@
+use std::net::IpAddr;
+#[cfg(feature = "geoip")]
+use std::path::Path;
+
+use moka::sync::Cache;
+
+/// 缓存的地址数
+const CACHE_SIZE: u64 = 10_000;
+
+/// 来源地址的大致位置
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoLocation {
+    pub country: Option<String>,
+    pub city: Option<String>,
+}
+
+/// 来源地址的地理位置查询，编译时开启geoip特性并指定--geoip-db时才有数据，
+/// 数据库启动时加载一次，查询结果按地址缓存
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    reader: Option<maxminddb::Reader<Vec<u8>>>,
+    cache: Cache<IpAddr, Option<GeoLocation>>,
+}
+
+impl GeoIp {
+    #[cfg(feature = "geoip")]
+    pub fn new(db: Option<&Path>) -> Self {
+        let reader = db.and_then(|path| match maxminddb::Reader::open_readfile(path) {
+            Ok(reader) => {
+                log::info!("加载GeoIP数据库 {:?}", path);
+                Some(reader)
+            }
+            Err(e) => {
+                log::error!("加载GeoIP数据库失败 {:?}: {:?}", path, e);
+                None
+            }
+        });
+        Self {
+            reader,
+            cache: Cache::new(CACHE_SIZE),
+        }
+    }
+    #[cfg(not(feature = "geoip"))]
+    pub fn new() -> Self {
+        Self {
+            cache: Cache::new(CACHE_SIZE),
+        }
+    }
+    /// 地址需要先还原成ipv4(如果是ipv4映射的地址)，查不到时返回None
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        if !self.enabled() {
+            return None;
+        }
+        self.cache.get_with(ip, || self.lookup0(ip))
+    }
+    #[cfg(feature = "geoip")]
+    fn enabled(&self) -> bool {
+        self.reader.is_some()
+    }
+    #[cfg(not(feature = "geoip"))]
+    fn enabled(&self) -> bool {
+        false
+    }
+    #[cfg(feature = "geoip")]
+    fn lookup0(&self, ip: IpAddr) -> Option<GeoLocation> {
+        use maxminddb::geoip2;
+        let reader = self.reader.as_ref()?;
+        let city: geoip2::City = reader.lookup(ip).ok()?.decode().ok()??;
+        let location = GeoLocation {
+            country: name(&city.country.names).or(city.country.iso_code.map(String::from)),
+            city: name(&city.city.names),
+        };
+        if location == GeoLocation::default() {
+            None
+        } else {
+            Some(location)
+        }
+    }
+    #[cfg(not(feature = "geoip"))]
+    fn lookup0(&self, _ip: IpAddr) -> Option<GeoLocation> {
+        None
+    }
+}
+
+/// 优先使用中文名称
+#[cfg(feature = "geoip")]
+fn name(names: &maxminddb::geoip2::Names) -> Option<String> {
+    names.simplified_chinese.or(names.english).map(String::from)
+}
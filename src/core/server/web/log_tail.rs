@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// 每次从文件末尾向前读取的字节数
+const CHUNK_SIZE: u64 = 8192;
+
+/// 读取文件的最后lines行，从末尾向前分块读取，不加载整个文件
+pub fn tail(path: &Path, lines: usize) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut pos = len;
+    let mut buf: Vec<u8> = Vec::new();
+    // 文件末尾的换行不算作一行
+    let mut newlines = 0;
+    while pos > 0 && newlines <= lines {
+        let size = CHUNK_SIZE.min(pos);
+        pos -= size;
+        file.seek(SeekFrom::Start(pos))?;
+        let mut chunk = vec![0u8; size as usize];
+        file.read_exact(&mut chunk)?;
+        newlines += chunk.iter().filter(|v| **v == b'\n').count();
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+    }
+    let text = String::from_utf8_lossy(&buf);
+    let all: Vec<&str> = text.lines().collect();
+    // 没读到文件开头时第一行可能不完整
+    let skip = if pos > 0 { 1 } else { 0 };
+    let start = all.len().saturating_sub(lines).max(skip.min(all.len()));
+    Ok(all[start..].iter().map(|v| v.to_string()).collect())
+}
+
+/// 读取文件最后不超过max_bytes字节的内容，从第一个完整的行开始
+pub fn tail_bytes(path: &Path, max_bytes: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let pos = len.saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(pos))?;
+    let mut buf = Vec::with_capacity((len - pos) as usize);
+    file.take(max_bytes).read_to_end(&mut buf)?;
+    if pos > 0 {
+        // 丢掉被截断的第一行
+        match buf.iter().position(|v| *v == b'\n') {
+            Some(index) => {
+                buf.drain(..=index);
+            }
+            None => buf.clear(),
+        }
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn tail_lines() {
+        let path = std::env::temp_dir().join(format!("vnts_log_tail_{}.log", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for i in 0..5000 {
+            writeln!(file, "line {}", i).unwrap();
+        }
+        drop(file);
+        let lines = tail(&path, 3).unwrap();
+        assert_eq!(lines, vec!["line 4997", "line 4998", "line 4999"]);
+        // 跨越多个块
+        let lines = tail(&path, 2000).unwrap();
+        assert_eq!(lines.len(), 2000);
+        assert_eq!(lines[0], "line 3000");
+        // 行数超过文件
+        assert_eq!(tail(&path, 10000).unwrap().len(), 5000);
+        assert!(tail(&path, 0).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+        assert!(tail(&path, 3).is_err());
+    }
+
+    #[test]
+    fn tail_bytes_whole_lines() {
+        let path = std::env::temp_dir().join(format!("vnts_tail_bytes_{}.log", std::process::id()));
+        std::fs::write(&path, "line 1\nline 2\nline 3\n").unwrap();
+        assert_eq!(tail_bytes(&path, 10).unwrap(), b"line 3\n");
+        assert_eq!(tail_bytes(&path, 3).unwrap(), b"");
+        assert_eq!(tail_bytes(&path, 1024).unwrap().len(), 21);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::core::entity::{Metrics, JOIN_BUCKETS_MS};
+use crate::core::server::web::vo::GroupUtilization;
+
+/// 一次采集的全部指标，/metrics和/metrics.json都由它渲染，两者的内容保持一致
+#[derive(Debug, Serialize)]
+pub struct MetricsSnapshot {
+    #[serde(flatten)]
+    pub metrics: Metrics,
+    pub max_clients: Option<usize>,
+    pub groups: Vec<GroupUtilization>,
+}
+
+/// 渲染成Prometheus文本格式
+pub fn render(snapshot: &MetricsSnapshot) -> String {
+    let metrics = &snapshot.metrics;
+    let max_clients = snapshot.max_clients;
+    let groups = &snapshot.groups;
+    let mut text = String::with_capacity(2048 + groups.len() * 256);
+    let gauges = [
+        ("vnts_groups", "组网数", metrics.group_num),
+        ("vnts_clients", "客户端数", metrics.client_num),
+        ("vnts_online_clients", "在线客户端数", metrics.online_num),
+    ];
+    for (name, help, value) in gauges {
+        sample(&mut text, name, help, "gauge", value);
+    }
+    if let Some(max_clients) = max_clients {
+        sample(
+            &mut text,
+            "vnts_max_clients",
+            "客户端总数上限",
+            "gauge",
+            max_clients as u64,
+        );
+    }
+    let counters = [
+        (
+            "vnts_paused_drop_total",
+            "组内转发暂停期间丢弃的数据包数",
+            metrics.paused_drop,
+        ),
+        (
+            "vnts_forward_packets_total",
+            "转发的数据包数",
+            metrics.forward_packets,
+        ),
+        (
+            "vnts_forward_bytes_total",
+            "转发的字节数",
+            metrics.forward_bytes,
+        ),
+        (
+            "vnts_udp_requeued_total",
+            "udp发送重试的数据包数",
+            metrics.udp_requeued,
+        ),
+        (
+            "vnts_udp_dropped_total",
+            "udp发送丢弃的数据包数",
+            metrics.udp_dropped,
+        ),
+        (
+            "vnts_route_forwarded_total",
+            "组网间转发的数据包数",
+            metrics.route_forwarded,
+        ),
+        (
+            "vnts_route_dropped_total",
+            "组网间转发丢弃的数据包数",
+            metrics.route_dropped,
+        ),
+        (
+            "vnts_version_blocked_total",
+            "客户端版本被禁止而拒绝的注册数",
+            metrics.version_blocked,
+        ),
+        (
+            "vnts_registration_shed_total",
+            "负载过高而拒绝的注册数",
+            metrics.registration_shed,
+        ),
+        (
+            "vnts_spoof_dropped_total",
+            "源虚拟ip伪造而丢弃的数据包数",
+            metrics.spoof_dropped,
+        ),
+        (
+            "vnts_expire_send_blocked_total",
+            "过期任务通道满需要等待的次数",
+            metrics.expire_send_blocked,
+        ),
+        (
+            "vnts_expire_send_failed_total",
+            "过期任务投递失败的次数",
+            metrics.expire_send_failed,
+        ),
+    ];
+    for (name, help, value) in counters {
+        sample(&mut text, name, help, "counter", value);
+    }
+    let _ = writeln!(
+        text,
+        "# HELP vnts_offline_packets_total 发往掉线客户端的数据包数\n# TYPE vnts_offline_packets_total counter"
+    );
+    let outcomes = [
+        ("dropped", metrics.offline_dropped),
+        ("queued", metrics.offline_queued),
+        ("flushed", metrics.offline_flushed),
+        ("expired", metrics.offline_expired),
+        ("rejected", metrics.offline_rejected),
+    ];
+    for (outcome, value) in outcomes {
+        let _ = writeln!(
+            text,
+            "vnts_offline_packets_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+    let _ = writeln!(
+        text,
+        "# HELP vnts_join_phase_seconds 注册各阶段的耗时\n# TYPE vnts_join_phase_seconds histogram"
+    );
+    for phase in &metrics.join_timing {
+        for (index, count) in phase.buckets.iter().enumerate() {
+            let le = match JOIN_BUCKETS_MS.get(index) {
+                Some(ms) => (*ms as f64 / 1000.0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                text,
+                "vnts_join_phase_seconds_bucket{{phase=\"{}\",le=\"{}\"}} {}",
+                phase.phase, le, count
+            );
+        }
+        let _ = writeln!(
+            text,
+            "vnts_join_phase_seconds_sum{{phase=\"{}\"}} {}\nvnts_join_phase_seconds_count{{phase=\"{}\"}} {}",
+            phase.phase,
+            phase.sum_us as f64 / 1_000_000.0,
+            phase.phase,
+            phase.count
+        );
+    }
+    group_sample(
+        &mut text,
+        "vnts_group_usable_hosts",
+        "组网可分配的地址数",
+        "gauge",
+        groups,
+        |v| v.usable as f64,
+    );
+    group_sample(
+        &mut text,
+        "vnts_group_assigned_hosts",
+        "组网已分配的地址数",
+        "gauge",
+        groups,
+        |v| v.assigned as f64,
+    );
+    group_sample(
+        &mut text,
+        "vnts_group_used_ratio",
+        "组网地址使用率(0~1)",
+        "gauge",
+        groups,
+        |v| v.used_pct / 100.0,
+    );
+    group_sample(
+        &mut text,
+        "vnts_group_bytes_total",
+        "组内客户端发出并由服务端转发的字节数，组网回收后重新计数",
+        "counter",
+        groups,
+        |v| v.forward_bytes as f64,
+    );
+    text
+}
+
+fn group_sample<F: Fn(&GroupUtilization) -> f64>(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    groups: &[GroupUtilization],
+    value: F,
+) {
+    let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+    for group in groups {
+        // 没有配置客户信息的组网不输出customer标签
+        if group.customer.is_empty() {
+            let _ = writeln!(
+                text,
+                "{}{{group=\"{}\"}} {}",
+                name,
+                escape(&group.group),
+                value(group)
+            );
+        } else {
+            let _ = writeln!(
+                text,
+                "{}{{group=\"{}\",customer=\"{}\"}} {}",
+                name,
+                escape(&group.group),
+                escape(&group.customer),
+                value(group)
+            );
+        }
+    }
+}
+
+fn sample(text: &mut String, name: &str, help: &str, kind: &str, value: u64) {
+    let _ = writeln!(
+        text,
+        "# HELP {} {}\n# TYPE {} {}\n{} {}",
+        name, help, name, kind, name, value
+    );
+}
+
+/// 组网名称来自客户端，标签值需要转义
+fn escape(s: &str) -> String {
+    let mut rs = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => rs.push_str("\\\\"),
+            '"' => rs.push_str("\\\""),
+            '\n' => rs.push_str("\\n"),
+            c => rs.push(c),
+        }
+    }
+    rs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_group_label() {
+        let groups = [
+            GroupUtilization {
+                group: "a\"b\\c\nd".to_string(),
+                customer: String::new(),
+                usable: 253,
+                assigned: 2,
+                free: 251,
+                used_pct: 50.0,
+                forward_bytes: 1500,
+            },
+            GroupUtilization {
+                group: "g".to_string(),
+                customer: "ACME".to_string(),
+                usable: 253,
+                assigned: 0,
+                free: 253,
+                used_pct: 0.0,
+                forward_bytes: 0,
+            },
+        ];
+        let snapshot = MetricsSnapshot {
+            metrics: Metrics::default(),
+            max_clients: Some(10),
+            groups: groups.into(),
+        };
+        let text = render(&snapshot);
+        assert!(text.contains("vnts_group_usable_hosts{group=\"a\\\"b\\\\c\\nd\"} 253\n"));
+        assert!(text.contains("vnts_group_used_ratio{group=\"a\\\"b\\\\c\\nd\"} 0.5\n"));
+        assert!(text.contains("vnts_group_assigned_hosts{group=\"g\",customer=\"ACME\"} 0\n"));
+        assert!(text.contains("# TYPE vnts_group_bytes_total counter\n"));
+        assert!(text.contains("vnts_group_bytes_total{group=\"a\\\"b\\\\c\\nd\"} 1500\n"));
+        assert!(text.contains("vnts_offline_packets_total{outcome=\"queued\"} 0\n"));
+        assert!(text.contains("# TYPE vnts_online_clients gauge\nvnts_online_clients 0\n"));
+        assert!(text.contains("\nvnts_max_clients 10\n"));
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["online_num"], 0);
+        assert_eq!(json["max_clients"], 10);
+        assert_eq!(json["groups"][1]["customer"], "ACME");
+    }
+
+    #[test]
+    fn render_join_histogram() {
+        let histogram = crate::core::entity::JoinHistogram::default();
+        histogram.observe(
+            crate::core::entity::JoinPhase::LockWait,
+            std::time::Duration::from_millis(20),
+        );
+        let snapshot = MetricsSnapshot {
+            metrics: Metrics {
+                join_timing: histogram.snapshot(),
+                ..Default::default()
+            },
+            max_clients: None,
+            groups: vec![],
+        };
+        let text = render(&snapshot);
+        assert!(text.contains("# TYPE vnts_join_phase_seconds histogram\n"));
+        assert!(
+            text.contains("vnts_join_phase_seconds_bucket{phase=\"lock_wait\",le=\"0.01\"} 0\n")
+        );
+        assert!(
+            text.contains("vnts_join_phase_seconds_bucket{phase=\"lock_wait\",le=\"0.05\"} 1\n")
+        );
+        assert!(
+            text.contains("vnts_join_phase_seconds_bucket{phase=\"lock_wait\",le=\"+Inf\"} 1\n")
+        );
+        assert!(text.contains("vnts_join_phase_seconds_sum{phase=\"lock_wait\"} 0.02\n"));
+        assert!(text.contains("vnts_join_phase_seconds_count{phase=\"rsa\"} 0\n"));
+    }
+}
@@ -1,28 +1,49 @@
 use std::collections::{HashMap, HashSet};
 use std::net;
+use std::path::Path;
 use std::sync::Arc;
 
+use actix_files::Files;
 use actix_web::dev::Service;
 use actix_web::web::Data;
-use actix_web::{middleware, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use actix_web::{
+    get, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer, ResponseError,
+};
 
 use actix_web_static_files::ResourceFiles;
 
+use crate::core::server::web::error::WebError;
 use crate::core::server::web::service::VntsWebService;
-use crate::core::server::web::vo::{LoginData, ResponseMessage};
+use crate::core::server::web::vo::{
+    AllClientsQuery, ChangePassword, ClientKey, DeviceQuery, GroupBundle, GroupList,
+    GroupRouteItem, GroupTags, LogTailQuery, LoginData, LogsQuery, MoveClient, PauseScope,
+    PreviewQuery, ResponseMessage, SetTokens,
+};
 use crate::core::store::cache::AppCache;
+use crate::util::WebCapability;
 use crate::ConfigInfo;
 
+mod capability;
+mod dash;
+mod error;
+mod geoip;
+mod log_tail;
+mod metrics;
 mod service;
 mod vo;
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
 #[post("/login")]
-async fn login(service: Data<VntsWebService>, data: web::Json<LoginData>) -> HttpResponse {
-    match service.login(data.0).await {
+async fn login(
+    req: HttpRequest,
+    service: Data<VntsWebService>,
+    data: web::Json<LoginData>,
+) -> HttpResponse {
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    match service.login(data.0, ip).await {
         Ok(auth) => HttpResponse::Ok().json(ResponseMessage::success(auth)),
-        Err(e) => HttpResponse::Ok().json(ResponseMessage::fail(e)),
+        Err(e) => e.error_response(),
     }
 }
 
@@ -32,6 +53,21 @@ async fn group_list(_req: HttpRequest, service: Data<VntsWebService>) -> HttpRes
     HttpResponse::Ok().json(ResponseMessage::success(info))
 }
 
+#[post("/server_info")]
+async fn server_info(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.server_info()))
+}
+
+#[post("/status")]
+async fn status(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.status()))
+}
+
+#[post("/config")]
+async fn config_info(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.config()))
+}
+
 #[post("/group_info")]
 async fn group_info(
     _req: HttpRequest,
@@ -39,13 +75,389 @@ async fn group_info(
     group: web::Json<HashMap<String, String>>,
 ) -> HttpResponse {
     if let Some(group) = group.get("group") {
-        let info = service.group_info(group.to_string());
-        HttpResponse::Ok().json(ResponseMessage::success(info))
+        match service.group_info(group.to_string()) {
+            Ok(info) => HttpResponse::Ok().json(ResponseMessage::success(info)),
+            Err(e) => e.error_response(),
+        }
+    } else {
+        WebError::NotFound("no group found".into()).error_response()
+    }
+}
+
+#[post("/group_utilization")]
+async fn group_utilization(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    group: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    if let Some(group) = group.get("group") {
+        match service.group_utilization(group.to_string()) {
+            Ok(info) => HttpResponse::Ok().json(ResponseMessage::success(info)),
+            Err(e) => e.error_response(),
+        }
     } else {
-        HttpResponse::Ok().json(ResponseMessage::fail("no group found".into()))
+        WebError::NotFound("no group found".into()).error_response()
     }
 }
 
+#[post("/pause_group")]
+async fn pause_group(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    group: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    pause_group0(service, group, true)
+}
+
+#[post("/resume_group")]
+async fn resume_group(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    group: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    pause_group0(service, group, false)
+}
+
+fn pause_group0(
+    service: Data<VntsWebService>,
+    group: web::Json<HashMap<String, String>>,
+    paused: bool,
+) -> HttpResponse {
+    // scope=join时只暂停新客户端加入，默认暂停组内转发
+    let scope = match group.get("scope").map(|v| v.as_str()) {
+        None | Some("forward") => PauseScope::Forward,
+        Some("join") => PauseScope::Join,
+        Some(_) => return WebError::BadRequest("scope error".into()).error_response(),
+    };
+    if let Some(group) = group.get("group") {
+        if service.pause_group(group, paused, scope) {
+            return HttpResponse::Ok().json(ResponseMessage::success(paused));
+        }
+    } else if let Some(tag) = group.get("tag") {
+        // 按标签批量操作，返回命中的组网数
+        return match service.pause_tag(tag, paused, scope) {
+            Ok(count) => HttpResponse::Ok().json(ResponseMessage::success(count)),
+            Err(e) => e.error_response(),
+        };
+    }
+    WebError::NotFound("no group found".into()).error_response()
+}
+
+#[post("/set_group_tags")]
+async fn set_group_tags(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    group_tags: web::Json<GroupTags>,
+) -> HttpResponse {
+    match service.set_group_tags(group_tags.0) {
+        Ok(()) => HttpResponse::Ok().json(ResponseMessage::success(true)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/export_group")]
+async fn export_group(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    group: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    if let Some(group) = group.get("group") {
+        match service.export_group(group.to_string()) {
+            Ok(bundle) => HttpResponse::Ok().json(ResponseMessage::success(bundle)),
+            Err(e) => e.error_response(),
+        }
+    } else {
+        WebError::NotFound("no group found".into()).error_response()
+    }
+}
+
+#[post("/import_group")]
+async fn import_group(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    bundle: web::Json<GroupBundle>,
+) -> HttpResponse {
+    match service.import_group(bundle.0).await {
+        Ok(()) => HttpResponse::Ok().json(ResponseMessage::success(true)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/groups_by_tag")]
+async fn groups_by_tag(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    tag: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    if let Some(tag) = tag.get("tag") {
+        match service.groups_by_tag(tag) {
+            Ok(list) => {
+                HttpResponse::Ok().json(ResponseMessage::success(GroupList { group_list: list }))
+            }
+            Err(e) => e.error_response(),
+        }
+    } else {
+        WebError::BadRequest("no tag found".into()).error_response()
+    }
+}
+
+#[post("/rekey_client")]
+async fn rekey_client(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    client: web::Json<ClientKey>,
+) -> HttpResponse {
+    let (group, virtual_ip) = (client.group.clone(), client.virtual_ip);
+    match service.rekey_client(client.0) {
+        Ok(addr) => {
+            log::info!(
+                "重置客户端密钥 group={},virtual_ip={},addr={}",
+                group,
+                virtual_ip,
+                addr
+            );
+            HttpResponse::Ok().json(ResponseMessage::success(true))
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/move_client")]
+async fn move_client(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    client: web::Json<MoveClient>,
+) -> HttpResponse {
+    let (group, virtual_ip, to_group) = (
+        client.group.clone(),
+        client.virtual_ip,
+        client.to_group.clone(),
+    );
+    match service.move_client(client.0).await {
+        Ok((new_ip, addr)) => {
+            log::info!(
+                "移动客户端 group={},virtual_ip={},to_group={},new_ip={},addr={}",
+                group,
+                virtual_ip,
+                to_group,
+                new_ip,
+                addr
+            );
+            HttpResponse::Ok().json(ResponseMessage::success(new_ip))
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/client_p2p")]
+async fn client_p2p(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    client: web::Json<ClientKey>,
+) -> HttpResponse {
+    match service.client_p2p(client.0) {
+        Ok(p2p) => HttpResponse::Ok().json(ResponseMessage::success(p2p)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/find_device")]
+async fn find_device(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    query: web::Json<DeviceQuery>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(
+        service.find_device(&query.device_id),
+    ))
+}
+
+#[post("/group_routes")]
+async fn group_routes(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.group_routes()))
+}
+
+#[post("/add_group_route")]
+async fn add_group_route(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    route: web::Json<GroupRouteItem>,
+) -> HttpResponse {
+    match service.update_group_route(route.0, true) {
+        Ok(changed) => HttpResponse::Ok().json(ResponseMessage::success(changed)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/remove_group_route")]
+async fn remove_group_route(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    route: web::Json<GroupRouteItem>,
+) -> HttpResponse {
+    match service.update_group_route(route.0, false) {
+        Ok(changed) => HttpResponse::Ok().json(ResponseMessage::success(changed)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/all_clients")]
+async fn all_clients(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    query: web::Json<AllClientsQuery>,
+) -> HttpResponse {
+    let page = service.all_clients(query.0);
+    HttpResponse::Ok().json(ResponseMessage::success(page))
+}
+
+#[post("/preview_registration")]
+async fn preview_registration(
+    req: HttpRequest,
+    service: Data<VntsWebService>,
+    query: web::Json<PreviewQuery>,
+) -> HttpResponse {
+    match service.preview_registration(query.0, req.peer_addr()) {
+        Ok(preview) => HttpResponse::Ok().json(ResponseMessage::success(preview)),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// 请求头中的token，经过了鉴权中间件的接口一定存在
+fn auth_token(req: &HttpRequest) -> String {
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[post("/user_sessions")]
+async fn user_sessions(
+    req: HttpRequest,
+    service: Data<VntsWebService>,
+    user: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    let auth = auth_token(&req);
+    if let Some(username) = user.get("username") {
+        let list = service.user_sessions(username, &auth);
+        HttpResponse::Ok().json(ResponseMessage::success(list))
+    } else {
+        WebError::BadRequest("no username found".into()).error_response()
+    }
+}
+
+#[post("/revoke_user_sessions")]
+async fn revoke_user_sessions(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    user: web::Json<HashMap<String, String>>,
+) -> HttpResponse {
+    if let Some(username) = user.get("username") {
+        let count = service.revoke_user_sessions(username);
+        log::info!("注销用户的登录会话 username={},count={}", username, count);
+        HttpResponse::Ok().json(ResponseMessage::success(count))
+    } else {
+        WebError::BadRequest("no username found".into()).error_response()
+    }
+}
+
+#[post("/change_password")]
+async fn change_password(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    data: web::Json<ChangePassword>,
+) -> HttpResponse {
+    match service.change_password(data.0) {
+        Ok(count) => {
+            log::info!("修改密码，注销登录会话 count={}", count);
+            HttpResponse::Ok().json(ResponseMessage::success(count))
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/set_tokens")]
+async fn set_tokens(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    data: web::Json<SetTokens>,
+) -> HttpResponse {
+    match service.set_tokens(data.0) {
+        Ok(count) => {
+            log::info!("替换token白名单 count={}", count);
+            HttpResponse::Ok().json(ResponseMessage::success(count))
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+#[post("/relay_stats")]
+async fn relay_stats(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.relay_stats()))
+}
+
+#[post("/token_usage")]
+async fn token_usage(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.token_usage()))
+}
+
+#[post("/ip_bans")]
+async fn ip_bans(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseMessage::success(service.ip_bans()))
+}
+
+#[post("/log_tail")]
+async fn tail_log(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    query: web::Json<LogTailQuery>,
+) -> HttpResponse {
+    match web::block(move || service.log_tail(query.0)).await {
+        Ok(Ok(lines)) => HttpResponse::Ok().json(ResponseMessage::success(lines)),
+        Ok(Err(e)) => e.error_response(),
+        Err(e) => WebError::Internal(e.to_string()).error_response(),
+    }
+}
+
+#[get("/logs")]
+async fn logs(
+    _req: HttpRequest,
+    service: Data<VntsWebService>,
+    query: web::Query<LogsQuery>,
+) -> HttpResponse {
+    match web::block(move || service.logs(query.kb)).await {
+        Ok(Ok(text)) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(text),
+        Ok(Err(e)) => e.error_response(),
+        Err(e) => WebError::Internal(e.to_string()).error_response(),
+    }
+}
+
+#[get("/dash")]
+async fn dash_page(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    match service.group_summary() {
+        Ok(list) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(dash::render(&list)),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[get("/metrics")]
+async fn metrics_page(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(service.metrics())
+}
+
+#[get("/metrics.json")]
+async fn metrics_json(_req: HttpRequest, service: Data<VntsWebService>) -> HttpResponse {
+    HttpResponse::Ok().json(service.metrics_snapshot())
+}
+
 #[derive(Clone)]
 struct AuthApi {
     api_set: Arc<HashSet<String>>,
@@ -55,52 +467,237 @@ fn auth_api_set() -> AuthApi {
     let mut api_set = HashSet::new();
     api_set.insert("/group_info".to_string());
     api_set.insert("/group_list".to_string());
+    api_set.insert("/pause_group".to_string());
+    api_set.insert("/resume_group".to_string());
+    api_set.insert("/dash".to_string());
+    api_set.insert("/all_clients".to_string());
+    api_set.insert("/preview_registration".to_string());
+    api_set.insert("/set_group_tags".to_string());
+    api_set.insert("/export_group".to_string());
+    api_set.insert("/import_group".to_string());
+    api_set.insert("/groups_by_tag".to_string());
+    api_set.insert("/user_sessions".to_string());
+    api_set.insert("/revoke_user_sessions".to_string());
+    api_set.insert("/server_info".to_string());
+    api_set.insert("/rekey_client".to_string());
+    api_set.insert("/client_p2p".to_string());
+    api_set.insert("/find_device".to_string());
+    api_set.insert("/group_routes".to_string());
+    api_set.insert("/add_group_route".to_string());
+    api_set.insert("/remove_group_route".to_string());
+    api_set.insert("/change_password".to_string());
+    api_set.insert("/set_tokens".to_string());
+    api_set.insert("/token_usage".to_string());
+    api_set.insert("/ip_bans".to_string());
+    api_set.insert("/move_client".to_string());
+    api_set.insert("/relay_stats".to_string());
+    api_set.insert("/log_tail".to_string());
+    api_set.insert("/logs".to_string());
+    api_set.insert("/group_utilization".to_string());
+    api_set.insert("/metrics".to_string());
+    api_set.insert("/metrics.json".to_string());
+    api_set.insert("/config".to_string());
+    api_set.insert("/status".to_string());
     AuthApi {
         api_set: Arc::new(api_set),
     }
 }
 
+/// 内置页面的入口文件
+const INDEX_FILE: &str = "index.html";
+
+/// 检查编译时内置的页面，返回文件数，构建时没有生成页面或缺少入口文件时返回错误
+pub fn check_assets() -> Result<usize, String> {
+    check_resources(&generate())
+}
+
+fn check_resources(resources: &HashMap<&str, static_files::Resource>) -> Result<usize, String> {
+    if resources.is_empty() {
+        return Err("没有内置的页面文件".into());
+    }
+    match resources.get(INDEX_FILE) {
+        Some(index) if !index.data.is_empty() => Ok(resources.len()),
+        Some(_) => Err(format!("内置页面的{}为空", INDEX_FILE)),
+        None => Err(format!("内置页面缺少{}", INDEX_FILE)),
+    }
+}
+
+/// 从磁盘目录提供页面，路径中包含..的请求会被拒绝，不会读取目录以外的文件
+fn static_files(dir: &Path) -> Files {
+    Files::new("/", dir).index_file("index.html")
+}
+
+/// 访问日志，最外层记录包括认证失败在内的所有请求，只记录路径，不记录查询参数和请求体
+fn access_logger() -> middleware::Logger {
+    middleware::Logger::new("method=%{method}xi path=%U status=%s ip=%a latency_ms=%D")
+        .custom_request_replace("method", |req| req.method().to_string())
+        .log_target("web_access")
+}
+
+/// 每个监听单独一个HttpServer，共享同一个VntsWebService，按监听的权限过滤可以访问的接口
 pub async fn start(
-    lst: net::TcpListener,
+    listeners: Vec<(net::TcpListener, WebCapability)>,
     cache: AppCache,
     config: ConfigInfo,
 ) -> std::io::Result<()> {
+    let api_only = config.web_api_only;
+    let access_log = config.web_access_log;
+    let static_dir = config.web_static_dir.clone();
+    if !api_only && static_dir.is_none() {
+        // 构建出错时页面会是空白，启动时提示，不影响接口
+        if let Err(e) = check_assets() {
+            println!("web页面异常: {}", e);
+            log::warn!("web页面异常 {}", e);
+        }
+    }
     let web_service = VntsWebService::new(cache, config);
     let auth_api = auth_api_set();
-    HttpServer::new(move || {
-        let generated = generate();
-        App::new()
-            .app_data(Data::new(web_service.clone()))
-            .app_data(Data::new(auth_api.clone()))
-            .wrap_fn(|request, srv| {
-                let auth_api: &Data<AuthApi> = request.app_data().unwrap();
-                let path = request.path();
-                if path == "/login" || !auth_api.api_set.contains(path) {
-                    return srv.call(request);
-                }
-                let service: &Data<VntsWebService> = request.app_data().unwrap();
-                if let Some(authorization) = request.headers().get("Authorization") {
-                    if let Ok(auth) = authorization.to_str() {
-                        if auth.starts_with("Bearer ") {
-                            let auth = &auth["Bearer ".len()..];
-                            if service.check_auth(&auth.to_string()) {
-                                return srv.call(request);
+    let mut servers = Vec::with_capacity(listeners.len());
+    for (lst, capability) in listeners {
+        let web_service = web_service.clone();
+        let auth_api = auth_api.clone();
+        let static_dir = static_dir.clone();
+        let server = HttpServer::new(move || {
+            let app = App::new()
+                .app_data(Data::new(web_service.clone()))
+                .app_data(Data::new(auth_api.clone()))
+                .app_data(Data::new(capability))
+                .wrap_fn(|request, srv| {
+                    let auth_api: &Data<AuthApi> = request.app_data().unwrap();
+                    let capability: &Data<WebCapability> = request.app_data().unwrap();
+                    let path = request.path();
+                    let service: &Data<VntsWebService> = request.app_data().unwrap();
+                    // 封禁的ip所有请求都拒绝，包括登录和页面
+                    let banned = request
+                        .peer_addr()
+                        .is_some_and(|addr| service.is_banned(addr.ip()));
+                    let error = if banned {
+                        WebError::Banned
+                    } else if !capability::allowed(*capability.get_ref(), path, &auth_api.api_set) {
+                        // 监听权限以外的接口当作不存在
+                        WebError::NotFound("not found".into())
+                    } else if path == "/login" || !auth_api.api_set.contains(path) {
+                        return srv.call(request);
+                    } else {
+                        if let Some(authorization) = request.headers().get("Authorization") {
+                            if let Ok(auth) = authorization.to_str() {
+                                if auth.starts_with("Bearer ") {
+                                    let auth = &auth["Bearer ".len()..];
+                                    if service.check_auth(&auth.to_string()) {
+                                        return srv.call(request);
+                                    }
+                                }
+                            }
+                        }
+                        if path == "/dash" {
+                            // 页面直接访问时带不了请求头，使用登录后保存的cookie
+                            if let Some(auth) = request.cookie("auth") {
+                                if service.check_auth(&auth.value().to_string()) {
+                                    return srv.call(request);
+                                }
                             }
                         }
-                    }
-                }
-                Box::pin(async move {
-                    Ok(request
-                        .into_response(HttpResponse::Ok().json(ResponseMessage::unauthorized())))
+                        WebError::Unauthorized
+                    };
+                    Box::pin(async move { Ok(request.into_response(error.error_response())) })
                 })
-            })
-            .wrap(middleware::Compress::default())
-            .service(login)
-            .service(group_list)
-            .service(group_info)
-            .service(ResourceFiles::new("/", generated))
-    })
-    .listen(lst)?
-    .run()
-    .await
+                .wrap(middleware::Compress::default())
+                .wrap(middleware::Condition::new(access_log, access_logger()))
+                .service(login)
+                .service(group_list)
+                .service(server_info)
+                .service(config_info)
+                .service(status)
+                .service(group_info)
+                .service(pause_group)
+                .service(resume_group)
+                .service(metrics_page)
+                .service(metrics_json)
+                .service(group_utilization)
+                .service(relay_stats)
+                .service(all_clients)
+                .service(rekey_client)
+                .service(client_p2p)
+                .service(find_device)
+                .service(group_routes)
+                .service(add_group_route)
+                .service(remove_group_route)
+                .service(preview_registration)
+                .service(set_group_tags)
+                .service(export_group)
+                .service(import_group)
+                .service(groups_by_tag)
+                .service(user_sessions)
+                .service(revoke_user_sessions)
+                .service(change_password)
+                .service(set_tokens)
+                .service(token_usage)
+                .service(ip_bans)
+                .service(move_client)
+                .service(tail_log)
+                .service(logs);
+            if api_only {
+                // 只开放接口，页面路径返回404
+                app
+            } else if let Some(dir) = &static_dir {
+                app.service(dash_page).service(static_files(dir))
+            } else {
+                app.service(dash_page)
+                    .service(ResourceFiles::new("/", generate()))
+            }
+        })
+        .listen(lst)?
+        .run();
+        servers.push(server);
+    }
+    futures_util::future::try_join_all(servers).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn embedded_assets() {
+        assert!(check_assets().unwrap() > 1);
+        let mut resources = HashMap::new();
+        assert!(check_resources(&resources).is_err());
+        let resource = |data: &'static [u8]| static_files::Resource {
+            data,
+            modified: 0,
+            mime_type: "text/html",
+        };
+        resources.insert("login.html", resource(b"login"));
+        assert_eq!(
+            check_resources(&resources).unwrap_err(),
+            "内置页面缺少index.html"
+        );
+        resources.insert(INDEX_FILE, resource(b""));
+        assert!(check_resources(&resources).is_err());
+        resources.insert(INDEX_FILE, resource(b"<html></html>"));
+        assert_eq!(check_resources(&resources), Ok(2));
+    }
+
+    #[actix_web::test]
+    async fn serve_static_dir() {
+        let root = std::env::temp_dir().join(format!("vnts_static_{}", std::process::id()));
+        let dir = root.join("static");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "patched").unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+        let app = test::init_service(App::new().service(static_files(&dir))).await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "patched");
+        for uri in ["/../secret.txt", "/%2e%2e/secret.txt", "/missing.js"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert!(resp.status().is_client_error(), "{} {}", uri, resp.status());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
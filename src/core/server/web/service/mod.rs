@@ -1,54 +1,484 @@
+use chrono::{Local, TimeZone};
 use crossbeam_utils::atomic::AtomicCell;
-use std::net::{SocketAddr, SocketAddrV4};
+use parking_lot::RwLock;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crate::core::entity;
+use crate::core::server::web::error::WebError;
+use crate::core::server::web::geoip::GeoIp;
+use crate::core::server::web::log_tail;
+use crate::core::server::web::metrics;
+use crate::core::server::web::metrics::MetricsSnapshot;
 use crate::core::server::web::vo::{
-    ClientInfo, ClientStatusInfo, GroupList, LoginData, NetworkInfo,
+    AllClientsQuery, ChangePassword, ClientInfo, ClientItem, ClientKey, ClientP2p, ClientPage,
+    ClientStatusInfo, ConfigView, DeviceItem, DeviceLocation, GroupBundle, GroupList,
+    GroupRouteItem, GroupSummary, GroupTags, GroupUtilization, IpBanItem, LogTailQuery, LoginData,
+    MoveClient, NatStats, NetworkInfo, PauseScope, PreviewQuery, RegistrationPreview, RelayClient,
+    RelayStats, ServerInfo, ServerStatus, SetTokens, TokenUsage, UserSession,
 };
-use crate::core::store::cache::AppCache;
+use crate::core::service::server::{allocate_ip, choose_ip, grant_lease};
+use crate::core::store::cache::{AppCache, MoveError};
+use crate::util::{canonical_ip, usable_hosts, GroupRoute};
 use crate::ConfigInfo;
 
+/// 全局客户端列表的最大分页大小
+const MAX_PAGE_SIZE: usize = 200;
+/// 全局客户端列表最多扫描的客户端数
+const MAX_SCAN_CLIENTS: usize = 100_000;
+/// 每个组网最多的标签数
+const MAX_GROUP_TAGS: usize = 16;
+/// 标签最大长度
+const MAX_TAG_LEN: usize = 64;
+/// /log_tail默认返回的行数
+const DEFAULT_LOG_TAIL_LINES: usize = 100;
+/// /logs默认返回的大小(KB)
+const DEFAULT_LOGS_KB: u64 = 64;
+
 #[derive(Clone)]
 pub struct VntsWebService {
     cache: AppCache,
     config: ConfigInfo,
     login_time: Arc<AtomicCell<(Instant, usize)>>,
+    // 运行时可以修改的密码，初始值来自启动参数
+    password: Arc<RwLock<String>>,
+    geoip: Arc<GeoIp>,
 }
 
 impl VntsWebService {
     pub fn new(cache: AppCache, config: ConfigInfo) -> Self {
+        let password = Arc::new(RwLock::new(config.password.clone()));
+        #[cfg(feature = "geoip")]
+        let geoip = GeoIp::new(config.geoip_db.as_deref());
+        #[cfg(not(feature = "geoip"))]
+        let geoip = GeoIp::new();
         Self {
             cache,
             config,
             login_time: Arc::new(AtomicCell::new((Instant::now(), 0))),
+            password,
+            geoip: Arc::new(geoip),
         }
     }
 }
 
+/// 用户名或密码为空时不允许登录，返回和账号密码错误不同的错误，方便发现配置问题
+fn check_login_enabled(username: &str, password: &str) -> Result<(), WebError> {
+    if username.is_empty() || password.is_empty() {
+        return Err(WebError::Disabled("web management disabled".into()));
+    }
+    Ok(())
+}
+
+/// 组网的客户名称和标签
+fn customer_label(info: &entity::NetworkInfo) -> (String, String) {
+    match &info.meta {
+        Some(meta) => (meta.customer.clone(), meta.label.clone()),
+        None => Default::default(),
+    }
+}
+
+fn utilization(group: String, info: &entity::NetworkInfo) -> GroupUtilization {
+    let usable = usable_hosts(info.gateway_ip, info.mask_ip);
+    let assigned = info.clients.len() as u32;
+    let used_pct = if usable == 0 {
+        100.0
+    } else {
+        assigned as f64 * 100.0 / usable as f64
+    };
+    GroupUtilization {
+        group,
+        customer: customer_label(info).0,
+        usable,
+        assigned,
+        free: usable.saturating_sub(assigned),
+        used_pct,
+        forward_bytes: info.forward_bytes.load(Ordering::Relaxed),
+    }
+}
+
+/// 根据客户端上报的p2p列表统计组网内的中继情况，只统计在线客户端
+fn relay_stats(group: String, info: &entity::NetworkInfo) -> RelayStats {
+    let online: Vec<&entity::ClientInfo> = info.clients.values().filter(|v| v.online).collect();
+    let mut stats = RelayStats {
+        group,
+        p2p: 0,
+        relay: 0,
+        unknown: 0,
+        clients: Vec::with_capacity(online.len()),
+    };
+    for client in &online {
+        let relay_peers: Vec<Ipv4Addr> = match &client.client_status {
+            Some(status) => online
+                .iter()
+                .filter(|peer| peer.virtual_ip != client.virtual_ip)
+                .map(|peer| Ipv4Addr::from(peer.virtual_ip))
+                .filter(|ip| !status.p2p_list.contains(ip))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mode = if client.client_status.is_none() {
+            stats.unknown += 1;
+            "unknown"
+        } else if relay_peers.is_empty() {
+            stats.p2p += 1;
+            "p2p"
+        } else {
+            stats.relay += 1;
+            "relay"
+        };
+        stats.clients.push(RelayClient {
+            virtual_ip: Ipv4Addr::from(client.virtual_ip),
+            name: client.name.clone(),
+            mode: mode.to_string(),
+            relay_peers,
+            relayed_bytes: client.relayed_bytes.load(Ordering::Relaxed),
+        });
+    }
+    stats.clients.sort_by_key(|v| v.virtual_ip);
+    stats
+}
+
+fn count_nat(stats: &mut NatStats, info: &entity::ClientInfo) {
+    if !info.online {
+        return;
+    }
+    match &info.client_status {
+        Some(status) if status.is_cone => stats.cone += 1,
+        Some(_) => stats.symmetric += 1,
+        None => stats.unknown += 1,
+    }
+}
+
+fn status_info(into: &entity::ClientInfo) -> Option<ClientStatusInfo> {
+    let client_status = into.client_status.as_ref()?;
+    let link_quality = into.link_quality.lock();
+    Some(ClientStatusInfo {
+        p2p_list: client_status.p2p_list.clone(),
+        up_stream: client_status.up_stream,
+        down_stream: client_status.down_stream,
+        is_cone: client_status.is_cone,
+        update_time: format!("{}", client_status.update_time.format("%Y-%m-%d %H:%M:%S")),
+        rtt_ms: link_quality.rtt_ms(),
+        loss_pct: link_quality.loss_pct(),
+        jitter_ms: link_quality.jitter_ms(),
+        compress: into.compress,
+        compress_ratio: into.compress_stats.ratio(),
+    })
+}
+
+/// 组网内设备ID为device_id的客户端
+fn find_device(group: &str, info: &entity::NetworkInfo, device_id: &str) -> Vec<DeviceLocation> {
+    let mut list: Vec<DeviceLocation> = info
+        .clients
+        .values()
+        .filter(|v| v.device_id == device_id)
+        .map(|v| DeviceLocation {
+            group: group.to_string(),
+            virtual_ip: v.virtual_ip.into(),
+            online: v.online,
+            status_info: status_info(v),
+        })
+        .collect();
+    list.sort_by_key(|v| v.virtual_ip);
+    list
+}
+
+/// ipv4映射的地址还原成ipv4，便于展示
+fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(canonical_ip(addr.ip()), addr.port())
+}
+
 impl VntsWebService {
-    pub async fn login(&self, login_data: LoginData) -> Result<String, String> {
+    pub async fn login(
+        &self,
+        login_data: LoginData,
+        ip: Option<IpAddr>,
+    ) -> Result<String, WebError> {
+        check_login_enabled(&self.config.username, &self.password.read())?;
         let (time, count) = self.login_time.load();
         if count >= 3 && time.elapsed() < Duration::from_secs(60) {
-            return Err("一分钟后再试".into());
+            return Err(WebError::RateLimited("一分钟后再试".into()));
         }
         if login_data.username == self.config.username
-            && login_data.password == self.config.password
+            && login_data.password == *self.password.read()
         {
             self.login_time.store((time, 0));
             let auth = uuid::Uuid::new_v4().to_string().replace("-", "");
-            self.cache
-                .auth_map
-                .insert(auth.clone(), (), Duration::from_secs(3600 * 24))
-                .await;
+            let now = Local::now();
+            let session = entity::AuthSession {
+                username: login_data.username,
+                create_time: now,
+                last_active: AtomicI64::new(now.timestamp()),
+            };
+            self.cache.insert_auth_session(auth.clone(), session).await;
             Ok(auth)
         } else {
             self.login_time.store((Instant::now(), count + 1));
-            Err("账号或密码错误".into())
+            if let Some(ip) = ip {
+                self.cache.ip_ban.fail(ip).await;
+            }
+            Err(WebError::BadRequest("账号或密码错误".into()))
         }
     }
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.cache.ip_ban.is_banned(ip)
+    }
+    pub fn ip_bans(&self) -> Vec<IpBanItem> {
+        self.cache
+            .ip_ban
+            .list()
+            .into_iter()
+            .map(|(ip, unban_time)| IpBanItem {
+                ip,
+                unban_time: Local
+                    .timestamp_opt(unban_time, 0)
+                    .single()
+                    .map(|v| v.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
     pub fn check_auth(&self, auth: &String) -> bool {
-        self.cache.auth_map.get(auth).is_some()
+        if let Some(session) = self.cache.auth_map.get(auth) {
+            session
+                .last_active
+                .store(Local::now().timestamp(), Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+    /// 修改密码，并注销该用户的所有登录会话
+    pub fn change_password(&self, data: ChangePassword) -> Result<usize, WebError> {
+        if data.new_password.is_empty() || data.new_password.len() > 128 {
+            return Err(WebError::BadRequest("新密码长度错误".into()));
+        }
+        {
+            // 校验和修改在同一个写锁内完成，并发登录看到的要么是旧密码要么是新密码
+            let mut password = self.password.write();
+            if data.old_password != *password {
+                return Err(WebError::BadRequest("原密码错误".into()));
+            }
+            *password = data.new_password;
+        }
+        Ok(self.revoke_user_sessions(&self.config.username))
+    }
+    /// 整体替换token白名单，已经在线的客户端不受影响，返回替换后的数量
+    pub fn set_tokens(&self, data: SetTokens) -> Result<usize, WebError> {
+        let white_token = match &self.config.white_token {
+            Some(white_token) => white_token,
+            None => return Err(WebError::BadRequest("未开启token白名单".into())),
+        };
+        let tokens: Vec<String> = data
+            .tokens
+            .iter()
+            .map(|token| token.trim().to_string())
+            .collect();
+        if tokens.is_empty() {
+            return Err(WebError::BadRequest("token白名单不能为空".into()));
+        }
+        if tokens
+            .iter()
+            .any(|token| token.is_empty() || token.len() > 128)
+        {
+            return Err(WebError::BadRequest("token长度错误".into()));
+        }
+        Ok(white_token.replace(tokens))
+    }
+    /// 只读取启动时确定的日志文件，不接受外部传入的路径
+    fn read_log<T>(&self, read: impl FnOnce(&Path) -> io::Result<T>) -> Result<T, WebError> {
+        let log_file = match &self.config.log_file {
+            Some(log_file) => log_file,
+            None => return Err(WebError::BadRequest("未开启日志文件".into())),
+        };
+        read(log_file).map_err(|e| {
+            log::warn!("读取日志失败 {:?}: {:?}", log_file, e);
+            WebError::Internal("读取日志失败".into())
+        })
+    }
+    /// 日志文件的最后几行
+    pub fn log_tail(&self, query: LogTailQuery) -> Result<Vec<String>, WebError> {
+        let lines = query
+            .lines
+            .unwrap_or(DEFAULT_LOG_TAIL_LINES)
+            .min(self.config.log_tail_max_lines);
+        self.read_log(|path| log_tail::tail(path, lines))
+    }
+    /// 日志文件最后kb千字节的内容
+    pub fn logs(&self, kb: Option<u64>) -> Result<Vec<u8>, WebError> {
+        let kb = kb.unwrap_or(DEFAULT_LOGS_KB).min(self.config.logs_max_kb);
+        self.read_log(|path| log_tail::tail_bytes(path, kb * 1024))
+    }
+    /// 用户的所有登录会话
+    pub fn user_sessions(&self, username: &str, current: &str) -> Vec<UserSession> {
+        let mut list: Vec<(i64, UserSession)> = self
+            .cache
+            .auth_map
+            .key_values()
+            .into_iter()
+            .filter(|(_, session)| session.username == username)
+            .map(|(token, session)| {
+                let last_active = session.last_active.load(Ordering::Relaxed);
+                let item = UserSession {
+                    token: format!("{}...", &token[..8.min(token.len())]),
+                    create_time: session.create_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    last_active: Local
+                        .timestamp_opt(last_active, 0)
+                        .single()
+                        .map(|v| v.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default(),
+                    current: token == current,
+                };
+                (last_active, item)
+            })
+            .collect();
+        list.sort_by_key(|(last_active, _)| std::cmp::Reverse(*last_active));
+        list.into_iter().map(|(_, item)| item).collect()
+    }
+    /// 注销用户的所有登录会话，返回注销的数量
+    pub fn revoke_user_sessions(&self, username: &str) -> usize {
+        self.cache
+            .auth_map
+            .key_values()
+            .into_iter()
+            .filter(|(_, session)| session.username == username)
+            .filter(|(token, _)| self.cache.auth_map.remove(token).is_some())
+            .count()
+    }
+    pub fn server_info(&self) -> ServerInfo {
+        ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            port: self.config.port,
+            announce_addr: self.config.announce_addr,
+            gateway: self.config.gateway,
+            netmask: self.config.netmask,
+            check_finger: self.config.check_finger,
+        }
+    }
+    /// 组网的地址使用情况
+    pub fn group_utilization(&self, group: String) -> Result<GroupUtilization, WebError> {
+        let info = match self.cache.virtual_network.get_val(&group) {
+            Some(info) => info,
+            None => return Err(WebError::NotFound("no group found".into())),
+        };
+        let guard = match info.try_read_for(self.config.web_lock_timeout) {
+            Some(guard) => guard,
+            None => {
+                log::warn!("group_utilization获取读锁超时 group={}", group);
+                return Err(WebError::Busy);
+            }
+        };
+        Ok(utilization(group, &guard))
+    }
+    /// 采集运行指标，获取读锁超时的组网不输出
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let mut groups: Vec<GroupUtilization> = self
+            .cache
+            .virtual_network
+            .key_values()
+            .into_iter()
+            .filter_map(|(group, info)| {
+                let guard = info.try_read_for(self.config.web_lock_timeout)?;
+                Some(utilization(group, &guard))
+            })
+            .collect();
+        groups.sort_by(|v1, v2| v1.group.cmp(&v2.group));
+        MetricsSnapshot {
+            metrics: self.cache.metrics(),
+            max_clients: self.config.max_total_clients,
+            groups,
+        }
+    }
+    /// Prometheus文本格式的运行指标
+    pub fn metrics(&self) -> String {
+        metrics::render(&self.metrics_snapshot())
+    }
+    pub fn status(&self) -> ServerStatus {
+        let mut nat = NatStats::default();
+        for (group, info) in self.cache.virtual_network.key_values() {
+            let guard = match info.try_read_for(self.config.web_lock_timeout) {
+                Some(guard) => guard,
+                None => {
+                    log::warn!("status获取读锁超时 group={}", group);
+                    continue;
+                }
+            };
+            for client in guard.clients.values() {
+                count_nat(&mut nat, client);
+            }
+        }
+        ServerStatus {
+            clients: self.cache.client_count.load(Ordering::Relaxed),
+            max_clients: self.config.max_total_clients,
+            nat,
+        }
+    }
+    pub fn config(&self) -> ConfigView {
+        ConfigView {
+            hash: self.config.config_hash.clone(),
+            token_count: self.config.white_token.as_ref().map(|set| set.len()),
+            token_meta: self.config.token_meta.sorted(),
+        }
+    }
+    /// 各组网的p2p和中继情况，获取读锁超时的组网不输出
+    pub fn relay_stats(&self) -> Vec<RelayStats> {
+        let mut list: Vec<RelayStats> = self
+            .cache
+            .virtual_network
+            .key_values()
+            .into_iter()
+            .filter_map(|(group, info)| {
+                let guard = match info.try_read_for(self.config.web_lock_timeout) {
+                    Some(guard) => guard,
+                    None => {
+                        log::warn!("relay_stats获取读锁超时 group={}", group);
+                        return None;
+                    }
+                };
+                Some(relay_stats(group, &guard))
+            })
+            .collect();
+        list.sort_by(|v1, v2| v1.group.cmp(&v2.group));
+        list
+    }
+    /// 在所有组网中查找设备，获取读锁超时的组网跳过，每个组网只在复制结果时持有读锁
+    pub fn find_device(&self, device_id: &str) -> Vec<DeviceLocation> {
+        let mut list = Vec::new();
+        for (group, info) in self.cache.virtual_network.key_values() {
+            let guard = match info.try_read_for(self.config.web_lock_timeout) {
+                Some(guard) => guard,
+                None => {
+                    log::warn!("find_device获取读锁超时 group={}", group);
+                    continue;
+                }
+            };
+            list.extend(find_device(&group, &guard, device_id));
+        }
+        list.sort_by(|v1, v2| v1.group.cmp(&v2.group));
+        list
+    }
+    /// 各token的在线客户端数，只包含有在线客户端的token
+    pub fn token_usage(&self) -> Vec<TokenUsage> {
+        self.cache
+            .token_online
+            .list()
+            .into_iter()
+            .map(|(group, online)| {
+                let meta = self.config.token_meta.get(&group).unwrap_or_default();
+                TokenUsage {
+                    group,
+                    customer: meta.customer.clone(),
+                    label: meta.label.clone(),
+                    online,
+                    max_online: meta.max_online,
+                }
+            })
+            .collect()
     }
     pub fn group_list(&self) -> GroupList {
         let group_list: Vec<String> = self
@@ -60,40 +490,189 @@ impl VntsWebService {
             .collect();
         GroupList { group_list }
     }
-    pub fn group_info(&self, group: String) -> Option<NetworkInfo> {
-        if let Some(info) = self.cache.virtual_network.get(&group) {
+    /// 各组网的在线数概览，有组网获取读锁超时时返回busy
+    pub fn group_summary(&self) -> Result<Vec<GroupSummary>, WebError> {
+        let mut list = Vec::new();
+        for (group, info) in self.cache.virtual_network.key_values() {
+            let guard = match info.try_read_for(self.config.web_lock_timeout) {
+                Some(guard) => guard,
+                None => {
+                    log::warn!("group_summary获取读锁超时 group={}", group);
+                    return Err(WebError::Busy);
+                }
+            };
+            list.push(GroupSummary {
+                group,
+                customer: customer_label(&guard).0,
+                online: guard.clients.values().filter(|v| v.online).count(),
+                total: guard.clients.len(),
+                paused: guard.paused,
+            });
+        }
+        list.sort_by(|v1, v2| v1.group.cmp(&v2.group));
+        Ok(list)
+    }
+    /// 所有组网的在线客户端，分页返回
+    pub fn all_clients(&self, query: AllClientsQuery) -> ClientPage {
+        let page = query.page.max(1);
+        let page_size = query.page_size.clamp(1, MAX_PAGE_SIZE);
+        let mut truncated = false;
+        let mut list = Vec::new();
+        for (group, info) in self.cache.virtual_network.key_values() {
+            // 每个组网只短暂持有读锁
             let guard = info.read();
+            for client in guard.clients.values() {
+                if !client.online {
+                    continue;
+                }
+                if list.len() >= MAX_SCAN_CLIENTS {
+                    truncated = true;
+                    break;
+                }
+                let (up_stream, down_stream) = client
+                    .client_status
+                    .as_ref()
+                    .map(|v| (v.up_stream, v.down_stream))
+                    .unwrap_or_default();
+                list.push(ClientItem {
+                    group: group.clone(),
+                    device_id: client.device_id.clone(),
+                    name: client.name.clone(),
+                    virtual_ip: client.virtual_ip.into(),
+                    address: canonical_addr(client.address),
+                    up_stream,
+                    down_stream,
+                });
+            }
+            drop(guard);
+            if truncated {
+                break;
+            }
+        }
+        match query.sort.as_str() {
+            "name" => list.sort_by(|v1, v2| v1.name.cmp(&v2.name)),
+            "virtual_ip" => list.sort_by_key(|v| v.virtual_ip),
+            "address" => list.sort_by_key(|v| v.address),
+            "traffic" => list.sort_by(|v1, v2| {
+                (v2.up_stream + v2.down_stream).cmp(&(v1.up_stream + v1.down_stream))
+            }),
+            _ => list.sort_by(|v1, v2| {
+                v1.group
+                    .cmp(&v2.group)
+                    .then(v1.virtual_ip.cmp(&v2.virtual_ip))
+            }),
+        }
+        let total = list.len();
+        let clients = list
+            .into_iter()
+            .skip((page - 1).saturating_mul(page_size))
+            .take(page_size)
+            .collect();
+        ClientPage {
+            page,
+            page_size,
+            total,
+            truncated,
+            clients,
+        }
+    }
+    /// 预览注册结果，不会真正注册
+    pub fn preview_registration(
+        &self,
+        query: PreviewQuery,
+        addr: Option<SocketAddr>,
+    ) -> Result<RegistrationPreview, WebError> {
+        if let Some(white_token) = &self.config.white_token {
+            if !white_token.contains(&query.group) {
+                return Err(WebError::BadRequest("token不在白名单".into()));
+            }
+        }
+        let gateway: u32 = self.config.gateway.into();
+        let netmask: u32 = self.config.netmask.into();
+        let network: u32 = gateway & netmask;
+        let preview = |info: &entity::NetworkInfo| {
+            let (virtual_ip, _, reclaimed) = allocate_ip(
+                &self.config,
+                info,
+                network + 1..gateway | (!netmask),
+                &query.device_id,
+                query.virtual_ip.map(u32::from).unwrap_or(0),
+                query.allow_ip_change,
+            )?;
+            let mut device_info_list: Vec<DeviceItem> = info
+                .clients
+                .values()
+                .filter(|v| v.virtual_ip != virtual_ip)
+                .map(|v| DeviceItem {
+                    name: v.name.clone(),
+                    virtual_ip: v.virtual_ip.into(),
+                    online: v.online,
+                    client_secret: v.client_secret,
+                })
+                .collect();
+            device_info_list.sort_by_key(|v| v.virtual_ip);
+            Ok(RegistrationPreview {
+                virtual_ip: virtual_ip.into(),
+                virtual_gateway: self.config.gateway,
+                virtual_netmask: self.config.netmask,
+                // 回收地址时组网的epoch会多加一次
+                epoch: info.epoch + 1 + reclaimed.is_some() as u64,
+                reclaimed_ip: reclaimed.map(Ipv4Addr::from),
+                device_info_list,
+                lease_secs: grant_lease(
+                    query.lease_secs,
+                    self.config.min_lease_secs,
+                    self.config.max_lease_secs,
+                ),
+                nat_test_port: self.config.nat_test_port.unwrap_or(0) as u32,
+                compress: query.compress,
+                public_addr: addr.map(canonical_addr),
+            })
+        };
+        // 不续期，避免预览影响组网回收
+        match self.cache.virtual_network.get_val(&query.group) {
+            Some(info) => {
+                let guard = match info.try_read_for(self.config.web_lock_timeout) {
+                    Some(guard) => guard,
+                    None => {
+                        log::warn!("preview_registration获取读锁超时 group={}", query.group);
+                        return Err(WebError::Busy);
+                    }
+                };
+                preview(&guard)
+            }
+            None => preview(&entity::NetworkInfo::new(network, netmask, gateway)),
+        }
+    }
+    /// 读锁在超时时间内拿不到时返回busy，避免请求一直阻塞
+    pub fn group_info(&self, group: String) -> Result<NetworkInfo, WebError> {
+        if let Some(info) = self.cache.virtual_network.get(&group) {
+            let guard = match info.try_read_for(self.config.web_lock_timeout) {
+                Some(guard) => guard,
+                None => {
+                    log::warn!("group_info获取读锁超时 group={}", group);
+                    return Err(WebError::Busy);
+                }
+            };
             let mut network = NetworkInfo::new(
                 guard.network_ip.into(),
                 guard.mask_ip.into(),
                 guard.gateway_ip.into(),
             );
+            network.paused = guard.paused;
+            network.paused_drop = guard.paused_drop.load(Ordering::Relaxed);
+            network.join_paused = guard.join_paused;
+            network.allow_broadcast = guard.allow_broadcast;
+            network.broadcast_drop = guard.broadcast_drop.load(Ordering::Relaxed);
+            network.forward_bytes = guard.forward_bytes.load(Ordering::Relaxed);
+            network.tags = guard.tags.iter().cloned().collect();
+            network.tags.sort();
+            (network.customer, network.label) = customer_label(&guard);
             for into in guard.clients.values() {
-                let address = match into.address {
-                    SocketAddr::V4(_) => into.address,
-                    SocketAddr::V6(ipv6) => {
-                        if let Some(ipv4) = ipv6.ip().to_ipv4_mapped() {
-                            SocketAddr::V4(SocketAddrV4::new(ipv4, ipv6.port()))
-                        } else {
-                            into.address
-                        }
-                    }
-                };
-                let status_info = if let Some(client_status) = &into.client_status {
-                    Some(ClientStatusInfo {
-                        p2p_list: client_status.p2p_list.clone(),
-                        up_stream: client_status.up_stream,
-                        down_stream: client_status.down_stream,
-                        is_cone: client_status.is_cone,
-                        update_time: format!(
-                            "{}",
-                            client_status.update_time.format("%Y-%m-%d %H:%M:%S")
-                        ),
-                    })
-                } else {
-                    None
-                };
-
+                count_nat(&mut network.nat, into);
+                let address = canonical_addr(into.address);
+                let status_info = status_info(into);
+                let location = self.geoip.lookup(address.ip()).unwrap_or_default();
                 let client_info = ClientInfo {
                     device_id: into.device_id.clone(),
                     version: into.version.clone(),
@@ -105,16 +684,299 @@ impl VntsWebService {
                     virtual_ip: into.virtual_ip.into(),
                     status_info,
                     last_join_time: into.last_join_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    broadcast_dropped: into.broadcast_dropped.load(Ordering::Relaxed),
+                    subnet_overlaps: into.subnet_overlaps.iter().map(|v| v.to_string()).collect(),
+                    country: location.country,
+                    city: location.city,
+                    role: into.role.as_str().to_string(),
+                    effective_mtu: into.effective_mtu,
                 };
+                if into.online {
+                    network.min_effective_mtu =
+                        match (network.min_effective_mtu, into.effective_mtu) {
+                            (Some(min), Some(mtu)) => Some(min.min(mtu)),
+                            (min, mtu) => min.or(mtu),
+                        };
+                }
                 network.clients.push(client_info);
             }
             network
                 .clients
                 .sort_by(|v1, v2| v1.virtual_ip.cmp(&v2.virtual_ip));
-            Some(network)
+            Ok(network)
+        } else {
+            Err(WebError::NotFound("no group found".into()))
+        }
+    }
+    /// 暂停/恢复组内转发或新客户端加入，返回组是否存在
+    pub fn pause_group(&self, group: &String, paused: bool, scope: PauseScope) -> bool {
+        if let Some(info) = self.cache.virtual_network.get(group) {
+            let mut guard = info.write();
+            let flag = match scope {
+                PauseScope::Forward => &mut guard.paused,
+                PauseScope::Join => &mut guard.join_paused,
+            };
+            if *flag != paused {
+                *flag = paused;
+                log::info!("group={},scope={:?},paused={}", group, scope, paused);
+            }
+            true
+        } else {
+            false
+        }
+    }
+    /// 丢弃客户端和服务端之间的加密会话，客户端下一次通信时会重新握手
+    pub fn rekey_client(&self, client: ClientKey) -> Result<SocketAddr, WebError> {
+        let info = self
+            .cache
+            .virtual_network
+            .get_val(&client.group)
+            .ok_or_else(|| WebError::NotFound("no group found".into()))?;
+        let guard = match info.try_read_for(self.config.web_lock_timeout) {
+            Some(guard) => guard,
+            None => {
+                log::warn!("rekey_client获取读锁超时 group={}", client.group);
+                return Err(WebError::Busy);
+            }
+        };
+        let addr = guard
+            .clients
+            .get(&client.virtual_ip.into())
+            .filter(|v| v.online)
+            .map(|v| v.address)
+            .ok_or_else(|| WebError::NotFound("client not online".into()))?;
+        drop(guard);
+        if self.cache.cipher_session.remove(&addr).is_none() {
+            return Err(WebError::NotFound("no cipher session".into()));
+        }
+        self.cache.context_cache.invalidate(&addr);
+        Ok(addr)
+    }
+    /// 把在线客户端移到其他组网，尽量保留原来的虚拟ip，返回新的虚拟ip和客户端地址，
+    /// 客户端的token不变，重新注册后会回到原来的组网
+    pub async fn move_client(
+        &self,
+        client: MoveClient,
+    ) -> Result<(Ipv4Addr, SocketAddr), WebError> {
+        if client.group == client.to_group {
+            return Err(WebError::BadRequest("same group".into()));
+        }
+        let gateway: u32 = self.config.gateway.into();
+        let netmask: u32 = self.config.netmask.into();
+        let network: u32 = gateway & netmask;
+        let lease = grant_lease(0, self.config.min_lease_secs, self.config.max_lease_secs);
+        let rs = self
+            .cache
+            .move_client(
+                &client.group,
+                client.virtual_ip.into(),
+                &client.to_group,
+                self.config
+                    .gateway_client
+                    .get(&client.to_group)
+                    .map(|v| v.as_str()),
+                Duration::from_secs(lease as u64),
+                |info, device_id| {
+                    choose_ip(
+                        &self.config,
+                        info,
+                        network + 1..gateway | (!netmask),
+                        device_id,
+                        client.virtual_ip.into(),
+                        true,
+                    )
+                    .ok()
+                    .map(|(virtual_ip, _)| virtual_ip)
+                },
+            )
+            .await;
+        match rs {
+            Ok((virtual_ip, addr)) => Ok((virtual_ip.into(), addr)),
+            Err(MoveError::NotFound(msg)) => Err(WebError::NotFound(msg.into())),
+            Err(MoveError::Conflict(msg)) => Err(WebError::Conflict(msg.into())),
+        }
+    }
+    /// 单个客户端上报的p2p地址，不需要查询整个组网
+    pub fn client_p2p(&self, client: ClientKey) -> Result<ClientP2p, WebError> {
+        let info = self
+            .cache
+            .virtual_network
+            .get_val(&client.group)
+            .ok_or_else(|| WebError::NotFound("no group found".into()))?;
+        let guard = match info.try_read_for(self.config.web_lock_timeout) {
+            Some(guard) => guard,
+            None => {
+                log::warn!("client_p2p获取读锁超时 group={}", client.group);
+                return Err(WebError::Busy);
+            }
+        };
+        let status = guard
+            .clients
+            .get(&client.virtual_ip.into())
+            .ok_or_else(|| WebError::NotFound("no client found".into()))?
+            .client_status
+            .as_ref()
+            .ok_or_else(|| WebError::NotFound("no status info".into()))?;
+        Ok(ClientP2p {
+            p2p_list: status.p2p_list.clone(),
+            is_cone: status.is_cone,
+            update_time: format!("{}", status.update_time.format("%Y-%m-%d %H:%M:%S")),
+        })
+    }
+    /// 组网间转发规则及计数
+    pub fn group_routes(&self) -> Vec<GroupRouteItem> {
+        let mut list: Vec<GroupRouteItem> = self
+            .cache
+            .group_routes
+            .list()
+            .into_iter()
+            .map(|(route, meter)| GroupRouteItem {
+                src_group: route.src_group,
+                src_ip: route.src_ip,
+                dst_group: route.dst_group,
+                dst_ip: route.dst_ip,
+                forwarded: meter.forwarded.load(Ordering::Relaxed),
+                dropped: meter.dropped.load(Ordering::Relaxed),
+            })
+            .collect();
+        list.sort_by(|v1, v2| {
+            (&v1.src_group, v1.src_ip, &v1.dst_group, v1.dst_ip).cmp(&(
+                &v2.src_group,
+                v2.src_ip,
+                &v2.dst_group,
+                v2.dst_ip,
+            ))
+        });
+        list
+    }
+    /// 添加或删除组网间转发规则，返回是否有变化
+    pub fn update_group_route(&self, item: GroupRouteItem, add: bool) -> Result<bool, WebError> {
+        let route = GroupRoute::new(item.src_group, item.src_ip, item.dst_group, item.dst_ip)
+            .map_err(WebError::BadRequest)?;
+        let changed = if add {
+            self.cache.group_routes.add(route.clone())
+        } else {
+            self.cache.group_routes.remove(&route)
+        };
+        log::info!(
+            "组网间转发规则 add={},route={},changed={}",
+            add,
+            route,
+            changed
+        );
+        Ok(changed)
+    }
+    /// 按标签暂停/恢复组内转发或新客户端加入，返回命中的组网数
+    pub fn pause_tag(&self, tag: &str, paused: bool, scope: PauseScope) -> Result<usize, WebError> {
+        let groups = self.groups_by_tag(tag)?;
+        Ok(groups
+            .iter()
+            .filter(|group| self.pause_group(group, paused, scope))
+            .count())
+    }
+    /// 设置组网标签
+    pub fn set_group_tags(&self, group_tags: GroupTags) -> Result<(), WebError> {
+        check_tags(&group_tags.tags)?;
+        if let Some(info) = self.cache.virtual_network.get(&group_tags.group) {
+            info.write().tags = group_tags.tags.into_iter().collect();
+            Ok(())
         } else {
-            None
+            Err(WebError::NotFound("no group found".into()))
+        }
+    }
+    /// 导出组网配置，包含网段、标签、暂停状态和相关的组网间转发规则
+    pub fn export_group(&self, group: String) -> Result<GroupBundle, WebError> {
+        let info = self
+            .cache
+            .virtual_network
+            .get_val(&group)
+            .ok_or_else(|| WebError::NotFound("no group found".into()))?;
+        let mut routes: Vec<GroupRouteItem> = self
+            .group_routes()
+            .into_iter()
+            .filter(|v| v.src_group == group || v.dst_group == group)
+            .collect();
+        for route in routes.iter_mut() {
+            route.forwarded = 0;
+            route.dropped = 0;
+        }
+        let guard = match info.try_read_for(self.config.web_lock_timeout) {
+            Some(guard) => guard,
+            None => {
+                log::warn!("export_group获取读锁超时 group={}", group);
+                return Err(WebError::Busy);
+            }
+        };
+        let mut tags: Vec<String> = guard.tags.iter().cloned().collect();
+        tags.sort();
+        Ok(GroupBundle {
+            group,
+            network: guard.network_ip.into(),
+            netmask: guard.mask_ip.into(),
+            gateway: guard.gateway_ip.into(),
+            tags,
+            paused: guard.paused,
+            join_paused: guard.join_paused,
+            allow_broadcast: guard.allow_broadcast,
+            routes,
+        })
+    }
+    /// 导入组网配置，网段必须和本服务器一致，组网已存在时拒绝
+    pub async fn import_group(&self, bundle: GroupBundle) -> Result<(), WebError> {
+        let routes = check_bundle(&bundle, self.config.gateway, self.config.netmask)?;
+        let mut created = false;
+        let info = self
+            .cache
+            .get_or_insert_network(bundle.group.clone(), || {
+                created = true;
+                let mut info = entity::NetworkInfo::new(
+                    bundle.network.into(),
+                    bundle.netmask.into(),
+                    bundle.gateway.into(),
+                );
+                info.tags = bundle.tags.iter().cloned().collect();
+                info.paused = bundle.paused;
+                info.join_paused = bundle.join_paused;
+                info.allow_broadcast = bundle.allow_broadcast;
+                info.meta = self.config.token_meta.get(&bundle.group);
+                info
+            })
+            .await;
+        drop(info);
+        if !created {
+            return Err(WebError::Conflict("group already exists".into()));
         }
+        let added = routes
+            .into_iter()
+            .filter(|route| self.cache.group_routes.add(route.clone()))
+            .count();
+        log::info!(
+            "导入组网配置 group={},tags={:?},paused={},join_paused={},routes={}",
+            bundle.group,
+            bundle.tags,
+            bundle.paused,
+            bundle.join_paused,
+            added
+        );
+        Ok(())
+    }
+    /// 带有指定标签的组网，有组网获取读锁超时时返回busy
+    pub fn groups_by_tag(&self, tag: &str) -> Result<Vec<String>, WebError> {
+        let mut list = Vec::new();
+        for (group, info) in self.cache.virtual_network.key_values() {
+            let tagged = match info.try_read_for(self.config.web_lock_timeout) {
+                Some(guard) => guard.tags.contains(tag),
+                None => {
+                    log::warn!("groups_by_tag获取读锁超时 group={}", group);
+                    return Err(WebError::Busy);
+                }
+            };
+            if tagged {
+                list.push(group);
+            }
+        }
+        list.sort();
+        Ok(list)
     }
     // pub fn groups_info(&self) -> GroupsInfo {
     //     let mut data = GroupsInfo::new();
@@ -145,3 +1007,164 @@ impl VntsWebService {
     //     data
     // }
 }
+
+fn check_tags(tags: &[String]) -> Result<(), WebError> {
+    if tags.len() > MAX_GROUP_TAGS {
+        return Err(WebError::BadRequest(format!(
+            "最多{}个标签",
+            MAX_GROUP_TAGS
+        )));
+    }
+    if tags
+        .iter()
+        .any(|tag| tag.is_empty() || tag.len() > MAX_TAG_LEN)
+    {
+        return Err(WebError::BadRequest("标签长度错误".into()));
+    }
+    Ok(())
+}
+
+/// 校验导入的组网配置，网段、掩码和网关必须和本服务器一致，转发规则必须和该组网相关
+fn check_bundle(
+    bundle: &GroupBundle,
+    gateway: Ipv4Addr,
+    netmask: Ipv4Addr,
+) -> Result<Vec<GroupRoute>, WebError> {
+    if bundle.group.is_empty() {
+        return Err(WebError::BadRequest("no group found".into()));
+    }
+    let network = Ipv4Addr::from(u32::from(gateway) & u32::from(netmask));
+    if bundle.network != network || bundle.netmask != netmask || bundle.gateway != gateway {
+        return Err(WebError::BadRequest(format!(
+            "网段不匹配 bundle={}/{} gateway={}，本服务器={}/{} gateway={}",
+            bundle.network, bundle.netmask, bundle.gateway, network, netmask, gateway
+        )));
+    }
+    check_tags(&bundle.tags)?;
+    bundle
+        .routes
+        .iter()
+        .map(|item| {
+            if item.src_group != bundle.group && item.dst_group != bundle.group {
+                return Err(WebError::BadRequest(format!(
+                    "转发规则和组网无关 {}->{}",
+                    item.src_group, item.dst_group
+                )));
+            }
+            GroupRoute::new(
+                item.src_group.clone(),
+                item.src_ip,
+                item.dst_group.clone(),
+                item.dst_ip,
+            )
+            .map_err(WebError::BadRequest)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(ip: u32, online: bool, p2p: Option<Vec<u32>>) -> entity::ClientInfo {
+        entity::ClientInfo {
+            virtual_ip: ip,
+            online,
+            client_status: p2p.map(|list| entity::ClientStatusInfo {
+                p2p_list: list.into_iter().map(Ipv4Addr::from).collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn relay_mode() {
+        let mut info = entity::NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        info.clients.insert(2, client(2, true, Some(vec![3, 4])));
+        info.clients.insert(3, client(3, true, Some(vec![2])));
+        info.clients.insert(4, client(4, false, None));
+        info.clients.insert(5, client(5, true, None));
+        info.clients[&3].relayed_bytes.store(100, Ordering::Relaxed);
+        let stats = relay_stats("a".into(), &info);
+        assert_eq!((stats.p2p, stats.relay, stats.unknown), (0, 2, 1));
+        assert_eq!(stats.clients.len(), 3);
+        // 掉线的客户端不算
+        assert_eq!(stats.clients[0].relay_peers, vec![Ipv4Addr::from(5)]);
+        assert_eq!(stats.clients[1].mode, "relay");
+        assert_eq!(stats.clients[1].relay_peers.len(), 1);
+        assert_eq!(stats.clients[1].relayed_bytes, 100);
+        assert_eq!(stats.clients[2].mode, "unknown");
+        info.clients.remove(&5);
+        let stats = relay_stats("a".into(), &info);
+        assert_eq!((stats.p2p, stats.relay, stats.unknown), (2, 0, 0));
+    }
+
+    #[test]
+    fn find_device_in_group() {
+        let mut info = entity::NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        for (ip, device_id, online) in [(3, "d1", false), (2, "d1", true), (4, "d2", true)] {
+            let mut client = client(ip, online, Some(vec![4]));
+            client.device_id = device_id.into();
+            info.clients.insert(ip, client);
+        }
+        let list = find_device("a", &info, "d1");
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].group, "a");
+        assert_eq!(list[0].virtual_ip, Ipv4Addr::from(2));
+        assert!(list[0].online);
+        assert_eq!(
+            list[0].status_info.as_ref().unwrap().p2p_list,
+            vec![Ipv4Addr::from(4)]
+        );
+        assert!(!list[1].online);
+        assert!(find_device("a", &info, "d3").is_empty());
+        assert!(find_device("a", &info, "D1").is_empty());
+    }
+
+    #[test]
+    fn bundle_subnet() {
+        let gateway: Ipv4Addr = "10.26.0.1".parse().unwrap();
+        let netmask: Ipv4Addr = "255.255.255.0".parse().unwrap();
+        let route = |src: &str, dst: &str| GroupRouteItem {
+            src_group: src.into(),
+            src_ip: "10.26.0.2".parse().unwrap(),
+            dst_group: dst.into(),
+            dst_ip: "10.26.0.3".parse().unwrap(),
+            forwarded: 0,
+            dropped: 0,
+        };
+        let mut bundle = GroupBundle {
+            group: "a".into(),
+            network: "10.26.0.0".parse().unwrap(),
+            netmask,
+            gateway,
+            tags: vec!["prod".into()],
+            paused: false,
+            join_paused: true,
+            allow_broadcast: true,
+            routes: vec![route("a", "b"), route("c", "a")],
+        };
+        assert_eq!(check_bundle(&bundle, gateway, netmask).unwrap().len(), 2);
+        bundle.routes.push(route("b", "c"));
+        assert!(check_bundle(&bundle, gateway, netmask).is_err());
+        bundle.routes.pop();
+        bundle.tags.push(String::new());
+        assert!(check_bundle(&bundle, gateway, netmask).is_err());
+        bundle.tags.pop();
+        // 网段和本服务器不一致
+        assert!(check_bundle(&bundle, gateway, "255.255.0.0".parse().unwrap()).is_err());
+        assert!(check_bundle(&bundle, "10.26.0.254".parse().unwrap(), netmask).is_err());
+    }
+
+    #[test]
+    fn login_disabled() {
+        assert!(check_login_enabled("admin", "admin").is_ok());
+        for (username, password) in [("admin", ""), ("", "admin")] {
+            match check_login_enabled(username, password) {
+                Err(WebError::Disabled(msg)) => assert_eq!(msg, "web management disabled"),
+                _ => panic!("login enabled without credentials"),
+            }
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+use actix_web::ResponseError;
 use serde::{Deserialize, Serialize};
 
+use crate::core::server::web::error::WebError;
+use crate::util::TokenMeta;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseMessage<V> {
     data: V,
@@ -20,21 +24,13 @@ impl<V> ResponseMessage<V> {
     }
 }
 
-impl ResponseMessage<Option<()>> {}
-
 impl ResponseMessage<Option<()>> {
-    pub fn fail(message: String) -> ResponseMessage<Option<()>> {
-        Self {
-            data: Option::<()>::None,
-            message: Some(message),
-            code: 400,
-        }
-    }
-    pub fn unauthorized() -> ResponseMessage<Option<()>> {
+    /// code和http状态码一致
+    pub fn error(e: &WebError) -> ResponseMessage<Option<()>> {
         Self {
             data: Option::<()>::None,
-            message: Some("unauthorized".into()),
-            code: 401,
+            message: Some(e.to_string()),
+            code: e.status_code().as_u16() as u32,
         }
     }
 }
@@ -59,6 +55,17 @@ pub struct ClientInfo {
     pub virtual_ip: Ipv4Addr,
     pub status_info: Option<ClientStatusInfo>,
     pub last_join_time: String,
+    // 超过广播限速而丢弃的数据包数
+    pub broadcast_dropped: u64,
+    // 和虚拟网段重叠的本地网段，重叠时该客户端的路由可能出错
+    pub subnet_overlaps: Vec<String>,
+    // 来源地址所在的国家和城市，没有GeoIP数据库时为null
+    pub country: Option<String>,
+    pub city: Option<String>,
+    // normal或gateway(--gateway-client指定的网关客户端)
+    pub role: String,
+    // 客户端上报的PMTUD测得的有效MTU，没有上报时为null
+    pub effective_mtu: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -68,6 +75,16 @@ pub struct ClientStatusInfo {
     pub down_stream: u64,
     pub is_cone: bool,
     pub update_time: String,
+    // 心跳估算的往返时延
+    pub rtt_ms: u32,
+    // 心跳估算的丢包率
+    pub loss_pct: f32,
+    // 心跳估算的抖动
+    pub jitter_ms: u32,
+    // 注册时是否协商了压缩
+    pub compress: bool,
+    // 压缩后和压缩前的比值，没有压缩过数据时为null
+    pub compress_ratio: Option<f32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -78,31 +95,375 @@ pub struct NetworkInfo {
     pub mask_ip: Ipv4Addr,
     // 网关
     pub gateway_ip: Ipv4Addr,
+    // 是否暂停组内转发
+    pub paused: bool,
+    // 暂停期间丢弃的数据包数
+    pub paused_drop: u64,
+    // 是否暂停新客户端加入
+    pub join_paused: bool,
+    // 是否转发广播和组播
+    pub allow_broadcast: bool,
+    // 禁止广播而丢弃的数据包数
+    pub broadcast_drop: u64,
+    // 组内客户端发出并由服务端转发的字节数，组网回收后重新计数
+    pub forward_bytes: u64,
+    // 组网标签
+    pub tags: Vec<String>,
+    // --token-meta配置的客户和标签，没有配置时为空
+    pub customer: String,
+    pub label: String,
+    // 在线客户端的nat类型统计
+    pub nat: NatStats,
+    // 在线客户端上报的最小有效MTU，用于发现受底层网络限制的客户端，只作参考
+    pub min_effective_mtu: Option<u32>,
     // 网段下的客户端列表
     pub clients: Vec<ClientInfo>,
 }
 
+/// 在线客户端的nat类型统计，对称型nat的客户端多时中继流量会更大
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NatStats {
+    pub cone: usize,
+    pub symmetric: usize,
+    // 还没有上报状态的客户端
+    pub unknown: usize,
+}
+
 impl NetworkInfo {
     pub fn new(network_ip: Ipv4Addr, mask_ip: Ipv4Addr, gateway_ip: Ipv4Addr) -> Self {
         Self {
             network_ip,
             mask_ip,
             gateway_ip,
+            paused: false,
+            paused_drop: 0,
+            join_paused: false,
+            allow_broadcast: true,
+            broadcast_drop: 0,
+            forward_bytes: 0,
+            tags: Default::default(),
+            customer: Default::default(),
+            label: Default::default(),
+            nat: Default::default(),
+            min_effective_mtu: None,
             clients: Default::default(),
         }
     }
 }
 
+/// 暂停的范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseScope {
+    // 组内转发，客户端保持在线
+    Forward,
+    // 新客户端加入，已注册的客户端不受影响
+    Join,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientKey {
+    pub group: String,
+    pub virtual_ip: Ipv4Addr,
+}
+
+/// 把客户端移到其他组网
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveClient {
+    pub group: String,
+    pub virtual_ip: Ipv4Addr,
+    pub to_group: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientP2p {
+    // 客户端上报的p2p地址
+    pub p2p_list: Vec<Ipv4Addr>,
+    pub is_cone: bool,
+    pub update_time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupRouteItem {
+    pub src_group: String,
+    pub src_ip: Ipv4Addr,
+    pub dst_group: String,
+    pub dst_ip: Ipv4Addr,
+    // 转发的数据包数，添加和删除时不需要
+    #[serde(default)]
+    pub forwarded: u64,
+    // 丢弃的数据包数
+    #[serde(default)]
+    pub dropped: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupTags {
+    pub group: String,
+    // 覆盖原有标签，为空则清除
+    pub tags: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// 组网配置，用于迁移到其他服务器时在客户端连接前重建组网
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupBundle {
+    pub group: String,
+    pub network: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub join_paused: bool,
+    #[serde(default = "default_true")]
+    pub allow_broadcast: bool,
+    // 源或目标是该组网的组网间转发规则
+    #[serde(default)]
+    pub routes: Vec<GroupRouteItem>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GroupList {
     pub group_list: Vec<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupSummary {
+    pub group: String,
+    pub customer: String,
+    // 在线客户端数
+    pub online: usize,
+    // 客户端总数
+    pub total: usize,
+    pub paused: bool,
+}
+
+/// 组网的地址使用情况
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupUtilization {
+    pub group: String,
+    // 客户名称，没有配置时为空
+    pub customer: String,
+    // 可分配的地址数，不含网络地址、广播地址和网关
+    pub usable: u32,
+    // 已分配的地址数
+    pub assigned: u32,
+    pub free: u32,
+    // 使用率(百分比)
+    pub used_pct: f64,
+    // 组内客户端发出并由服务端转发的字节数
+    pub forward_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AllClientsQuery {
+    // 页码，从1开始
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default)]
+    pub page_size: usize,
+    // 排序字段 group|name|virtual_ip|address|traffic，默认group
+    #[serde(default)]
+    pub sort: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientItem {
+    pub group: String,
+    pub device_id: String,
+    pub name: String,
+    pub virtual_ip: Ipv4Addr,
+    pub address: SocketAddr,
+    pub up_stream: u64,
+    pub down_stream: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientPage {
+    pub page: usize,
+    pub page_size: usize,
+    // 在线客户端总数
+    pub total: usize,
+    // 超过扫描上限，结果不完整
+    pub truncated: bool,
+    pub clients: Vec<ClientItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviewQuery {
+    pub group: String,
+    #[serde(default)]
+    pub device_id: String,
+    // 手动指定的ip
+    #[serde(default)]
+    pub virtual_ip: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub allow_ip_change: bool,
+    // 客户端期望的租期，0表示默认
+    #[serde(default)]
+    pub lease_secs: u32,
+    #[serde(default)]
+    pub compress: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceItem {
+    pub name: String,
+    pub virtual_ip: Ipv4Addr,
+    pub online: bool,
+    pub client_secret: bool,
+}
+
+/// 注册时服务端会返回的内容
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegistrationPreview {
+    pub virtual_ip: Ipv4Addr,
+    pub virtual_gateway: Ipv4Addr,
+    pub virtual_netmask: Ipv4Addr,
+    pub epoch: u64,
+    // 地址用完时按--on-exhaustion回收的掉线客户端ip
+    pub reclaimed_ip: Option<Ipv4Addr>,
+    pub device_info_list: Vec<DeviceItem>,
+    pub lease_secs: u32,
+    // 未配置--nat-test-port时为0
+    pub nat_test_port: u32,
+    pub compress: bool,
+    // 注册时的来源地址，预览中是web请求的来源地址
+    pub public_addr: Option<SocketAddr>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GroupsInfo {
     pub data: HashMap<String, NetworkInfo>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSession {
+    // 只展示token前缀
+    pub token: String,
+    pub create_time: String,
+    pub last_active: String,
+    // 是否为当前请求使用的会话
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub version: String,
+    pub port: u16,
+    // 对外公布的地址
+    pub announce_addr: Option<SocketAddr>,
+    pub gateway: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub check_finger: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerStatus {
+    // 所有组网的客户端总数
+    pub clients: usize,
+    // 客户端总数上限，null表示不限制
+    pub max_clients: Option<usize>,
+    // 所有组网在线客户端的nat类型统计
+    pub nat: NatStats,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigView {
+    // 当前配置的hash，变更历史见程序目录下的config_history.jsonl
+    pub hash: String,
+    // 当前token白名单的数量，未开启白名单时为null
+    pub token_count: Option<usize>,
+    // --token-meta配置的客户信息，不包含token
+    pub token_meta: Vec<TokenMeta>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogTailQuery {
+    // 返回最后多少行，默认100，不超过--log-tail-max-lines
+    pub lines: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogsQuery {
+    // 返回最后多少KB，默认64，不超过--logs-max-kb
+    pub kb: Option<u64>,
+}
+
+/// token的在线客户端数，token就是组网id
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub group: String,
+    pub customer: String,
+    pub label: String,
+    pub online: usize,
+    // --token-meta配置的上限，没有配置时为null
+    pub max_online: Option<usize>,
+}
+
+/// 组网内客户端之间使用p2p和经服务端中继的情况
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayStats {
+    pub group: String,
+    // 和所有在线客户端都建立了p2p的客户端数
+    pub p2p: usize,
+    // 至少和一个在线客户端经服务端中继的客户端数
+    pub relay: usize,
+    // 没有上报状态的客户端数
+    pub unknown: usize,
+    pub clients: Vec<RelayClient>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayClient {
+    pub virtual_ip: Ipv4Addr,
+    pub name: String,
+    // p2p|relay|unknown
+    pub mode: String,
+    // 没有建立p2p的在线客户端
+    pub relay_peers: Vec<Ipv4Addr>,
+    // 经服务端中继发出的字节数
+    pub relayed_bytes: u64,
+}
+
+/// 认证失败过多而临时封禁的来源ip
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IpBanItem {
+    pub ip: IpAddr,
+    pub unban_time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceQuery {
+    pub device_id: String,
+}
+
+/// 按设备ID查到的客户端，同一设备可能在多个组网中
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceLocation {
+    pub group: String,
+    pub virtual_ip: Ipv4Addr,
+    pub online: bool,
+    pub status_info: Option<ClientStatusInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetTokens {
+    // 完整的token白名单，覆盖原有的
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePassword {
+    pub old_password: String,
+    pub new_password: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginData {
     pub username: String,
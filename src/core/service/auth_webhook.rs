@@ -0,0 +1,221 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::core::store::expire_map::ExpireMap;
+use crate::util::HttpUrl;
+use crate::AuthFallback;
+
+/// 校验结果的缓存时间，客户端重复注册时不再请求
+const DECISION_TTL: Duration = Duration::from_secs(30);
+/// 响应状态行的最大长度
+const MAX_STATUS_LINE: usize = 1024;
+
+/// 注册时通过外部服务校验token，POST json {token,group,device_id,name,addr}，
+/// 响应2xx表示允许，401/403表示拒绝，其他状态码、连接失败或超时使用fallback且不缓存
+pub struct AuthWebhook {
+    url: HttpUrl,
+    timeout: Duration,
+    fallback: AuthFallback,
+    cache: ExpireMap<(String, String, IpAddr), bool>,
+}
+
+impl AuthWebhook {
+    pub fn new(url: HttpUrl, timeout: Duration, fallback: AuthFallback) -> Self {
+        Self {
+            url,
+            timeout,
+            fallback,
+            cache: ExpireMap::new(|_k, _v| {}),
+        }
+    }
+    pub async fn check(&self, token: &str, device_id: &str, name: &str, ip: IpAddr) -> bool {
+        let key = (token.to_string(), device_id.to_string(), ip);
+        if let Some(allow) = self.cache.get_val(&key) {
+            return allow;
+        }
+        let body = serde_json::json!({
+            "token": token,
+            // token就是组网id
+            "group": token,
+            "device_id": device_id,
+            "name": name,
+            "addr": ip.to_string(),
+        })
+        .to_string();
+        match tokio::time::timeout(self.timeout, post(&self.url, &body)).await {
+            Ok(Ok(status)) if (200..300).contains(&status) => {
+                self.cache.insert(key, true, DECISION_TTL).await;
+                true
+            }
+            Ok(Ok(status)) if status == 401 || status == 403 => {
+                self.cache.insert(key, false, DECISION_TTL).await;
+                false
+            }
+            Ok(Ok(status)) => {
+                log::warn!(
+                    "auth webhook响应异常 url={},status={},fallback={:?}",
+                    self.url,
+                    status,
+                    self.fallback
+                );
+                self.fallback == AuthFallback::Allow
+            }
+            Ok(Err(e)) => {
+                log::warn!(
+                    "auth webhook请求失败 url={},e={:?},fallback={:?}",
+                    self.url,
+                    e,
+                    self.fallback
+                );
+                self.fallback == AuthFallback::Allow
+            }
+            Err(_) => {
+                log::warn!(
+                    "auth webhook请求超时 url={},fallback={:?}",
+                    self.url,
+                    self.fallback
+                );
+                self.fallback == AuthFallback::Allow
+            }
+        }
+    }
+}
+
+/// 发送请求并返回http状态码，只读取状态行
+async fn post(url: &HttpUrl, body: &str) -> io::Result<u16> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        url.port,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut buf = Vec::with_capacity(128);
+    let mut chunk = [0u8; 128];
+    loop {
+        if let Some(end) = buf.windows(2).position(|v| v == b"\r\n") {
+            return parse_status(&buf[..end]);
+        }
+        if buf.len() > MAX_STATUS_LINE {
+            break;
+        }
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..len]);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "invalid http response",
+    ))
+}
+
+/// 解析 HTTP/1.1 200 OK
+fn parse_status(line: &[u8]) -> io::Result<u16> {
+    let line = String::from_utf8_lossy(line);
+    let mut items = line.split(' ');
+    match (items.next(), items.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => status
+            .parse::<u16>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid status line {}", line),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// 模拟的webhook服务，按token返回状态码，记录请求次数
+    async fn mock_server(delay: Duration) -> (HttpUrl, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let count = Arc::new(AtomicUsize::new(0));
+        let count1 = count.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                count1.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let len = stream.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..len]).to_string();
+                    let status = if request.contains("\"token\":\"allow\"") {
+                        "200 OK"
+                    } else if request.contains("\"token\":\"deny\"") {
+                        "403 Forbidden"
+                    } else {
+                        "500 Internal Server Error"
+                    };
+                    tokio::time::sleep(delay).await;
+                    let _ = stream
+                        .write_all(
+                            format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes(),
+                        )
+                        .await;
+                });
+            }
+        });
+        let url = format!("http://127.0.0.1:{}/auth", port).parse().unwrap();
+        (url, count)
+    }
+
+    #[tokio::test]
+    async fn decision_cached() {
+        let (url, count) = mock_server(Duration::ZERO).await;
+        let webhook = AuthWebhook::new(url, Duration::from_secs(1), AuthFallback::Deny);
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        assert!(webhook.check("allow", "d1", "n1", ip).await);
+        assert!(webhook.check("allow", "d1", "n1", ip).await);
+        assert!(!webhook.check("deny", "d1", "n1", ip).await);
+        assert!(!webhook.check("deny", "d1", "n1", ip).await);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        // 服务异常时使用fallback，不缓存
+        assert!(!webhook.check("error", "d1", "n1", ip).await);
+        assert!(!webhook.check("error", "d1", "n1", ip).await);
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn fallback() {
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        // 超时
+        let (url, _) = mock_server(Duration::from_secs(5)).await;
+        let webhook = AuthWebhook::new(url, Duration::from_millis(100), AuthFallback::Allow);
+        let start = std::time::Instant::now();
+        assert!(webhook.check("deny", "d1", "n1", ip).await);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // 无法连接
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let url: HttpUrl = format!("http://127.0.0.1:{}/", port).parse().unwrap();
+        let webhook = AuthWebhook::new(url.clone(), Duration::from_secs(1), AuthFallback::Allow);
+        assert!(webhook.check("deny", "d1", "n1", ip).await);
+        let webhook = AuthWebhook::new(url, Duration::from_secs(1), AuthFallback::Deny);
+        assert!(!webhook.check("allow", "d1", "n1", ip).await);
+    }
+
+    #[test]
+    fn status_line() {
+        assert_eq!(parse_status(b"HTTP/1.1 204 No Content").unwrap(), 204);
+        assert_eq!(parse_status(b"HTTP/1.0 403").unwrap(), 403);
+        assert!(parse_status(b"SSH-2.0-OpenSSH").is_err());
+    }
+}
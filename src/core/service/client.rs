@@ -1,23 +1,32 @@
 #![allow(dead_code)]
 
-use std::net::SocketAddr;
+use chrono::Local;
+use std::cell::OnceCell;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-
-use tokio::net::UdpSocket;
+use std::time::{Duration, Instant};
 
 use crate::cipher::RsaCipher;
-use crate::core::entity::ClientInfo;
+use crate::core::entity::{ClientInfo, ClientRole, ForwardStats, NetworkInfo, OfflinePacketStats};
+use crate::core::service::compress::{compress, decompress};
+use crate::core::service::udp_sender::{TrySendTo, UdpSender};
 use crate::core::store::cache::{AppCache, Context};
 use crate::error::*;
-use crate::protocol::NetPacket;
-use crate::ConfigInfo;
+use crate::protocol::body::ENCRYPTION_RESERVED;
+use crate::protocol::{NetPacket, Protocol, MAX_TTL};
+use crate::{ConfigInfo, OfflinePacket};
+
+/// 每个掉线客户端最多暂存的数据包数
+const OFFLINE_QUEUE_LEN: usize = 64;
 
 #[derive(Clone)]
 pub struct ClientPacketHandler {
     cache: AppCache,
     config: ConfigInfo,
     rsa_cipher: Option<RsaCipher>,
-    udp: Arc<UdpSocket>,
+    udp: UdpSender,
 }
 
 impl ClientPacketHandler {
@@ -25,7 +34,7 @@ impl ClientPacketHandler {
         cache: AppCache,
         config: ConfigInfo,
         rsa_cipher: Option<RsaCipher>,
-        udp: Arc<UdpSocket>,
+        udp: UdpSender,
     ) -> Self {
         Self {
             cache,
@@ -42,12 +51,29 @@ impl ClientPacketHandler {
         net_packet: NetPacket<B>,
         addr: SocketAddr,
     ) -> Result<()> {
-        if let Some(context) = self.cache.get_context(&addr) {
-            self.handle0(net_packet, context)
+        if let Some(context) = self.context(&addr) {
+            if net_packet.is_compress() {
+                let net_packet = decompress_uplink(&net_packet, &context)?;
+                self.handle0(net_packet, addr, &context)
+            } else {
+                self.handle0(net_packet, addr, &context)
+            }
         } else {
             Err(Error::Disconnect)
         }
     }
+    /// 开启--context-cache-ms时优先使用缓存的上下文，会话只在刷新时续期
+    fn context(&self, addr: &SocketAddr) -> Option<Arc<Context>> {
+        let context_cache = &self.cache.context_cache;
+        if let Some(context) = context_cache.get(addr) {
+            return Some(context);
+        }
+        let context = Arc::new(self.cache.get_context(addr)?);
+        // 只有客户端间转发的流量时也要保持和服务端的加密会话
+        self.cache.cipher_session.renew(addr);
+        context_cache.insert(*addr, context.clone());
+        Some(context)
+    }
 }
 
 impl ClientPacketHandler {
@@ -55,43 +81,668 @@ impl ClientPacketHandler {
     fn handle0<B: AsRef<[u8]> + AsMut<[u8]>>(
         &self,
         mut net_packet: NetPacket<B>,
-        context: Context,
+        addr: SocketAddr,
+        context: &Context,
     ) -> Result<()> {
         if net_packet.incr_ttl() > 1 {
+            if self.config.anti_spoof
+                && !source_matches(&net_packet, context, addr, &self.cache.spoof_dropped)
+            {
+                return Ok(());
+            }
+            if context.network_info.read().check_paused() {
+                return Ok(());
+            }
             if self.config.check_finger {
                 let finger = crate::cipher::Finger::new(&context.group);
-                finger.check_finger(&net_packet)?;
+                finger
+                    .check_finger(&net_packet)
+                    .map_err(|_| Error::FingerError)?;
             }
             let destination = net_packet.destination();
-            if destination.is_broadcast() || self.config.broadcast == destination {
-                //处理广播
-                broadcast(&self.udp, context, net_packet);
-            } else if let Some(client_info) =
-                context.network_info.read().clients.get(&destination.into())
+            let is_broadcast = destination.is_broadcast() || self.config.broadcast == destination;
             {
-                send_one(&self.udp, client_info, &net_packet);
+                let guard = context.network_info.read();
+                if broadcast_disabled(&guard, destination, is_broadcast) {
+                    return Ok(());
+                }
+                guard
+                    .forward_bytes
+                    .fetch_add(net_packet.data_len() as u64, Ordering::Relaxed);
+            }
+            if is_broadcast {
+                //处理广播
+                broadcast(&self.udp, &self.cache.forward_stats, context, net_packet);
+            } else {
+                let guard = context.network_info.read();
+                if let Some(client_info) = guard.clients.get(&destination.into()) {
+                    if client_info.online {
+                        send_one(
+                            &self.udp,
+                            &self.cache.forward_stats,
+                            client_info,
+                            &Outgoing::new(&net_packet),
+                        );
+                        if let Some(sender) = guard.clients.get(&context.virtual_ip) {
+                            sender
+                                .relayed_bytes
+                                .fetch_add(net_packet.buffer().len() as u64, Ordering::Relaxed);
+                        }
+                    } else {
+                        self.offline(addr, context, &guard, client_info, &net_packet)?;
+                    }
+                } else if let Some(gateway) =
+                    gateway_target(&guard, context.virtual_ip, destination.into())
+                {
+                    send_one(
+                        &self.udp,
+                        &self.cache.forward_stats,
+                        gateway,
+                        &Outgoing::new(&net_packet),
+                    );
+                } else {
+                    drop(guard);
+                    self.inter_group(context, &net_packet);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 目标客户端已注册但掉线
+    fn offline<B: AsRef<[u8]>>(
+        &self,
+        addr: SocketAddr,
+        context: &Context,
+        network_info: &NetworkInfo,
+        client_info: &ClientInfo,
+        net_packet: &NetPacket<B>,
+    ) -> Result<()> {
+        let reject = offline_packet(
+            self.config.offline_packet,
+            self.config.offline_grace,
+            client_info,
+            net_packet.buffer(),
+            Local::now().timestamp(),
+            Instant::now(),
+            &self.cache.offline_stats,
+        );
+        if reject {
+            if let Some(sender) = network_info.clients.get(&context.virtual_ip) {
+                self.unreachable_notice(addr, sender, net_packet.destination())?;
             }
         }
         Ok(())
     }
+    /// 通知发送方目标不在线，和其他服务端错误一样使用错误包携带消息
+    fn unreachable_notice(
+        &self,
+        addr: SocketAddr,
+        sender: &ClientInfo,
+        destination: Ipv4Addr,
+    ) -> Result<()> {
+        let msg = format!("destination offline {}", destination);
+        let bytes = msg.as_bytes();
+        let rs = vec![0u8; 12 + bytes.len() + ENCRYPTION_RESERVED];
+        let mut packet = NetPacket::new_encrypt(rs)?;
+        packet.set_protocol(Protocol::Error);
+        packet.set_payload(bytes)?;
+        packet.set_default_version();
+        packet.set_destination(Ipv4Addr::from(sender.virtual_ip));
+        packet.set_source(self.config.gateway);
+        packet.first_set_ttl(MAX_TTL);
+        packet.set_gateway_flag(true);
+        if let Some(aes) = self.cache.cipher_session.get_val(&addr) {
+            aes.encrypt_ipv4(&mut packet)?;
+        }
+        if let Some(tcp_sender) = &sender.tcp_sender {
+            let _ = tcp_sender.try_send(packet.buffer().to_vec());
+        } else {
+            self.udp.send_to(packet.buffer(), addr);
+        }
+        Ok(())
+    }
+    /// 按组网间转发规则转发到其他组网，各组网的网段相同，源ip在目标组网中被占用时丢弃
+    fn inter_group<B: AsRef<[u8]>>(&self, context: &Context, net_packet: &NetPacket<B>) {
+        let source: u32 = net_packet.source().into();
+        let destination: u32 = net_packet.destination().into();
+        if source != context.virtual_ip {
+            // 只转发客户端自己的数据
+            return;
+        }
+        let (dst_group, meter) =
+            match self
+                .cache
+                .group_routes
+                .get(&context.group, source, destination)
+            {
+                Some(rs) => rs,
+                None => return,
+            };
+        if let Some(network_info) = self.cache.virtual_network.get_val(&dst_group) {
+            let guard = network_info.read();
+            if !guard.clients.contains_key(&source) && !guard.check_paused() {
+                if let Some(client_info) = guard.clients.get(&destination) {
+                    if client_info.online {
+                        send_one(
+                            &self.udp,
+                            &self.cache.forward_stats,
+                            client_info,
+                            &Outgoing::new(net_packet),
+                        );
+                        meter.forwarded.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            }
+        }
+        meter.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 目标不在虚拟网段内时交给在线的网关客户端，网关客户端自己发出的不再转回
+fn gateway_target(
+    network_info: &NetworkInfo,
+    source: u32,
+    destination: u32,
+) -> Option<&ClientInfo> {
+    if destination & network_info.mask_ip == network_info.network_ip {
+        return None;
+    }
+    let ip = network_info.gateway_client?;
+    if ip == source {
+        return None;
+    }
+    network_info
+        .clients
+        .get(&ip)
+        .filter(|v| v.online && v.role == ClientRole::Gateway)
+}
+
+/// 组网禁止了广播时丢弃广播和组播，单播不受影响
+fn broadcast_disabled(
+    network_info: &NetworkInfo,
+    destination: Ipv4Addr,
+    is_broadcast: bool,
+) -> bool {
+    (is_broadcast || destination.is_multicast()) && network_info.check_broadcast_disabled()
+}
+
+/// 源虚拟ip必须是服务端分配给该地址的ip，不一致时计数并返回false，防止冒充同组网的其他客户端，
+/// 每个数据包都可能触发，只输出debug日志，数量看spoof_dropped计数
+fn source_matches<B: AsRef<[u8]>>(
+    net_packet: &NetPacket<B>,
+    context: &Context,
+    addr: SocketAddr,
+    counter: &AtomicU64,
+) -> bool {
+    let source = net_packet.source();
+    if u32::from(source) == context.virtual_ip {
+        return true;
+    }
+    incr(counter);
+    log::debug!(
+        "源虚拟ip和分配的ip不一致，丢弃 addr={},source={},virtual_ip={}",
+        addr,
+        source,
+        Ipv4Addr::from(context.virtual_ip)
+    );
+    false
 }
 
-fn broadcast<B: AsRef<[u8]>>(udp_socket: &UdpSocket, context: Context, net_packet: NetPacket<B>) {
-    for client_info in context.network_info.read().clients.values() {
-        send_one(udp_socket, client_info, &net_packet);
+/// 按配置处理发往掉线客户端的数据包并计数，返回是否需要通知发送方
+fn offline_packet(
+    mode: OfflinePacket,
+    grace: Duration,
+    client_info: &ClientInfo,
+    buf: &[u8],
+    timestamp: i64,
+    now: Instant,
+    stats: &OfflinePacketStats,
+) -> bool {
+    match mode {
+        OfflinePacket::Drop => {
+            incr(&stats.dropped);
+            false
+        }
+        OfflinePacket::Reject => {
+            incr(&stats.rejected);
+            true
+        }
+        OfflinePacket::Queue => {
+            if timestamp - client_info.offline_time > grace.as_secs() as i64 {
+                // 掉线太久，不太可能很快重连
+                incr(&stats.dropped);
+                return false;
+            }
+            let mut queue = client_info.offline_queue.lock();
+            while let Some((time, _)) = queue.front() {
+                if now.duration_since(*time) <= grace && queue.len() < OFFLINE_QUEUE_LEN {
+                    break;
+                }
+                queue.pop_front();
+                incr(&stats.expired);
+            }
+            queue.push_back((now, buf.to_vec()));
+            incr(&stats.queued);
+            false
+        }
     }
 }
 
-fn send_one<B: AsRef<[u8]>>(
-    udp_socket: &UdpSocket,
+/// 取出客户端掉线期间暂存的数据包，丢弃超过宽限时间的和加密方式不一致的
+pub fn take_offline_queue(
     client_info: &ClientInfo,
+    grace: Duration,
+    now: Instant,
+    stats: &OfflinePacketStats,
+) -> Vec<Vec<u8>> {
+    let queue = std::mem::take(&mut *client_info.offline_queue.lock());
+    let mut list = Vec::with_capacity(queue.len());
+    for (time, buf) in queue {
+        let secret_match = NetPacket::new(buf.as_slice())
+            .map(|packet| packet.is_encrypt() == client_info.client_secret)
+            .unwrap_or(false);
+        if now.duration_since(time) <= grace && secret_match {
+            incr(&stats.flushed);
+            list.push(buf);
+        } else {
+            incr(&stats.expired);
+        }
+    }
+    list
+}
+
+fn incr(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+fn broadcast<B: AsRef<[u8]>, S: TrySendTo>(
+    udp_socket: &UdpSender<S>,
+    stats: &ForwardStats,
+    context: &Context,
+    net_packet: NetPacket<B>,
+) {
+    let guard = context.network_info.read();
+    if let Some(sender) = guard.clients.get(&context.virtual_ip) {
+        if !sender.allow_broadcast() {
+            return;
+        }
+    }
+    let outgoing = Outgoing::new(&net_packet);
+    for client_info in guard.clients.values() {
+        send_one(udp_socket, stats, client_info, &outgoing);
+    }
+}
+
+/// 解压客户端发来的压缩包并计入该客户端的压缩统计，注册时没有协商压缩的拒绝
+fn decompress_uplink<B: AsRef<[u8]>>(
     net_packet: &NetPacket<B>,
+    context: &Context,
+) -> io::Result<NetPacket<Vec<u8>>> {
+    let guard = context.network_info.read();
+    let sender = guard
+        .clients
+        .get(&context.virtual_ip)
+        .filter(|v| v.compress)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "compress not negotiated"))?;
+    let packet = decompress(net_packet)?;
+    sender
+        .compress_stats
+        .add(packet.data_len(), net_packet.data_len());
+    Ok(packet)
+}
+
+/// 待转发的数据包，发给协商了压缩的客户端时压缩，广播时只压缩一次
+struct Outgoing<'a, B> {
+    net_packet: &'a NetPacket<B>,
+    compressed: OnceCell<Option<Vec<u8>>>,
+}
+
+impl<'a, B: AsRef<[u8]>> Outgoing<'a, B> {
+    fn new(net_packet: &'a NetPacket<B>) -> Self {
+        Self {
+            net_packet,
+            compressed: OnceCell::new(),
+        }
+    }
+    /// 发给该客户端的数据，加密的数据体压缩不了，直接发送
+    fn bytes(&self, client_info: &ClientInfo) -> &[u8] {
+        if client_info.compress && !self.net_packet.is_encrypt() {
+            if let Some(buf) = self.compressed.get_or_init(|| compress(self.net_packet)) {
+                client_info
+                    .compress_stats
+                    .add(self.net_packet.data_len(), buf.len());
+                return buf;
+            }
+        }
+        self.net_packet.buffer()
+    }
+}
+
+fn send_one<B: AsRef<[u8]>, S: TrySendTo>(
+    udp_socket: &UdpSender<S>,
+    stats: &ForwardStats,
+    client_info: &ClientInfo,
+    outgoing: &Outgoing<B>,
 ) {
-    if client_info.online && client_info.client_secret == net_packet.is_encrypt() {
+    if client_info.online && client_info.client_secret == outgoing.net_packet.is_encrypt() {
+        let buf = outgoing.bytes(client_info);
+        incr(&stats.packets);
+        stats.bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
         if let Some(sender) = &client_info.tcp_sender {
-            let _ = sender.try_send(net_packet.buffer().to_vec());
+            let _ = sender.try_send(buf.to_vec());
         } else {
-            let _ = udp_socket.try_send_to(net_packet.buffer(), client_info.address);
+            udp_socket.send_to(buf, client_info.address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(10);
+
+    fn offline_client(offline_time: i64) -> ClientInfo {
+        ClientInfo {
+            online: false,
+            offline_time,
+            ..Default::default()
+        }
+    }
+
+    fn packet(encrypt: bool) -> Vec<u8> {
+        let mut packet = NetPacket::new(vec![0u8; 16]).unwrap();
+        packet.set_encrypt_flag(encrypt);
+        packet.into_buffer()
+    }
+
+    #[test]
+    fn offline_drop() {
+        let stats = OfflinePacketStats::default();
+        let client = offline_client(100);
+        let now = Instant::now();
+        let reject = offline_packet(
+            OfflinePacket::Drop,
+            GRACE,
+            &client,
+            &packet(false),
+            100,
+            now,
+            &stats,
+        );
+        assert!(!reject);
+        assert!(client.offline_queue.lock().is_empty());
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn offline_reject() {
+        let stats = OfflinePacketStats::default();
+        let client = offline_client(100);
+        let now = Instant::now();
+        let reject = offline_packet(
+            OfflinePacket::Reject,
+            GRACE,
+            &client,
+            &packet(false),
+            100,
+            now,
+            &stats,
+        );
+        assert!(reject);
+        assert!(client.offline_queue.lock().is_empty());
+        assert_eq!(stats.rejected.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn offline_queue() {
+        let stats = OfflinePacketStats::default();
+        let client = offline_client(100);
+        let now = Instant::now();
+        let queue = |buf: Vec<u8>, timestamp: i64| {
+            offline_packet(
+                OfflinePacket::Queue,
+                GRACE,
+                &client,
+                &buf,
+                timestamp,
+                now,
+                &stats,
+            )
+        };
+        assert!(!queue(packet(false), 105));
+        assert!(!queue(packet(true), 105));
+        // 超过宽限时间不再暂存
+        assert!(!queue(packet(false), 111));
+        assert_eq!(stats.queued.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+        // 加密方式不一致的不补发
+        let list = take_offline_queue(&client, GRACE, now, &stats);
+        assert_eq!(list, vec![packet(false)]);
+        assert_eq!(stats.flushed.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.expired.load(Ordering::Relaxed), 1);
+        assert!(client.offline_queue.lock().is_empty());
+    }
+
+    #[test]
+    fn offline_queue_limit_and_expire() {
+        let stats = OfflinePacketStats::default();
+        let client = offline_client(100);
+        let now = Instant::now();
+        for _ in 0..OFFLINE_QUEUE_LEN + 1 {
+            offline_packet(
+                OfflinePacket::Queue,
+                GRACE,
+                &client,
+                &packet(false),
+                100,
+                now,
+                &stats,
+            );
+        }
+        assert_eq!(client.offline_queue.lock().len(), OFFLINE_QUEUE_LEN);
+        assert_eq!(stats.expired.load(Ordering::Relaxed), 1);
+        let list = take_offline_queue(&client, GRACE, now + GRACE * 2, &stats);
+        assert!(list.is_empty());
+        assert_eq!(
+            stats.expired.load(Ordering::Relaxed),
+            OFFLINE_QUEUE_LEN as u64 + 1
+        );
+    }
+
+    /// 记录发送的目标地址和数据
+    #[derive(Default)]
+    struct RecordSocket {
+        sent: parking_lot::Mutex<Vec<SocketAddr>>,
+        bufs: parking_lot::Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl TrySendTo for RecordSocket {
+        fn try_send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+            self.sent.lock().push(target);
+            self.bufs.lock().push(buf.to_vec());
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn broadcast_rate_limit() {
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        for (ip, addr) in [(2, "1.1.1.1:100"), (3, "1.1.1.2:100"), (4, "1.1.1.3:100")] {
+            let client = ClientInfo {
+                online: true,
+                virtual_ip: 0x0A1A0000 + ip,
+                address: addr.parse().unwrap(),
+                ..Default::default()
+            };
+            info.clients.insert(0x0A1A0000 + ip, client);
+        }
+        info.clients.get_mut(&0x0A1A0002).unwrap().broadcast_limiter =
+            Some(crate::util::RateLimiter::new(3, Duration::from_secs(60)));
+        let network_info = std::sync::Arc::new(parking_lot::RwLock::new(info));
+        let socket = std::sync::Arc::new(RecordSocket::default());
+        let udp = UdpSender::new(socket.clone(), 0, Default::default());
+        let stats = ForwardStats::default();
+        for _ in 0..10 {
+            let context = Context {
+                network_info: network_info.clone(),
+                group: "a".into(),
+                virtual_ip: 0x0A1A0002,
+            };
+            broadcast(
+                &udp,
+                &stats,
+                &context,
+                NetPacket::new(packet(false)).unwrap(),
+            );
+        }
+        // 只有限速内的3个广播转发给组网内的客户端
+        let peer: SocketAddr = "1.1.1.2:100".parse().unwrap();
+        let sent = socket.sent.lock();
+        assert_eq!(sent.len(), 3 * 3);
+        assert_eq!(sent.iter().filter(|v| **v == peer).count(), 3);
+        let guard = network_info.read();
+        assert_eq!(
+            guard.clients[&0x0A1A0002]
+                .broadcast_dropped
+                .load(Ordering::Relaxed),
+            7
+        );
+        // 没有限速的客户端不受影响
+        assert!(guard.clients[&0x0A1A0003].allow_broadcast());
+    }
+
+    #[test]
+    fn gateway_preferred() {
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        let external = u32::from(Ipv4Addr::new(192, 168, 1, 10));
+        info.clients.insert(
+            0x0A1A0002,
+            ClientInfo {
+                virtual_ip: 0x0A1A0002,
+                online: true,
+                ..Default::default()
+            },
+        );
+        info.clients.insert(
+            0x0A1A0003,
+            ClientInfo {
+                virtual_ip: 0x0A1A0003,
+                online: true,
+                role: ClientRole::Gateway,
+                ..Default::default()
+            },
+        );
+        // 没有网关客户端
+        assert!(gateway_target(&info, 0x0A1A0002, external).is_none());
+        info.gateway_client = Some(0x0A1A0003);
+        let gateway = gateway_target(&info, 0x0A1A0002, external).unwrap();
+        assert_eq!(gateway.virtual_ip, 0x0A1A0003);
+        // 虚拟网段内的地址和网关客户端自己发出的不转发
+        assert!(gateway_target(&info, 0x0A1A0002, 0x0A1A0009).is_none());
+        assert!(gateway_target(&info, 0x0A1A0003, external).is_none());
+        // 网关客户端掉线或者地址被其他设备占用
+        info.clients.get_mut(&0x0A1A0003).unwrap().online = false;
+        assert!(gateway_target(&info, 0x0A1A0002, external).is_none());
+        let client = info.clients.get_mut(&0x0A1A0003).unwrap();
+        client.online = true;
+        client.role = ClientRole::Normal;
+        assert!(gateway_target(&info, 0x0A1A0002, external).is_none());
+    }
+
+    #[test]
+    fn forged_source_dropped() {
+        let info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        let context = Context {
+            network_info: std::sync::Arc::new(parking_lot::RwLock::new(info)),
+            group: "a".into(),
+            virtual_ip: 0x0A1A0002,
+        };
+        let addr: SocketAddr = "1.1.1.1:100".parse().unwrap();
+        let counter = AtomicU64::new(0);
+        let mut net_packet = NetPacket::new(packet(false)).unwrap();
+        net_packet.set_source(Ipv4Addr::from(0x0A1A0002));
+        assert!(source_matches(&net_packet, &context, addr, &counter));
+        // 冒充同组网的其他客户端
+        net_packet.set_source(Ipv4Addr::from(0x0A1A0003));
+        assert!(!source_matches(&net_packet, &context, addr, &counter));
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn mixed_compression() {
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        for (ip, compress) in [(2, true), (3, true), (4, false)] {
+            let client = ClientInfo {
+                online: true,
+                virtual_ip: 0x0A1A0000 + ip,
+                address: format!("1.1.1.{}:100", ip).parse().unwrap(),
+                compress,
+                ..Default::default()
+            };
+            info.clients.insert(0x0A1A0000 + ip, client);
         }
+        let network_info = std::sync::Arc::new(parking_lot::RwLock::new(info));
+        let context = |virtual_ip| Context {
+            network_info: network_info.clone(),
+            group: "a".into(),
+            virtual_ip,
+        };
+        let mut original = NetPacket::new(vec![0u8; 12 + 1000]).unwrap();
+        original.set_source(Ipv4Addr::from(0x0A1A0002));
+        let compressed = NetPacket::new(compress(&original).unwrap()).unwrap();
+        // 协商了压缩的客户端发来的压缩包解压后转发
+        let restored = decompress_uplink(&compressed, &context(0x0A1A0002)).unwrap();
+        assert_eq!(restored.buffer(), original.buffer());
+        // 没有协商压缩的客户端不能发送压缩包
+        assert!(decompress_uplink(&compressed, &context(0x0A1A0004)).is_err());
+
+        let socket = std::sync::Arc::new(RecordSocket::default());
+        let udp = UdpSender::new(socket.clone(), 0, Default::default());
+        broadcast(
+            &udp,
+            &ForwardStats::default(),
+            &context(0x0A1A0002),
+            restored,
+        );
+        let sent = socket.sent.lock();
+        let bufs = socket.bufs.lock();
+        for (addr, buf) in sent.iter().zip(bufs.iter()) {
+            let packet = NetPacket::new(buf.as_slice()).unwrap();
+            // 只发压缩包给协商了压缩的客户端
+            if addr.ip().to_string() == "1.1.1.4" {
+                assert!(!packet.is_compress());
+                assert_eq!(buf, original.buffer());
+            } else {
+                assert!(packet.is_compress());
+                assert_eq!(buf, compressed.buffer());
+            }
+        }
+        assert_eq!(sent.len(), 3);
+        let guard = network_info.read();
+        let ratio = guard.clients[&0x0A1A0002].compress_stats.ratio().unwrap();
+        assert!(ratio < 0.1);
+        assert!(guard.clients[&0x0A1A0004].compress_stats.ratio().is_none());
+    }
+
+    #[test]
+    fn group_broadcast_disabled() {
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        let mdns = Ipv4Addr::new(224, 0, 0, 251);
+        let peer = Ipv4Addr::new(10, 26, 0, 3);
+        assert!(!broadcast_disabled(&info, Ipv4Addr::BROADCAST, true));
+        assert!(!broadcast_disabled(&info, mdns, false));
+        info.allow_broadcast = false;
+        assert!(broadcast_disabled(&info, Ipv4Addr::BROADCAST, true));
+        assert!(broadcast_disabled(
+            &info,
+            Ipv4Addr::new(10, 26, 0, 255),
+            true
+        ));
+        assert!(broadcast_disabled(&info, mdns, false));
+        // 单播照常转发
+        assert!(!broadcast_disabled(&info, peer, false));
+        assert_eq!(info.broadcast_drop.load(Ordering::Relaxed), 3);
     }
 }
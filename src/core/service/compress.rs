@@ -0,0 +1,87 @@
+use std::io;
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::protocol::{NetPacket, HEAD_LEN};
+
+/// 解压后的最大长度，和udp数据包的上限一致，防止压缩炸弹
+const MAX_LEN: usize = 65535;
+
+/// 压缩数据体，头部原样保留并设置压缩标志，压缩后没有变小时返回None，直接发送原数据包
+pub fn compress<B: AsRef<[u8]>>(net_packet: &NetPacket<B>) -> Option<Vec<u8>> {
+    let payload = net_packet.payload();
+    let mut buf = Vec::with_capacity(net_packet.data_len());
+    buf.extend_from_slice(net_packet.head());
+    let mut encoder = DeflateEncoder::new(buf, Compression::fast());
+    encoder.write_all(payload).ok()?;
+    let buf = encoder.finish().ok()?;
+    if buf.len() >= net_packet.data_len() {
+        return None;
+    }
+    let mut packet = NetPacket::new(buf).ok()?;
+    packet.set_compress_flag(true);
+    Some(packet.into_buffer())
+}
+
+/// 解压带压缩标志的数据包，返回清除了压缩标志的数据包
+pub fn decompress<B: AsRef<[u8]>>(net_packet: &NetPacket<B>) -> io::Result<NetPacket<Vec<u8>>> {
+    let mut buf = Vec::with_capacity(net_packet.data_len() * 4);
+    buf.extend_from_slice(net_packet.head());
+    DeflateDecoder::new(net_packet.payload())
+        .take((MAX_LEN - HEAD_LEN + 1) as u64)
+        .read_to_end(&mut buf)?;
+    if buf.len() > MAX_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decompressed packet too large",
+        ));
+    }
+    let mut packet = NetPacket::new(buf)?;
+    packet.set_compress_flag(false);
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Protocol;
+    use std::net::Ipv4Addr;
+
+    fn packet(payload: &[u8]) -> NetPacket<Vec<u8>> {
+        let mut packet = NetPacket::new(vec![0u8; HEAD_LEN + payload.len()]).unwrap();
+        packet.set_default_version();
+        packet.set_protocol(Protocol::IpTurn);
+        packet.set_source(Ipv4Addr::new(10, 26, 0, 2));
+        packet.set_destination(Ipv4Addr::new(10, 26, 0, 3));
+        packet.set_payload(payload).unwrap();
+        packet
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = packet(&[7u8; 1200]);
+        let buf = compress(&original).unwrap();
+        assert!(buf.len() < original.data_len());
+        let compressed = NetPacket::new(buf).unwrap();
+        assert!(compressed.is_compress());
+        assert_eq!(compressed.destination(), Ipv4Addr::new(10, 26, 0, 3));
+        let restored = decompress(&compressed).unwrap();
+        assert!(!restored.is_compress());
+        assert_eq!(restored.buffer(), original.buffer());
+        // 压缩后没有变小的直接发送原数据包
+        assert!(compress(&packet(&[1, 2, 3])).is_none());
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let mut bad = packet(&[0xFF; 32]);
+        bad.set_compress_flag(true);
+        assert!(decompress(&bad).is_err());
+        // 解压后超过上限
+        let huge = compress(&packet(&vec![0u8; MAX_LEN])).unwrap();
+        assert!(decompress(&NetPacket::new(huge).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::Local;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::core::entity::NetworkInfo;
+
+/// 每个消费者最多积压的事件数，超过时丢弃最早的事件并通知消费者
+const EVENT_BUFFER: usize = 1024;
+
+/// 客户端事件，每个事件一行json，没有的字段不输出
+#[derive(Debug, Serialize)]
+pub struct ClientEvent<'a> {
+    // 事件时间，rfc3339格式
+    pub time: String,
+    // join/leave/expire/remove/reclaim/forward_error/lagged
+    pub event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_ip: Option<Ipv4Addr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<SocketAddr>,
+    // 组网的在线客户端数、客户端总数和纪元号
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub online: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // 消费者太慢而跳过的事件数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<u64>,
+}
+
+impl<'a> ClientEvent<'a> {
+    fn new(event: &'a str) -> Self {
+        Self {
+            time: Local::now().to_rfc3339(),
+            event,
+            group: None,
+            virtual_ip: None,
+            addr: None,
+            online: None,
+            total: None,
+            epoch: None,
+            error: None,
+            skipped: None,
+        }
+    }
+}
+
+/// 通过unix socket输出客户端事件，本地工具连接--event-socket即可读取，可以有多个消费者，
+/// 没有消费者时不生成事件，发送不会阻塞数据包的处理，消费者断开后可以随时重新连接
+#[derive(Clone, Default)]
+pub struct EventSocket {
+    sender: Option<broadcast::Sender<Arc<str>>>,
+}
+
+impl EventSocket {
+    /// 监听path，已存在的socket文件(上次运行留下的)会先删除，需要在运行时中调用
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{:?} exists and is not a socket", path),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)?;
+        // 事件包含组网名和地址，只允许同用户和同组的进程读取
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        tokio::spawn(accept(listener, sender.clone()));
+        Ok(Self {
+            sender: Some(sender),
+        })
+    }
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "event socket is only supported on unix",
+        ))
+    }
+    fn active(&self) -> Option<&broadcast::Sender<Arc<str>>> {
+        self.sender.as_ref().filter(|v| v.receiver_count() > 0)
+    }
+    fn publish(sender: &broadcast::Sender<Arc<str>>, event: &ClientEvent) {
+        match serde_json::to_string(event) {
+            Ok(mut line) => {
+                line.push('\n');
+                // 没有消费者时返回错误，忽略
+                let _ = sender.send(line.into());
+            }
+            Err(e) => log::warn!("序列化事件失败 {:?}", e),
+        }
+    }
+    /// 组网成员变化
+    pub fn membership(
+        &self,
+        group: &str,
+        event: &str,
+        virtual_ip: u32,
+        network_info: &NetworkInfo,
+    ) {
+        if let Some(sender) = self.active() {
+            let mut client_event = ClientEvent::new(event);
+            client_event.group = Some(group);
+            client_event.virtual_ip = Some(virtual_ip.into());
+            client_event.online = Some(network_info.clients.values().filter(|v| v.online).count());
+            client_event.total = Some(network_info.clients.len());
+            client_event.epoch = Some(network_info.epoch);
+            Self::publish(sender, &client_event);
+        }
+    }
+    /// 转发数据包出错，来源已注册时带上组网和虚拟ip
+    pub fn forward_error<E: std::fmt::Debug>(
+        &self,
+        addr: SocketAddr,
+        session: Option<(String, u32)>,
+        error: &E,
+    ) {
+        if let Some(sender) = self.active() {
+            let mut client_event = ClientEvent::new("forward_error");
+            client_event.addr = Some(addr);
+            if let Some((group, virtual_ip)) = &session {
+                client_event.group = Some(group);
+                client_event.virtual_ip = Some((*virtual_ip).into());
+            }
+            client_event.error = Some(format!("{:?}", error));
+            Self::publish(sender, &client_event);
+        }
+    }
+    /// 是否有消费者，用于跳过生成事件需要的查询
+    pub fn is_active(&self) -> bool {
+        self.active().is_some()
+    }
+}
+
+#[cfg(unix)]
+async fn accept(listener: tokio::net::UnixListener, sender: broadcast::Sender<Arc<str>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                log::info!("事件socket消费者连接");
+                tokio::spawn(consume(stream, sender.subscribe()));
+            }
+            Err(e) => {
+                log::warn!("事件socket accept失败 {:?}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// 把事件写给一个消费者，消费者断开时结束，写得慢只影响该消费者
+#[cfg(unix)]
+async fn consume(mut stream: tokio::net::UnixStream, mut receiver: broadcast::Receiver<Arc<str>>) {
+    use tokio::io::AsyncWriteExt;
+    loop {
+        let line = match receiver.recv().await {
+            Ok(line) => line,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                let mut event = ClientEvent::new("lagged");
+                event.skipped = Some(skipped);
+                match serde_json::to_string(&event) {
+                    Ok(line) => Arc::from(line + "\n"),
+                    Err(_) => continue,
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if let Err(e) = stream.write_all(line.as_bytes()).await {
+            log::info!("事件socket消费者断开 {:?}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::UnixStream;
+
+    async fn next_event(reader: &mut BufReader<UnixStream>) -> serde_json::Value {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn wait_consumers(events: &EventSocket, count: usize) {
+        while events.sender.as_ref().unwrap().receiver_count() != count {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn consumer_reconnect() {
+        let path = std::env::temp_dir().join(format!("vnts_events_{}.sock", std::process::id()));
+        let events = EventSocket::bind(&path).unwrap();
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        info.epoch = 3;
+        // 没有消费者时直接丢弃
+        assert!(!events.is_active());
+        events.membership("a", "join", 0x0A1A0002, &info);
+
+        let mut reader = BufReader::new(UnixStream::connect(&path).await.unwrap());
+        wait_consumers(&events, 1).await;
+        events.membership("a", "join", 0x0A1A0002, &info);
+        let event = next_event(&mut reader).await;
+        assert_eq!(event["event"], "join");
+        assert_eq!(event["group"], "a");
+        assert_eq!(event["virtual_ip"], "10.26.0.2");
+        assert_eq!(event["epoch"], 3);
+        assert!(event.get("error").is_none());
+
+        // 消费者断开后重新连接
+        drop(reader);
+        events.membership("a", "leave", 0x0A1A0002, &info);
+        wait_consumers(&events, 0).await;
+        let mut reader = BufReader::new(UnixStream::connect(&path).await.unwrap());
+        wait_consumers(&events, 1).await;
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        events.forward_error(addr, Some(("a".into(), 0x0A1A0002)), &"FingerError");
+        let event = next_event(&mut reader).await;
+        assert_eq!(event["event"], "forward_error");
+        assert_eq!(event["addr"], "1.2.3.4:5000");
+        assert_eq!(event["error"], "\"FingerError\"");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
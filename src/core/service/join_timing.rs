@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::core::entity::{JoinHistogram, JoinPhase};
+use crate::core::store::expire_map::ExpireMap;
+
+/// 加密握手的耗时保存到注册时一起输出，客户端握手后没有注册则过期丢弃
+const HANDSHAKE_TTL: Duration = Duration::from_secs(30);
+
+/// 统计注册各阶段的耗时，计入直方图，每次注册输出一条debug日志，
+/// 超过--slow-join-ms时输出info日志，用于判断注册慢在加解密、分配ip还是锁竞争
+#[derive(Clone)]
+pub struct JoinTiming {
+    histogram: Arc<JoinHistogram>,
+    slow: Duration,
+    // 来源地址 -> (rsa解密,aes初始化)
+    handshakes: ExpireMap<SocketAddr, (Duration, Duration)>,
+}
+
+/// 一次注册的计时
+pub struct JoinTimer {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(JoinPhase, Duration)>,
+}
+
+impl JoinTimer {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            phases: Vec::with_capacity(JoinPhase::ALL.len()),
+        }
+    }
+    /// 记录从上一次lap(或开始)到现在的耗时
+    pub fn lap(&mut self, phase: JoinPhase) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+    }
+    pub fn record(&mut self, phase: JoinPhase, duration: Duration) {
+        self.phases.push((phase, duration));
+    }
+}
+
+impl JoinTiming {
+    pub fn new(histogram: Arc<JoinHistogram>, slow: Duration) -> Self {
+        Self {
+            histogram,
+            slow,
+            handshakes: ExpireMap::new(|_k, _v| {}),
+        }
+    }
+    /// 记录加密握手的耗时
+    pub async fn handshake(&self, addr: SocketAddr, rsa: Duration, aes: Duration) {
+        self.histogram.observe(JoinPhase::Rsa, rsa);
+        self.histogram.observe(JoinPhase::Aes, aes);
+        self.handshakes
+            .insert(addr, (rsa, aes), HANDSHAKE_TTL)
+            .await;
+    }
+    /// 开始一次注册，带上该地址之前的加密握手耗时
+    pub fn start(&self, addr: &SocketAddr) -> JoinTimer {
+        let mut timer = JoinTimer::new();
+        if let Some((rsa, aes)) = self.handshakes.remove(addr) {
+            timer.record(JoinPhase::Rsa, rsa);
+            timer.record(JoinPhase::Aes, aes);
+        }
+        timer
+    }
+    /// 注册结束，计入直方图并输出日志
+    pub fn finish<E: std::fmt::Debug>(&self, timer: JoinTimer, addr: SocketAddr, error: Option<E>) {
+        let total = timer.start.elapsed();
+        for (phase, duration) in &timer.phases {
+            // rsa和aes在握手时已经计入
+            if !matches!(phase, JoinPhase::Rsa | JoinPhase::Aes) {
+                self.histogram.observe(*phase, *duration);
+            }
+        }
+        self.histogram.observe(JoinPhase::Total, total);
+        let line = format_line(addr, &timer.phases, total, error);
+        if total >= self.slow {
+            log::info!("注册较慢 {}", line);
+        } else {
+            log::debug!("注册耗时 {}", line);
+        }
+    }
+}
+
+fn format_line<E: std::fmt::Debug>(
+    addr: SocketAddr,
+    phases: &[(JoinPhase, Duration)],
+    total: Duration,
+    error: Option<E>,
+) -> String {
+    let mut line = format!("addr={}", addr);
+    match error {
+        Some(e) => {
+            let _ = write!(line, ",outcome=error,error={:?}", e);
+        }
+        None => line.push_str(",outcome=ok"),
+    }
+    for (phase, duration) in phases {
+        let _ = write!(line, ",{}={:?}", phase.as_str(), duration);
+    }
+    let _ = write!(line, ",total={:?}", total);
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn phases_and_histogram() {
+        let histogram = Arc::new(JoinHistogram::default());
+        let timing = JoinTiming::new(histogram.clone(), Duration::from_secs(1));
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        timing
+            .handshake(addr, Duration::from_millis(3), Duration::from_micros(20))
+            .await;
+        let mut timer = timing.start(&addr);
+        timer.lap(JoinPhase::Token);
+        timer.record(JoinPhase::LockWait, Duration::from_millis(70));
+        timer.record(JoinPhase::Alloc, Duration::from_secs(6));
+        let phases: Vec<&str> = timer.phases.iter().map(|v| v.0.as_str()).collect();
+        assert_eq!(phases, vec!["rsa", "aes", "token", "lock_wait", "alloc"]);
+        let line = format_line(addr, &timer.phases, Duration::from_millis(5), Some("busy"));
+        assert!(line.starts_with("addr=1.2.3.4:5000,outcome=error,error=\"busy\",rsa=3ms"));
+        timing.finish::<()>(timer, addr, None);
+        // 握手耗时只使用一次
+        assert!(timing.start(&addr).phases.is_empty());
+
+        let snapshot = histogram.snapshot();
+        let phase = |name: &str| snapshot.iter().find(|v| v.phase == name).unwrap();
+        // 3ms落在5ms的桶
+        assert_eq!(phase("rsa").buckets, vec![0, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(phase("rsa").sum_us, 3000);
+        assert_eq!(phase("lock_wait").buckets[3], 0);
+        assert_eq!(phase("lock_wait").buckets[4], 1);
+        // 超过最大的桶只计入+Inf
+        assert_eq!(phase("alloc").buckets[7], 0);
+        assert_eq!(phase("alloc").buckets[8], 1);
+        assert_eq!(phase("total").count, 1);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// 同时处理中的注册数超过上限时拒绝新的注册，让客户端稍后重试，
+/// 注册需要等待auth webhook和组网的写锁，负载高时积压的注册会拖慢已连接客户端的转发
+#[derive(Clone)]
+pub struct LoadShedder {
+    max: Option<usize>,
+    in_flight: Arc<AtomicUsize>,
+    shed: Arc<AtomicU64>,
+}
+
+/// 处理中的注册，drop时减少计数
+pub struct InFlight {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadShedder {
+    pub fn new(max: Option<usize>, shed: Arc<AtomicU64>) -> Self {
+        Self {
+            max,
+            in_flight: Default::default(),
+            shed,
+        }
+    }
+    /// 开始处理一个注册，超过上限时计数并返回None
+    pub fn acquire(&self) -> Option<InFlight> {
+        let count = self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let guard = InFlight {
+            in_flight: self.in_flight.clone(),
+        };
+        match self.max {
+            Some(max) if count >= max => {
+                drop(guard);
+                self.shed.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            _ => Some(guard),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shed_when_saturated() {
+        let shed = Arc::new(AtomicU64::new(0));
+        let shedder = LoadShedder::new(Some(2), shed.clone());
+        let first = shedder.acquire().unwrap();
+        let _second = shedder.acquire().unwrap();
+        assert!(shedder.acquire().is_none());
+        assert!(shedder.acquire().is_none());
+        assert_eq!(shed.load(Ordering::Relaxed), 2);
+        // 处理完成后恢复
+        drop(first);
+        assert!(shedder.acquire().is_some());
+        assert_eq!(shedder.in_flight.load(Ordering::Relaxed), 1);
+
+        let unlimited = LoadShedder::new(None, shed.clone());
+        let guards: Vec<InFlight> = (0..100).filter_map(|_| unlimited.acquire()).collect();
+        assert_eq!(guards.len(), 100);
+        assert_eq!(shed.load(Ordering::Relaxed), 2);
+    }
+}
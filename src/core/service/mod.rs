@@ -7,18 +7,27 @@ use tokio::sync::mpsc::Sender;
 use crate::cipher::RsaCipher;
 use crate::core::service::client::ClientPacketHandler;
 use crate::core::service::server::ServerPacketHandler;
+use crate::core::service::udp_sender::UdpSender;
 use crate::core::store::cache::AppCache;
 use crate::error::*;
 use crate::protocol::NetPacket;
 use crate::ConfigInfo;
 
+pub mod auth_webhook;
 pub mod client;
+pub mod compress;
+pub mod event_socket;
+pub mod join_timing;
+pub mod load_shed;
+pub mod pressure;
 pub mod server;
+pub mod udp_sender;
 
 #[derive(Clone)]
 pub struct PacketHandler {
     client: ClientPacketHandler,
     server: ServerPacketHandler,
+    cache: AppCache,
 }
 
 impl PacketHandler {
@@ -28,6 +37,7 @@ impl PacketHandler {
         rsa_cipher: Option<RsaCipher>,
         udp: Arc<UdpSocket>,
     ) -> Self {
+        let udp = UdpSender::new(udp, config.udp_send_retries, cache.udp_send_stats.clone());
         let client = ClientPacketHandler::new(
             cache.clone(),
             config.clone(),
@@ -36,7 +46,11 @@ impl PacketHandler {
         );
         let server =
             ServerPacketHandler::new(cache.clone(), config.clone(), rsa_cipher.clone(), udp);
-        Self { client, server }
+        Self {
+            client,
+            server,
+            cache,
+        }
     }
 }
 
@@ -50,7 +64,11 @@ impl PacketHandler {
         self.handle0(net_packet, addr, tcp_sender)
             .await
             .unwrap_or_else(|e| {
-                log::error!("addr={},{:?}", addr, e);
+                if e.is_client_fault() {
+                    log::debug!("addr={},{:?}", addr, e);
+                } else {
+                    log::error!("addr={},{:?}", addr, e);
+                }
                 None
             })
     }
@@ -63,7 +81,19 @@ impl PacketHandler {
         if net_packet.is_gateway() {
             self.server.handle(net_packet, addr, tcp_sender).await
         } else {
-            self.client.handle(net_packet, addr)?;
+            if let Err(e) = self.client.handle(net_packet, addr) {
+                let events = &self.cache.events;
+                // 未注册的来源不输出
+                if events.is_active() && !matches!(e, Error::Disconnect) {
+                    let session = self
+                        .cache
+                        .addr_session
+                        .get_val(&addr)
+                        .map(|(group, virtual_ip, _)| (group, virtual_ip));
+                    events.forward_error(addr, session, &e);
+                }
+                return Err(e);
+            }
             Ok(None)
         }
     }
@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+/// 资源采样间隔，也是告知客户端的重试等待时间
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// 处于压力下时，降到阈值的该比例以下才解除，避免在阈值附近频繁切换
+const RELEASE_RATIO: f64 = 0.9;
+
+/// 资源阈值，为None的不检查
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub mem_mb: Option<u64>,
+    pub load: Option<f32>,
+}
+
+/// 一次采样，取不到的为None
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    rss_mb: Option<u64>,
+    load: Option<f32>,
+}
+
+fn exceeds(value: f64, limit: f64, pressured: bool) -> bool {
+    if pressured {
+        value >= limit * RELEASE_RATIO
+    } else {
+        value >= limit
+    }
+}
+
+/// 根据采样判断是否处于资源压力下，任意一项超过阈值即为压力
+fn under_pressure(pressured: bool, sample: Sample, limits: Limits) -> bool {
+    let mem = match (sample.rss_mb, limits.mem_mb) {
+        (Some(rss_mb), Some(limit)) => exceeds(rss_mb as f64, limit as f64, pressured),
+        _ => false,
+    };
+    let load = match (sample.load, limits.load) {
+        (Some(load), Some(limit)) => exceeds(load as f64, limit as f64, pressured),
+        _ => false,
+    };
+    mem || load
+}
+
+/// 解析/proc/self/status中的VmRSS(kB)
+fn parse_rss_mb(status: &str) -> Option<u64> {
+    let line = status.lines().find(|v| v.starts_with("VmRSS:"))?;
+    let kb: u64 = line["VmRSS:".len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb / 1024)
+}
+
+/// 解析/proc/loadavg中的1分钟平均负载
+fn parse_load(loadavg: &str) -> Option<f32> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+fn sample() -> Sample {
+    Sample {
+        rss_mb: std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|v| parse_rss_mb(&v)),
+        load: std::fs::read_to_string("/proc/loadavg")
+            .ok()
+            .and_then(|v| parse_load(&v)),
+    }
+}
+
+/// 定时采样进程内存和系统负载，超过阈值时设置flag，注册时拒绝新的客户端，
+/// 已注册的客户端不受影响，压力解除后清除flag；取不到采样(非linux)时不启用
+pub async fn start(limits: Limits, flag: Arc<AtomicBool>) {
+    let first = sample();
+    if (limits.mem_mb.is_some() && first.rss_mb.is_none())
+        || (limits.load.is_some() && first.load.is_none())
+    {
+        log::warn!(
+            "无法读取进程内存或系统负载，资源压力检查可能不生效 {:?}",
+            first
+        );
+    }
+    let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        ticker.tick().await;
+        let sample = sample();
+        let pressured = flag.load(Ordering::Relaxed);
+        let current = under_pressure(pressured, sample, limits);
+        if current != pressured {
+            flag.store(current, Ordering::Relaxed);
+            if current {
+                log::warn!(
+                    "资源紧张，暂停接受新客户端 rss_mb={:?},load={:?},limits={:?}",
+                    sample.rss_mb,
+                    sample.load,
+                    limits
+                );
+            } else {
+                log::info!(
+                    "资源压力解除，恢复接受新客户端 rss_mb={:?},load={:?}",
+                    sample.rss_mb,
+                    sample.load
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pressure_hysteresis() {
+        let limits = Limits {
+            mem_mb: Some(100),
+            load: None,
+        };
+        let sample = |rss_mb| Sample {
+            rss_mb: Some(rss_mb),
+            load: Some(0.5),
+        };
+        assert!(!under_pressure(false, sample(99), limits));
+        assert!(under_pressure(false, sample(100), limits));
+        // 降到阈值的90%以下才解除
+        assert!(under_pressure(true, sample(95), limits));
+        assert!(!under_pressure(true, sample(89), limits));
+        // 没有配置的阈值和取不到的采样不检查
+        assert!(!under_pressure(false, Sample::default(), limits));
+        let load = Limits {
+            mem_mb: None,
+            load: Some(4.0),
+        };
+        assert!(!under_pressure(false, sample(1000), load));
+        assert!(under_pressure(
+            false,
+            Sample {
+                rss_mb: None,
+                load: Some(4.5),
+            },
+            load
+        ));
+    }
+
+    #[test]
+    fn parse_proc() {
+        let status = "Name:\tvnts\nVmPeak:\t  20480 kB\nVmRSS:\t   10752 kB\nThreads:\t4\n";
+        assert_eq!(parse_rss_mb(status), Some(10));
+        assert_eq!(parse_rss_mb("Name:\tvnts\n"), None);
+        assert_eq!(parse_load("0.52 0.58 0.59 1/189 12345\n"), Some(0.52));
+        assert_eq!(parse_load(""), None);
+    }
+}
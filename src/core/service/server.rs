@@ -4,31 +4,56 @@ use packet::ip::ipv4;
 use packet::ip::ipv4::packet::IpV4Packet;
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io, result};
 
 use protobuf::Message;
-use tokio::net::UdpSocket;
+use rand::Rng;
 use tokio::sync::mpsc::Sender;
 
 use crate::cipher::{Aes256GcmCipher, Finger, RsaCipher};
-use crate::core::entity::{ClientInfo, ClientStatusInfo, NetworkInfo};
+use crate::core::entity::{ClientInfo, ClientRole, ClientStatusInfo, JoinPhase, NetworkInfo};
+use crate::core::service::auth_webhook::AuthWebhook;
+use crate::core::service::client::take_offline_queue;
+use crate::core::service::join_timing::{JoinTimer, JoinTiming};
+use crate::core::service::load_shed::LoadShedder;
+use crate::core::service::pressure;
+use crate::core::service::udp_sender::UdpSender;
 use crate::core::store::cache::{AppCache, Context};
 use crate::error::*;
 use crate::proto::message;
-use crate::proto::message::{DeviceList, RegistrationRequest, RegistrationResponse};
+use crate::proto::message::{DeviceList, LocalSubnet, RegistrationRequest, RegistrationResponse};
 use crate::protocol::body::ENCRYPTION_RESERVED;
 use crate::protocol::ip_turn_packet::BroadcastPacket;
 use crate::protocol::{control_packet, error_packet, service_packet, NetPacket, Protocol, MAX_TTL};
-use crate::{protocol, ConfigInfo};
+use crate::util::{Cidr, NamePattern, RateLimiter};
+use crate::{
+    protocol, AllocStrategy, ConfigInfo, CountOffline, DuplicateDevice, FieldLimits, NamePolicy,
+    OnExhaustion, OversizedField, SubnetOverlap,
+};
+
+/// 补发掉线期间暂存的数据包前的等待时间，让注册响应先到达客户端
+const OFFLINE_FLUSH_DELAY: Duration = Duration::from_millis(100);
+/// 数据端口每秒最多处理的指标查询次数，防止被用于放大攻击
+const METRICS_RATE_LIMIT: u32 = 10;
+/// 客户端上报的有效MTU的范围，超出范围的上报忽略
+const MTU_RANGE: std::ops::RangeInclusive<u32> = 576..=65535;
 
 #[derive(Clone)]
 pub struct ServerPacketHandler {
     cache: AppCache,
     config: ConfigInfo,
     rsa_cipher: Option<RsaCipher>,
-    udp: Arc<UdpSocket>,
+    udp: UdpSender,
+    metrics_limiter: Arc<RateLimiter>,
+    // 为None时不回应握手提示
+    handshake_hint_limiter: Option<Arc<RateLimiter>>,
+    auth_webhook: Option<Arc<AuthWebhook>>,
+    load_shedder: LoadShedder,
+    join_timing: JoinTiming,
 }
 
 impl ServerPacketHandler {
@@ -36,13 +61,36 @@ impl ServerPacketHandler {
         cache: AppCache,
         config: ConfigInfo,
         rsa_cipher: Option<RsaCipher>,
-        udp: Arc<UdpSocket>,
+        udp: UdpSender,
     ) -> Self {
+        let auth_webhook = config.auth_webhook.clone().map(|url| {
+            Arc::new(AuthWebhook::new(
+                url,
+                config.auth_webhook_timeout,
+                config.auth_webhook_fallback,
+            ))
+        });
+        let load_shedder = LoadShedder::new(
+            config.max_pending_registrations,
+            cache.registration_shed.clone(),
+        );
+        let join_timing = JoinTiming::new(cache.join_histogram.clone(), config.slow_join);
+        let handshake_hint_limiter = (config.handshake_hint_rate > 0).then(|| {
+            Arc::new(RateLimiter::new(
+                config.handshake_hint_rate,
+                Duration::from_secs(1),
+            ))
+        });
         Self {
             cache,
             config,
             rsa_cipher,
             udp,
+            metrics_limiter: Arc::new(RateLimiter::new(METRICS_RATE_LIMIT, Duration::from_secs(1))),
+            handshake_hint_limiter,
+            auth_webhook,
+            load_shedder,
+            join_timing,
         }
     }
 }
@@ -74,12 +122,24 @@ impl ServerPacketHandler {
         }
         // 解密
         let aes = if net_packet.is_encrypt() {
-            if let Some(aes) = self.cache.cipher_session.get(&addr) {
+            // 解密成功才续期，伪造来源地址的数据包不能让会话一直有效
+            if let Some(aes) = self.cache.cipher_session.get_val(&addr) {
                 aes.decrypt_ipv4(&mut net_packet)?;
+                self.cache.cipher_session.renew(&addr);
                 Some(aes)
             } else {
-                log::info!("没有密钥:{},head={:?}", addr, net_packet.head());
-                return Ok(Some(self.handle_err(addr, source, Error::NoKey)?));
+                // udp上数据包可能先于握手完成到达，回应NoKey让客户端重新握手，而不是一直重试
+                let hint = handshake_hint(
+                    self.handshake_hint_limiter.as_deref(),
+                    &net_packet,
+                    self.config.gateway,
+                );
+                if hint.is_some() {
+                    log::info!("没有密钥:{},head={:?}", addr, net_packet.head());
+                } else {
+                    log::debug!("没有密钥，不回应握手提示:{}", addr);
+                }
+                return Ok(hint);
             }
         } else {
             None
@@ -121,13 +181,18 @@ impl ServerPacketHandler {
         source: Ipv4Addr,
         e: Error,
     ) -> Result<NetPacket<Vec<u8>>> {
-        log::warn!("addr={},source={},{:?}", addr, source, e);
+        if e.is_client_fault() {
+            log::info!("addr={},source={},{:?}", addr, source, e);
+        } else {
+            log::warn!("addr={},source={},{:?}", addr, source, e);
+        }
         let rs = vec![0u8; 12 + ENCRYPTION_RESERVED];
         let mut packet = NetPacket::new_encrypt(rs)?;
         match e {
             Error::Io(_) => {}
             Error::Channel(_) => {}
             Error::Protobuf(_) => {}
+            Error::FingerError => {}
 
             Error::AddressExhausted => {
                 packet.set_transport_protocol(error_packet::Protocol::AddressExhausted.into());
@@ -212,6 +277,9 @@ impl ServerPacketHandler {
                 match protocol::ip_turn_packet::Protocol::from(net_packet.transport_protocol()) {
                     protocol::ip_turn_packet::Protocol::Ipv4Broadcast => {
                         //处理选择性广播,进过网关还原成原始广播
+                        if context.network_info.read().check_paused() {
+                            return Ok(None);
+                        }
                         let broadcast_packet = BroadcastPacket::new(net_packet.payload())?;
                         let exclude = broadcast_packet.addresses();
                         let broadcast_net_packet = NetPacket::new(broadcast_packet.data()?)?;
@@ -264,19 +332,27 @@ impl ServerPacketHandler {
         server_secret: bool,
     ) -> result::Result<Result<Option<NetPacket<Vec<u8>>>>, NetPacket<B>> {
         if net_packet.protocol() == Protocol::Service {
-            if let service_packet::Protocol::RegistrationRequest =
-                protocol::service_packet::Protocol::from(net_packet.transport_protocol())
-            {
-                //注册
-                return Ok(self
-                    .register(net_packet, addr, tcp_sender, server_secret)
-                    .await);
+            match protocol::service_packet::Protocol::from(net_packet.transport_protocol()) {
+                service_packet::Protocol::RegistrationRequest => {
+                    //注册
+                    return Ok(self
+                        .register(net_packet, addr, tcp_sender, server_secret)
+                        .await);
+                }
+                service_packet::Protocol::MetricsRequest => {
+                    return Ok(self.metrics(net_packet, addr));
+                }
+                _ => {}
             }
         } else if net_packet.protocol() == Protocol::Control {
-            if let control_packet::Protocol::AddrRequest =
-                protocol::control_packet::Protocol::from(net_packet.transport_protocol())
-            {
-                return Ok(self.control_addr_request(addr));
+            match protocol::control_packet::Protocol::from(net_packet.transport_protocol()) {
+                control_packet::Protocol::AddrRequest => {
+                    return Ok(self.control_addr_request(addr));
+                }
+                control_packet::Protocol::NatTestRequest => {
+                    return Ok(Ok(nat_test_response(&net_packet, addr, self.config.port)));
+                }
+                _ => {}
             }
         }
         Err(net_packet)
@@ -295,22 +371,50 @@ impl ServerPacketHandler {
         packet.set_transport_protocol(control_packet::Protocol::Pong.into());
         packet.set_payload(net_packet.payload())?;
         let mut pong_packet = control_packet::PongPacket::new(packet.payload_mut())?;
-        let epoch = context.network_info.read().epoch;
+        let guard = context.network_info.read();
+        if let Some(client) = guard.clients.get(&context.virtual_ip) {
+            client
+                .link_quality
+                .lock()
+                .on_heartbeat(Instant::now(), pong_packet.time());
+            if client.reassigned.swap(false, Ordering::Relaxed) {
+                // 被移到了其他组网，用注册响应代替心跳回应，客户端据此切换到新的地址
+                return self.reassigned_response(&guard, client).map(Some);
+            }
+        }
+        let epoch = guard.epoch;
+        drop(guard);
         // 这里给客户端的是丢失精度的，可能导致客户端无法感知变更
         pong_packet.set_epoch(epoch as u16);
         Ok(Some(packet))
     }
+    /// 不经过注册请求，直接把当前的地址分配发给客户端
+    fn reassigned_response(
+        &self,
+        network_info: &NetworkInfo,
+        client: &ClientInfo,
+    ) -> Result<NetPacket<Vec<u8>>> {
+        let mut response = RegistrationResponse::new();
+        response.virtual_ip = client.virtual_ip;
+        response.virtual_gateway = self.config.gateway.into();
+        response.virtual_netmask = self.config.netmask.into();
+        response.epoch = network_info.epoch as u32;
+        response.device_info_list = Self::clients_info(&network_info.clients, client.virtual_ip);
+        response.lease_secs =
+            grant_lease(0, self.config.min_lease_secs, self.config.max_lease_secs);
+        response.nat_test_port = self.config.nat_test_port.unwrap_or(0) as u32;
+        response.compress = client.compress;
+        set_public_addr(&mut response, client.address);
+        let bytes = response.write_to_bytes()?;
+        let rs = vec![0u8; 12 + bytes.len() + ENCRYPTION_RESERVED];
+        let mut packet = NetPacket::new_encrypt(rs)?;
+        packet.set_protocol(Protocol::Service);
+        packet.set_transport_protocol(service_packet::Protocol::RegistrationResponse.into());
+        packet.set_payload(&bytes)?;
+        Ok(packet)
+    }
     fn control_addr_request(&self, addr: SocketAddr) -> Result<Option<NetPacket<Vec<u8>>>> {
-        let ipv4 = match addr.ip() {
-            IpAddr::V4(ipv4) => ipv4,
-            IpAddr::V6(ip) => {
-                if let Some(ipv4) = ip.to_ipv4_mapped() {
-                    ipv4
-                } else {
-                    Ipv4Addr::UNSPECIFIED
-                }
-            }
-        };
+        let ipv4 = observed_ipv4(addr);
         let mut packet = NetPacket::new_encrypt(vec![0u8; 12 + 6 + ENCRYPTION_RESERVED])?;
         packet.set_protocol(Protocol::Control);
         packet.set_transport_protocol(control_packet::Protocol::AddrResponse.into());
@@ -321,6 +425,41 @@ impl ServerPacketHandler {
     }
 }
 
+/// 看到的来源ipv4地址，ipv6来源为0.0.0.0
+fn observed_ipv4(addr: SocketAddr) -> Ipv4Addr {
+    match addr.ip() {
+        IpAddr::V4(ipv4) => ipv4,
+        IpAddr::V6(ip) => {
+            if let Some(ipv4) = ip.to_ipv4_mapped() {
+                ipv4
+            } else {
+                Ipv4Addr::UNSPECIFIED
+            }
+        }
+    }
+}
+
+/// NAT测试的响应，回应看到的来源地址和收到请求的服务端端口，
+/// 请求的载荷短于响应时不回应，避免被伪造来源地址放大流量
+pub fn nat_test_response<B: AsRef<[u8]>>(
+    request: &NetPacket<B>,
+    addr: SocketAddr,
+    server_port: u16,
+) -> Option<NetPacket<Vec<u8>>> {
+    if request.payload().len() < control_packet::NAT_TEST_LEN {
+        return None;
+    }
+    let vec = vec![0u8; 12 + control_packet::NAT_TEST_LEN + ENCRYPTION_RESERVED];
+    let mut packet = NetPacket::new_encrypt(vec).ok()?;
+    packet.set_protocol(Protocol::Control);
+    packet.set_transport_protocol(control_packet::Protocol::NatTestResponse.into());
+    let mut nat_packet = control_packet::NatTestPacket::new(packet.payload_mut()).ok()?;
+    nat_packet.set_ipv4(observed_ipv4(addr));
+    nat_packet.set_port(addr.port());
+    nat_packet.set_server_port(server_port);
+    Some(packet)
+}
+
 impl ServerPacketHandler {
     async fn register<B: AsRef<[u8]>>(
         &self,
@@ -328,14 +467,58 @@ impl ServerPacketHandler {
         addr: SocketAddr,
         tcp_sender: &Option<Sender<Vec<u8>>>,
         server_secret: bool,
+    ) -> Result<Option<NetPacket<Vec<u8>>>> {
+        let mut timer = self.join_timing.start(&addr);
+        let rs = self
+            .register0(net_packet, addr, tcp_sender, server_secret, &mut timer)
+            .await;
+        self.join_timing.finish(timer, addr, rs.as_ref().err());
+        rs
+    }
+    async fn register0<B: AsRef<[u8]>>(
+        &self,
+        net_packet: NetPacket<B>,
+        addr: SocketAddr,
+        tcp_sender: &Option<Sender<Vec<u8>>>,
+        server_secret: bool,
+        timer: &mut JoinTimer,
     ) -> Result<Option<NetPacket<Vec<u8>>>> {
         let config = &self.config;
         let cache = &self.cache;
-        let request = RegistrationRequest::parse_from_bytes(net_packet.payload())?;
-        check_reg(&request)?;
+        if cache.ip_ban.is_banned(addr.ip()) {
+            log::debug!("来源ip已被封禁，拒绝注册 addr={}", addr);
+            return Err(Error::Other("source address banned".into()));
+        }
+        if let Some(allow_cidr) = &config.client_allow_cidr {
+            if !allow_cidr.iter().any(|cidr| cidr.contains(&addr.ip())) {
+                log::info!("来源地址不在允许的网段内，addr={}", addr);
+                return Err(Error::Other("source address not allowed".into()));
+            }
+        }
+        // 处理完成前一直占用
+        let _in_flight = match self.load_shedder.acquire() {
+            Some(in_flight) => in_flight,
+            None => {
+                // 负载高时不逐条输出info日志
+                log::debug!("处理中的注册过多，拒绝注册 addr={}", addr);
+                return Err(Error::Other("server busy, try again later".into()));
+            }
+        };
+        let mut request = RegistrationRequest::parse_from_bytes(net_packet.payload())?;
+        let truncated = check_reg(&mut request, &config.field_limits)?;
+        if !truncated.is_empty() {
+            log::info!(
+                "客户端上报的字段超长或包含控制字符，已截断 addr={},fields={:?}",
+                addr,
+                truncated
+            );
+        }
+        let meta = config.token_meta.get(&request.token);
+        let customer = meta.as_ref().map(|v| v.customer.as_str()).unwrap_or("");
         log::info!(
-            "register,{},id={:?},name={:?},version={:?},virtual_ip={},client_secret={},allow_ip_change={},is_fast={},tcp={}",
+            "register,{},customer={:?},id={:?},name={:?},version={:?},virtual_ip={},client_secret={},allow_ip_change={},is_fast={},tcp={}",
             addr,
+            customer,
             request.device_id,
             request.name,
             request.version,
@@ -345,7 +528,46 @@ impl ServerPacketHandler {
             request.is_fast,
             tcp_sender.is_some()
         );
+        if let Err(e) = check_version(&config.block_client_version, &request.version) {
+            log::info!(
+                "客户端版本被禁止，version={:?}，device_id={:?}，addr={}",
+                request.version,
+                request.device_id,
+                addr
+            );
+            cache.version_blocked.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
         let group_id = request.token.clone();
+        if let Err(e) = check_reserved_group(&config.reserved_group, &group_id) {
+            log::warn!(
+                "尝试使用保留的组网名，group_id={:?}，device_id={:?}，addr={}",
+                group_id,
+                request.device_id,
+                addr
+            );
+            return Err(e);
+        }
+        match check_name(config.name_policy.get(&group_id), &mut request.name) {
+            Ok(Some(original_name)) => log::info!(
+                "客户端名称不符合规则，改为默认名称 group_id={:?}，device_id={:?}，name={:?}，default_name={:?}",
+                group_id,
+                request.device_id,
+                original_name,
+                request.name
+            ),
+            Ok(None) => {}
+            Err(e) => {
+                log::info!(
+                    "客户端名称不符合规则，拒绝注册 group_id={:?}，device_id={:?}，name={:?}，addr={}",
+                    group_id,
+                    request.device_id,
+                    request.name,
+                    addr
+                );
+                return Err(e);
+            }
+        }
         if let Some(white_token) = &config.white_token {
             if !white_token.contains(&group_id) {
                 log::info!(
@@ -353,24 +575,36 @@ impl ServerPacketHandler {
                     white_token,
                     group_id
                 );
+                cache.ip_ban.fail(addr.ip()).await;
                 return Err(Error::TokenError);
             }
         }
-        let mut response = RegistrationResponse::new();
-        //公网地址
-        response.public_port = addr.port() as u32;
-        match addr.ip() {
-            IpAddr::V4(ipv4) => {
-                response.public_ip = ipv4.into();
-            }
-            IpAddr::V6(ipv6) => {
-                if let Some(ipv4) = ipv6.to_ipv4_mapped() {
-                    response.public_ip = ipv4.into();
-                } else {
-                    response.public_ipv6 = ipv6.octets().to_vec();
-                }
+        if let Some(auth_webhook) = &self.auth_webhook {
+            let ip = crate::util::canonical_ip(addr.ip());
+            if !auth_webhook
+                .check(&group_id, &request.device_id, &request.name, ip)
+                .await
+            {
+                log::info!(
+                    "auth webhook拒绝注册，group_id={:?}，device_id={:?}，addr={}",
+                    group_id,
+                    request.device_id,
+                    addr
+                );
+                cache.ip_ban.fail(addr.ip()).await;
+                return Err(Error::TokenError);
             }
         }
+        timer.lap(JoinPhase::Token);
+        let mut response = RegistrationResponse::new();
+        let lease_secs = grant_lease(
+            request.lease_secs,
+            config.min_lease_secs,
+            config.max_lease_secs,
+        );
+        response.lease_secs = lease_secs;
+        response.nat_test_port = config.nat_test_port.unwrap_or(0) as u32;
+        set_public_addr(&mut response, addr);
         //固定网段
         let gateway: u32 = config.gateway.into();
         let netmask: u32 = config.netmask.into();
@@ -378,79 +612,93 @@ impl ServerPacketHandler {
 
         response.virtual_netmask = netmask;
         response.virtual_gateway = gateway;
+        let subnet_overlaps = check_subnet_overlap(
+            &request.local_subnets,
+            network,
+            netmask,
+            config.subnet_overlap,
+        )?;
+        if !subnet_overlaps.is_empty() {
+            log::warn!(
+                "客户端本地网段和虚拟网段重叠，路由可能出错 device_id={:?},addr={},subnets={:?}",
+                request.device_id,
+                addr,
+                subnet_overlaps
+            );
+        }
 
         let v = cache
-            .virtual_network
-            .optionally_get_with(group_id.clone(), || {
-                (
-                    Duration::from_secs(7 * 24 * 3600),
-                    Arc::new(parking_lot::const_rwlock(NetworkInfo::new(
-                        network, netmask, gateway,
-                    ))),
-                )
+            .get_or_insert_network(group_id.clone(), || {
+                let mut info = NetworkInfo::new(network, netmask, gateway);
+                info.allow_broadcast = !config.no_broadcast_group.contains(&group_id);
+                info
             })
             .await;
-        let mut virtual_ip = request.virtual_ip;
         // 可分配的ip段
         let ip_range = network + 1..gateway | (!netmask);
         let timestamp = Local::now().timestamp();
-        {
+        let (old_session, reclaimed, virtual_ip, queued) = {
+            let lock_start = Instant::now();
             let mut lock = v.write();
-            let mut insert = true;
-            if virtual_ip != 0 {
-                if u32::from(config.gateway) == virtual_ip
-                    || u32::from(config.broadcast) == virtual_ip
-                    || !ip_range.contains(&virtual_ip)
-                {
-                    log::warn!("手动指定的ip无效: {:?}", request);
-                    return Err(Error::InvalidIp);
-                }
-                //指定了ip
-                if let Some(info) = lock.clients.get_mut(&request.virtual_ip) {
-                    if info.device_id != request.device_id {
-                        //ip被占用了,并且不能更改ip
-                        if !request.allow_ip_change {
-                            log::warn!("手动指定的ip已经存在:{:?}", request);
-                            return Err(Error::IpAlreadyExists);
-                        }
-                        // 重新挑选ip
-                        virtual_ip = 0;
-                    } else {
-                        insert = false;
-                    }
-                }
-            }
-            let mut old_ip = 0;
-            if insert {
-                // 找到上一次用的ip
-                for (ip, x) in &lock.clients {
-                    if x.device_id == request.device_id {
-                        if virtual_ip == 0 {
-                            virtual_ip = *ip;
-                        } else {
-                            old_ip = *ip;
-                        }
-                        break;
-                    }
-                }
-            }
-
-            if virtual_ip == 0 {
-                // 从小到大找一个未使用的ip
-                for ip in ip_range {
-                    if ip == lock.gateway_ip {
-                        continue;
-                    }
-                    if !lock.clients.contains_key(&ip) {
-                        virtual_ip = ip;
-                        break;
-                    }
+            timer.record(JoinPhase::LockWait, lock_start.elapsed());
+            let alloc_start = Instant::now();
+            let before = lock.clients.len();
+            check_join_paused(&lock, &request.device_id)?;
+            let old_session = check_duplicate(
+                &lock.clients,
+                &request.device_id,
+                addr,
+                config.duplicate_device,
+            )?;
+            let (virtual_ip, old_ip, reclaim) = match allocate_ip(
+                config,
+                &lock,
+                ip_range,
+                &request.device_id,
+                request.virtual_ip,
+                request.allow_ip_change,
+            ) {
+                Ok(rs) => rs,
+                Err(e) => {
+                    log::warn!("分配ip失败 {:?}: {:?}", e, request);
+                    return Err(e);
                 }
+            };
+            timer.record(JoinPhase::Alloc, alloc_start.elapsed());
+            // 回收的地址直接给新客户端，总数不变；此时要回收的客户端还在组网中，它是掉线的
+            let new_client =
+                old_ip == 0 && reclaim.is_none() && !lock.clients.contains_key(&virtual_ip);
+            let was_online = lock
+                .clients
+                .get(if old_ip == 0 { &virtual_ip } else { &old_ip })
+                .map(|v| v.online)
+                .unwrap_or(false);
+            let count = match config.count_offline {
+                CountOffline::Yes => cache.client_count.load(Ordering::Relaxed),
+                CountOffline::No => cache.online_count.load(Ordering::Relaxed),
+            };
+            // 接管回收地址的新设备也算新客户端
+            let new_device = !lock
+                .clients
+                .values()
+                .any(|v| v.device_id == request.device_id);
+            if let Err(e) =
+                check_pressure(cache.resource_pressure.load(Ordering::Relaxed), new_device)
+            {
+                cache.registration_shed.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
             }
-            if virtual_ip == 0 {
-                log::error!("地址使用完:{:?}", request);
-                return Err(Error::AddressExhausted);
-            }
+            // 同一token的注册都持有该组网的写锁，检查和增加之间不会有并发
+            let admission = Admission {
+                count_offline: config.count_offline,
+                count,
+                max_total_clients: config.max_total_clients,
+                quota: meta.as_ref().and_then(|v| v.quota),
+                token_online: cache.token_online.get(&group_id),
+                max_online: meta.as_ref().and_then(|v| v.max_online),
+            };
+            let reclaimed = admit(&mut lock, &admission, new_client, was_online, reclaim)?;
+            lock.meta = meta.clone();
             let info = if old_ip == 0 {
                 lock.clients
                     .entry(virtual_ip)
@@ -462,24 +710,95 @@ impl ServerPacketHandler {
                     .or_insert_with(|| client_info)
             };
             info.name = request.name;
-            info.device_id = request.device_id;
+            info.device_id = request.device_id.clone();
             info.version = request.version;
             info.client_secret = request.client_secret;
             info.server_secret = server_secret;
             info.address = addr;
             info.online = true;
+            if !was_online {
+                cache.online_count.fetch_add(1, Ordering::Relaxed);
+                cache.token_online.inc(&group_id);
+            }
             info.virtual_ip = virtual_ip;
             info.tcp_sender = tcp_sender.clone();
             info.last_join_time = Local::now();
             info.timestamp = timestamp;
+            info.link_quality = Default::default();
+            info.broadcast_limiter = config
+                .broadcast_rate
+                .map(|max| RateLimiter::new(max, Duration::from_secs(1)));
+            info.subnet_overlaps = subnet_overlaps;
+            // 重新注册后链路可能变化，等待客户端重新上报
+            info.effective_mtu = None;
+            // 每次注册重新协商压缩
+            info.compress = request.compress;
+            info.compress_stats = Default::default();
+            response.compress = request.compress;
+            info.role = if config.gateway_client.get(&group_id) == Some(&info.device_id) {
+                ClientRole::Gateway
+            } else {
+                ClientRole::Normal
+            };
+            let is_gateway = info.role == ClientRole::Gateway;
+            let queued = take_offline_queue(
+                info,
+                config.offline_grace,
+                Instant::now(),
+                &cache.offline_stats,
+            );
+            if is_gateway {
+                lock.gateway_client = Some(virtual_ip);
+            }
             lock.epoch += 1;
+            cache.clients_changed(before, lock.clients.len());
             response.virtual_ip = virtual_ip;
             response.epoch = lock.epoch as u32;
             response.device_info_list = Self::clients_info(&lock.clients, virtual_ip);
+            if let Some((reclaimed_ip, _)) = reclaimed {
+                cache
+                    .membership
+                    .event(&group_id, "reclaim", reclaimed_ip, &lock);
+            }
+            cache.membership.event(&group_id, "join", virtual_ip, &lock);
             drop(lock);
+            (old_session, reclaimed, virtual_ip, queued)
+        };
+        if let Some((reclaimed_ip, reclaimed_addr)) = reclaimed {
+            log::info!(
+                "地址用完，回收掉线最久的客户端 group={},ip={},addr={}",
+                group_id,
+                Ipv4Addr::from(reclaimed_ip),
+                reclaimed_addr
+            );
+            cache.ip_session.remove(&(group_id.clone(), reclaimed_ip));
+            if reclaimed_addr != addr {
+                cache.addr_session.remove(&reclaimed_addr);
+                cache.cipher_session.remove(&reclaimed_addr);
+                cache.context_cache.invalidate(&reclaimed_addr);
+            }
+        }
+        if let Some((old_addr, old_tcp_sender)) = old_session {
+            // 顶替旧连接，旧地址不再拥有上下文
+            log::info!(
+                "设备重复注册，顶替旧连接 device_id={},old_addr={},addr={}",
+                request.device_id,
+                old_addr,
+                addr
+            );
+            cache.addr_session.remove(&old_addr);
+            cache.cipher_session.remove(&old_addr);
+            cache.context_cache.invalidate(&old_addr);
+            if let Some(old_tcp_sender) = old_tcp_sender {
+                let _ = old_tcp_sender.try_send(Vec::new());
+            }
         }
         cache
-            .insert_ip_session((group_id.clone(), virtual_ip), addr)
+            .insert_ip_session(
+                (group_id.clone(), virtual_ip),
+                addr,
+                Duration::from_secs(lease_secs as u64),
+            )
             .await;
         cache
             .insert_addr_session(addr, (group_id, virtual_ip, timestamp))
@@ -490,23 +809,466 @@ impl ServerPacketHandler {
         packet.set_protocol(Protocol::Service);
         packet.set_transport_protocol(service_packet::Protocol::RegistrationResponse.into());
         packet.set_payload(&bytes)?;
+        if !queued.is_empty() {
+            let udp = self.udp.clone();
+            let tcp_sender = tcp_sender.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(OFFLINE_FLUSH_DELAY).await;
+                for buf in queued {
+                    if let Some(tcp_sender) = &tcp_sender {
+                        let _ = tcp_sender.try_send(buf);
+                    } else {
+                        udp.send_to(&buf, addr);
+                    }
+                }
+            });
+        }
         Ok(Some(packet))
     }
 }
 
-fn check_reg(request: &RegistrationRequest) -> Result<()> {
+/// 注册是否需要占用一个名额，yes时掉线客户端仍占着名额，只有新增的客户端需要；
+/// no时只有在线客户端占用名额，掉线的客户端重新上线也需要
+fn takes_slot(policy: CountOffline, new_client: bool, was_online: bool) -> bool {
+    match policy {
+        CountOffline::Yes => new_client,
+        CountOffline::No => !was_online,
+    }
+}
+
+/// 组网暂停加入时拒绝新设备，已注册的设备可以重新注册
+fn check_join_paused(network_info: &NetworkInfo, device_id: &str) -> Result<()> {
+    if network_info.join_paused
+        && !network_info
+            .clients
+            .values()
+            .any(|v| v.device_id == device_id)
+    {
+        return Err(Error::Other("group paused".into()));
+    }
+    Ok(())
+}
+
+/// 资源紧张时拒绝新客户端，已有记录的客户端重新注册不受影响，告知客户端在下一次采样后重试
+fn check_pressure(pressured: bool, new_device: bool) -> Result<()> {
+    if pressured && new_device {
+        log::debug!("资源紧张，拒绝新客户端注册");
+        return Err(Error::Other(format!(
+            "server under resource pressure, retry after {}s",
+            pressure::SAMPLE_INTERVAL.as_secs()
+        )));
+    }
+    Ok(())
+}
+
+/// 注册的准入条件
+struct Admission {
+    count_offline: CountOffline,
+    // 计入--max-total-clients的客户端数
+    count: usize,
+    max_total_clients: Option<usize>,
+    quota: Option<usize>,
+    // token当前的在线客户端数
+    token_online: usize,
+    max_online: Option<usize>,
+}
+
+/// 准入检查全部通过后才回收地址用完时挑选的掉线客户端，被拒绝的注册不会影响组网中的其他客户端
+fn admit(
+    network_info: &mut NetworkInfo,
+    admission: &Admission,
+    new_client: bool,
+    was_online: bool,
+    reclaim: Option<u32>,
+) -> Result<Option<(u32, SocketAddr)>> {
+    check_capacity(
+        admission.count,
+        admission.max_total_clients,
+        takes_slot(admission.count_offline, new_client, was_online),
+    )?;
+    check_quota(network_info.clients.len(), admission.quota, new_client)?;
+    check_token_online(admission.token_online, admission.max_online, !was_online)?;
+    Ok(reclaim.and_then(|ip| reclaim_ip(network_info, ip)))
+}
+
+/// 客户端总数达到上限时拒绝新客户端，已有客户端重新注册不受影响
+fn check_capacity(count: usize, max: Option<usize>, takes_slot: bool) -> Result<()> {
+    if let Some(max) = max {
+        if takes_slot && count >= max {
+            log::warn!("客户端总数达到上限，拒绝注册 count={},max={}", count, max);
+            return Err(Error::Other("server full".into()));
+        }
+    }
+    Ok(())
+}
+
+/// 客户端本地网段中和虚拟网段重叠的部分，--subnet-overlap reject时拒绝注册并告知重叠的网段
+fn check_subnet_overlap(
+    subnets: &[LocalSubnet],
+    network: u32,
+    netmask: u32,
+    policy: SubnetOverlap,
+) -> Result<Vec<Cidr>> {
+    let overlaps: Vec<Cidr> = subnets
+        .iter()
+        // 掩码为0是默认路由，不算本地网段
+        .filter(|v| v.netmask != 0)
+        .filter(|v| {
+            let mask = v.netmask & netmask;
+            v.network & mask == network & mask
+        })
+        .map(|v| {
+            Cidr::new(
+                Ipv4Addr::from(v.network & v.netmask).into(),
+                v.netmask.leading_ones() as u8,
+            )
+        })
+        .collect();
+    if !overlaps.is_empty() && policy == SubnetOverlap::Reject {
+        let list: Vec<String> = overlaps.iter().map(|v| v.to_string()).collect();
+        return Err(Error::Other(format!(
+            "local subnet {} overlaps virtual network {}",
+            list.join(","),
+            Cidr::new(Ipv4Addr::from(network).into(), netmask.leading_ones() as u8)
+        )));
+    }
+    Ok(overlaps)
+}
+
+/// 客户端上报的有效MTU，0表示没有测量，超出范围的忽略
+fn effective_mtu(mtu: u32) -> Option<u32> {
+    if MTU_RANGE.contains(&mtu) {
+        Some(mtu)
+    } else {
+        None
+    }
+}
+
+/// 组网的客户端数达到--token-meta配置的quota时拒绝新客户端，已有客户端重新注册不受影响
+fn check_quota(count: usize, quota: Option<usize>, new_client: bool) -> Result<()> {
+    if let Some(quota) = quota {
+        if new_client && count >= quota {
+            log::warn!(
+                "组网客户端数达到quota，拒绝注册 count={},quota={}",
+                count,
+                quota
+            );
+            return Err(Error::Other("group full".into()));
+        }
+    }
+    Ok(())
+}
+
+/// token的在线客户端数达到--token-meta配置的max_online时拒绝上线，已经在线的客户端重新注册不受影响
+fn check_token_online(online: usize, max_online: Option<usize>, goes_online: bool) -> Result<()> {
+    if let Some(max_online) = max_online {
+        if goes_online && online >= max_online {
+            log::warn!(
+                "token在线客户端数达到上限，拒绝注册 online={},max_online={}",
+                online,
+                max_online
+            );
+            return Err(Error::Other("token online limit reached".into()));
+        }
+    }
+    Ok(())
+}
+
+type OldSession = (SocketAddr, Option<Sender<Vec<u8>>>);
+
+/// 同一设备在线时从不同地址重新注册，顶替模式返回需要关闭的旧连接，拒绝模式返回错误
+fn check_duplicate(
+    clients: &HashMap<u32, ClientInfo>,
+    device_id: &str,
+    addr: SocketAddr,
+    policy: DuplicateDevice,
+) -> Result<Option<OldSession>> {
+    let old = clients
+        .values()
+        .find(|info| info.online && info.device_id == device_id && info.address != addr);
+    match old {
+        None => Ok(None),
+        Some(info) => match policy {
+            DuplicateDevice::Takeover => Ok(Some((info.address, info.tcp_sender.clone()))),
+            DuplicateDevice::Reject => {
+                log::warn!(
+                    "设备已在线，拒绝重复注册 device_id={},online_addr={},addr={}",
+                    device_id,
+                    info.address,
+                    addr
+                );
+                Err(Error::Other("device already online".into()))
+            }
+        },
+    }
+}
+
+/// 选择注册使用的ip，返回(分配的ip,需要迁移的旧ip)，旧ip为0表示不需要迁移
+pub fn choose_ip(
+    config: &ConfigInfo,
+    network_info: &NetworkInfo,
+    ip_range: Range<u32>,
+    device_id: &str,
+    mut virtual_ip: u32,
+    allow_ip_change: bool,
+) -> Result<(u32, u32)> {
+    let mut insert = true;
+    if virtual_ip != 0 {
+        if u32::from(config.gateway) == virtual_ip
+            || u32::from(config.broadcast) == virtual_ip
+            || !ip_range.contains(&virtual_ip)
+        {
+            // 手动指定的ip无效
+            return Err(Error::InvalidIp);
+        }
+        //指定了ip
+        if let Some(info) = network_info.clients.get(&virtual_ip) {
+            if info.device_id != device_id {
+                //ip被占用了,并且不能更改ip
+                if !allow_ip_change {
+                    return Err(Error::IpAlreadyExists);
+                }
+                // 重新挑选ip
+                virtual_ip = 0;
+            } else {
+                insert = false;
+            }
+        }
+    }
+    let mut old_ip = 0;
+    if insert {
+        // 找到上一次用的ip
+        for (ip, x) in &network_info.clients {
+            if x.device_id == device_id {
+                if virtual_ip == 0 {
+                    virtual_ip = *ip;
+                } else {
+                    old_ip = *ip;
+                }
+                break;
+            }
+        }
+    }
+    if virtual_ip == 0 {
+        // 找一个未使用的ip
+        virtual_ip = alloc_ip(ip_range, network_info, config.alloc_strategy);
+    }
+    if virtual_ip == 0 {
+        // 地址使用完
+        return Err(Error::AddressExhausted);
+    }
+    Ok((virtual_ip, old_ip))
+}
+
+/// 掉线客户端不占用名额时，它们的ip也可以回收给新客户端
+fn reclaim_policy(on_exhaustion: OnExhaustion, count_offline: CountOffline) -> OnExhaustion {
+    match count_offline {
+        CountOffline::Yes => on_exhaustion,
+        CountOffline::No => OnExhaustion::ReclaimOldest,
+    }
+}
+
+/// 在choose_ip的基础上，地址用完时按配置挑选要回收的客户端，返回(分配的ip,需要迁移的旧ip,要回收的ip)，
+/// 注册和web后台的预览共用，这里不修改组网，回收由调用方通过reclaim_ip完成
+pub fn allocate_ip(
+    config: &ConfigInfo,
+    network_info: &NetworkInfo,
+    ip_range: Range<u32>,
+    device_id: &str,
+    virtual_ip: u32,
+    allow_ip_change: bool,
+) -> Result<(u32, u32, Option<u32>)> {
+    match choose_ip(
+        config,
+        network_info,
+        ip_range,
+        device_id,
+        virtual_ip,
+        allow_ip_change,
+    ) {
+        Err(Error::AddressExhausted) => {
+            let policy = reclaim_policy(config.on_exhaustion, config.count_offline);
+            // 地址用完说明设备没有旧ip，回收后空出的地址就是唯一可分配的地址
+            match reclaim_candidate(network_info, policy) {
+                Some(ip) => Ok((ip, 0, Some(ip))),
+                None => Err(Error::AddressExhausted),
+            }
+        }
+        rs => rs.map(|(virtual_ip, old_ip)| (virtual_ip, old_ip, None)),
+    }
+}
+
+/// 地址用完时按配置挑选掉线最久的客户端
+fn reclaim_candidate(network_info: &NetworkInfo, policy: OnExhaustion) -> Option<u32> {
+    if policy != OnExhaustion::ReclaimOldest {
+        return None;
+    }
+    // 只回收掉线的客户端
+    network_info
+        .clients
+        .iter()
+        .filter(|(_, info)| !info.online)
+        .min_by_key(|(ip, info)| (info.offline_time, **ip))
+        .map(|(ip, _)| *ip)
+}
+
+/// 回收客户端的ip，返回回收的(ip,来源地址)
+fn reclaim_ip(network_info: &mut NetworkInfo, ip: u32) -> Option<(u32, SocketAddr)> {
+    let info = network_info.clients.remove(&ip)?;
+    network_info.epoch += 1;
+    Some((ip, info.address))
+}
+
+/// 按分配策略找一个未使用的ip，没有可用地址时返回0
+fn alloc_ip(ip_range: Range<u32>, network_info: &NetworkInfo, strategy: AllocStrategy) -> u32 {
+    let len = ip_range.end.saturating_sub(ip_range.start) as u64;
+    if len == 0 {
+        return 0;
+    }
+    let offset = match strategy {
+        AllocStrategy::Sequential => 0,
+        AllocStrategy::Random => rand::thread_rng().gen_range(0..len),
+    };
+    // 从起点开始环绕查找，地址快用完时也最多遍历一次
+    for i in 0..len {
+        let ip = ip_range.start + ((offset + i) % len) as u32;
+        if ip == network_info.gateway_ip {
+            continue;
+        }
+        if !network_info.clients.contains_key(&ip) {
+            return ip;
+        }
+    }
+    0
+}
+
+/// 注册响应中的公网地址
+fn set_public_addr(response: &mut RegistrationResponse, addr: SocketAddr) {
+    response.public_port = addr.port() as u32;
+    match addr.ip() {
+        IpAddr::V4(ipv4) => {
+            response.public_ip = ipv4.into();
+        }
+        IpAddr::V6(ipv6) => {
+            if let Some(ipv4) = ipv6.to_ipv4_mapped() {
+                response.public_ip = ipv4.into();
+            } else {
+                response.public_ipv6 = ipv6.octets().to_vec();
+            }
+        }
+    }
+}
+
+/// 客户端未指定租期时默认一天
+const DEFAULT_LEASE_SECS: u32 = 24 * 3600;
+
+/// 客户端期望的ip租期限制在服务端配置的范围内
+pub fn grant_lease(requested: u32, min: u32, max: u32) -> u32 {
+    let requested = if requested == 0 {
+        DEFAULT_LEASE_SECS
+    } else {
+        requested
+    };
+    requested.clamp(min, max)
+}
+
+/// 校验客户端上报的字符串，在写入组网之前调用，返回被截断的字段，
+/// token决定所在的组网，不截断
+fn check_reg(request: &mut RegistrationRequest, limits: &FieldLimits) -> Result<Vec<&'static str>> {
     if request.token.is_empty() || request.token.len() > 128 {
         return Err(Error::Other("group length error".into()));
     }
-    if request.device_id.is_empty() || request.device_id.len() > 128 {
-        return Err(Error::Other("device_id length error".into()));
+    if request.token.chars().any(char::is_control) {
+        return Err(Error::Other("group invalid character".into()));
     }
-    if request.name.is_empty() || request.name.len() > 128 {
-        return Err(Error::Other("name length error".into()));
+    let mut truncated = Vec::new();
+    let fields = [
+        ("device_id", &mut request.device_id, limits.device_id, false),
+        ("name", &mut request.name, limits.name, false),
+        ("version", &mut request.version, limits.version, true),
+    ];
+    for (field, value, max_len, allow_empty) in fields {
+        if check_field(field, value, max_len, limits.oversized)? {
+            truncated.push(field);
+        }
+        if value.is_empty() && !allow_empty {
+            return Err(Error::Other(format!("{} length error", field)));
+        }
+    }
+    Ok(truncated)
+}
+
+/// 超长或包含控制字符时拒绝，或者去掉控制字符并在字符边界截断，返回是否截断
+fn check_field(
+    field: &str,
+    value: &mut String,
+    max_len: usize,
+    mode: OversizedField,
+) -> Result<bool> {
+    let control = value.chars().any(char::is_control);
+    if !control && value.len() <= max_len {
+        return Ok(false);
+    }
+    match mode {
+        OversizedField::Reject if control => {
+            Err(Error::Other(format!("{} invalid character", field)))
+        }
+        OversizedField::Reject => Err(Error::Other(format!("{} length error", field))),
+        OversizedField::Truncate => {
+            let mut rs = String::with_capacity(max_len.min(value.len()));
+            for c in value.chars().filter(|c| !c.is_control()) {
+                if rs.len() + c.len_utf8() > max_len {
+                    break;
+                }
+                rs.push(c);
+            }
+            *value = rs;
+            Ok(true)
+        }
+    }
+}
+
+/// 版本和--block-client-version中的某一项完全一致时拒绝
+fn check_version(blocklist: &[String], version: &str) -> Result<()> {
+    if blocklist.iter().any(|v| v == version) {
+        return Err(Error::Other("client version blocked".into()));
     }
     Ok(())
 }
 
+/// --reserved-group中的组网名不允许创建和加入
+fn check_reserved_group(reserved: &[NamePattern], group_id: &str) -> Result<()> {
+    if reserved.iter().any(|v| v.matches(group_id)) {
+        return Err(Error::Other("group name reserved".into()));
+    }
+    Ok(())
+}
+
+/// 组网配置了--name-policy时检查客户端名称，不符合规则时有默认名称则改为默认名称，
+/// 否则拒绝注册，改了名称时返回原来的名称
+fn check_name(policy: Option<&NamePolicy>, name: &mut String) -> Result<Option<String>> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return Ok(None),
+    };
+    if policy.pattern.matches(name) {
+        return Ok(None);
+    }
+    match &policy.default_name {
+        Some(default_name) => Ok(Some(std::mem::replace(name, default_name.clone()))),
+        None => Err(Error::Other(format!(
+            "client name does not match {}",
+            policy.pattern
+        ))),
+    }
+}
+
+/// 服务端开启--finger时，客户端没有加--finger的数据包都会被丢弃，
+/// 已发布的vnt客户端握手时都不发送finger字段，无法据此拒绝，只用于输出警告
+fn finger_undeclared(check_finger: bool, request: &message::HandshakeRequest) -> bool {
+    check_finger && !request.finger
+}
+
 impl ServerPacketHandler {
     fn handshake<B: AsRef<[u8]>>(
         &self,
@@ -515,8 +1277,17 @@ impl ServerPacketHandler {
     ) -> Result<NetPacket<Vec<u8>>> {
         let req = message::HandshakeRequest::parse_from_bytes(net_packet.payload())?;
         log::info!("handshake:{},{}", addr, req);
+        if finger_undeclared(self.config.check_finger, &req) {
+            log::warn!(
+                "已开启--finger，客户端握手时没有声明finger，客户端没有加--finger时数据包会被丢弃 addr={}",
+                addr
+            );
+        }
         let mut res = message::HandshakeResponse::new();
         res.version = env!("CARGO_PKG_VERSION").to_string();
+        if let Some(announce_addr) = self.config.announce_addr {
+            res.announce_addr = announce_addr.to_string();
+        }
         if let Some(rsp_cipher) = &self.rsa_cipher {
             res.key_finger = rsp_cipher.finger();
             if res.key_finger != req.key_finger {
@@ -541,7 +1312,10 @@ impl ServerPacketHandler {
         log::info!("secret_handshake:{}", addr);
         if let Some(rsp_cipher) = &self.rsa_cipher {
             let source = net_packet.source();
+            let start = Instant::now();
             let rsa_secret_body = rsp_cipher.decrypt(&net_packet)?;
+            let rsa = start.elapsed();
+            let start = Instant::now();
             let sync_secret =
                 message::SecretHandshakeRequest::parse_from_bytes(rsa_secret_body.data())?;
             let c = Aes256GcmCipher::new(
@@ -551,6 +1325,7 @@ impl ServerPacketHandler {
                     .map_err(|_| Error::Other("key err".into()))?,
                 Finger::new(&sync_secret.token),
             );
+            self.join_timing.handshake(addr, rsa, start.elapsed()).await;
             let rs = vec![0u8; 12 + ENCRYPTION_RESERVED];
             let mut packet = NetPacket::new_encrypt(rs)?;
             packet.set_protocol(Protocol::Service);
@@ -565,6 +1340,53 @@ impl ServerPacketHandler {
 }
 
 impl ServerPacketHandler {
+    /// 数据端口查询运行指标，未开启、超过频率或令牌错误时都不回应
+    fn metrics<B: AsRef<[u8]>>(
+        &self,
+        net_packet: NetPacket<B>,
+        addr: SocketAddr,
+    ) -> Result<Option<NetPacket<Vec<u8>>>> {
+        let token = if let Some(token) = &self.config.udp_metrics_token {
+            token
+        } else {
+            return Ok(None);
+        };
+        if !self.metrics_limiter.check() {
+            log::debug!("指标查询超过频率限制 addr={}", addr);
+            return Ok(None);
+        }
+        let request = message::MetricsRequest::parse_from_bytes(net_packet.payload())?;
+        if &request.token != token {
+            log::info!("指标查询令牌错误 addr={}", addr);
+            return Ok(None);
+        }
+        let metrics = self.cache.metrics();
+        let mut response = message::MetricsResponse::new();
+        response.version = env!("CARGO_PKG_VERSION").to_string();
+        response.group_num = metrics.group_num;
+        response.client_num = metrics.client_num;
+        response.online_num = metrics.online_num;
+        response.paused_drop = metrics.paused_drop;
+        response.udp_requeued = metrics.udp_requeued;
+        response.udp_dropped = metrics.udp_dropped;
+        response.route_forwarded = metrics.route_forwarded;
+        response.route_dropped = metrics.route_dropped;
+        response.max_clients = self.config.max_total_clients.unwrap_or(0) as u64;
+        response.offline_dropped = metrics.offline_dropped;
+        response.offline_queued = metrics.offline_queued;
+        response.offline_flushed = metrics.offline_flushed;
+        response.offline_expired = metrics.offline_expired;
+        response.offline_rejected = metrics.offline_rejected;
+        response.forward_packets = metrics.forward_packets;
+        response.forward_bytes = metrics.forward_bytes;
+        let bytes = response.write_to_bytes()?;
+        let vec = vec![0u8; 12 + bytes.len() + ENCRYPTION_RESERVED];
+        let mut packet = NetPacket::new_encrypt(vec)?;
+        packet.set_protocol(Protocol::Service);
+        packet.set_transport_protocol(service_packet::Protocol::MetricsResponse.into());
+        packet.set_payload(&bytes)?;
+        Ok(Some(packet))
+    }
     fn poll_device_list<B: AsRef<[u8]>>(
         &self,
         _net_packet: NetPacket<B>,
@@ -610,6 +1432,9 @@ impl ServerPacketHandler {
             .get_mut(&client_status_info.source)
         {
             v.client_status = Some(status_info);
+            if let Some(mtu) = effective_mtu(client_status_info.effective_mtu) {
+                v.effective_mtu = Some(mtu);
+            }
         }
     }
     fn clients_info(
@@ -625,6 +1450,7 @@ impl ServerPacketHandler {
                 dev.name = device_info.name.clone();
                 dev.device_status = if device_info.online { 0 } else { 1 };
                 dev.client_secret = device_info.client_secret;
+                dev.gateway = device_info.role == ClientRole::Gateway;
                 dev
             })
             .collect()
@@ -644,12 +1470,603 @@ impl ServerPacketHandler {
                 if let Some(sender) = &client_info.tcp_sender {
                     let _ = sender.try_send(net_packet.buffer().to_vec());
                 } else {
-                    let _ = self
-                        .udp
-                        .try_send_to(net_packet.buffer(), client_info.address);
+                    self.udp.send_to(net_packet.buffer(), client_info.address);
                 }
             }
         }
         Ok(())
     }
 }
+
+/// 没有加密会话时的握手提示，limiter为None或超过频率时返回None
+fn handshake_hint<B: AsRef<[u8]>>(
+    limiter: Option<&RateLimiter>,
+    net_packet: &NetPacket<B>,
+    gateway: Ipv4Addr,
+) -> Option<NetPacket<Vec<u8>>> {
+    if !limiter?.check() {
+        return None;
+    }
+    let mut packet = NetPacket::new_encrypt(vec![0u8; 12 + ENCRYPTION_RESERVED]).ok()?;
+    packet.set_protocol(Protocol::Error);
+    packet.set_transport_protocol(error_packet::Protocol::NoKey.into());
+    packet.set_default_version();
+    packet.set_destination(net_packet.source());
+    packet.set_source(gateway);
+    packet.first_set_ttl(MAX_TTL);
+    packet.set_gateway_flag(true);
+    Some(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10.26.0.0/29，可分配10.26.0.2~10.26.0.6
+    const NETWORK: u32 = 0x0A1A0000;
+    const GATEWAY: u32 = 0x0A1A0001;
+    const IP_RANGE: Range<u32> = NETWORK + 1..NETWORK + 7;
+
+    fn network_info(used: &[u32]) -> NetworkInfo {
+        let mut info = NetworkInfo::new(NETWORK, 0xFFFFFFF8, GATEWAY);
+        for ip in used {
+            info.clients.insert(*ip, ClientInfo::default());
+        }
+        info
+    }
+
+    fn online_client(device_id: &str, addr: &str) -> ClientInfo {
+        ClientInfo {
+            device_id: device_id.into(),
+            address: addr.parse().unwrap(),
+            online: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lease_clamp() {
+        assert_eq!(grant_lease(0, 600, 7 * 24 * 3600), DEFAULT_LEASE_SECS);
+        assert_eq!(grant_lease(3600, 600, 7 * 24 * 3600), 3600);
+        assert_eq!(grant_lease(60, 600, 7 * 24 * 3600), 600);
+        assert_eq!(grant_lease(u32::MAX, 600, 7 * 24 * 3600), 7 * 24 * 3600);
+        // 服务端范围不包含默认值时默认值也会被限制
+        assert_eq!(grant_lease(0, 600, 3600), 3600);
+        assert_eq!(grant_lease(0, 2 * 24 * 3600, 7 * 24 * 3600), 2 * 24 * 3600);
+    }
+
+    #[test]
+    fn handshake_finger_undeclared() {
+        // 模拟已发布的客户端，不发送finger字段
+        let mut req = message::HandshakeRequest::new();
+        req.version = "1.2.0".into();
+        let req =
+            message::HandshakeRequest::parse_from_bytes(&req.write_to_bytes().unwrap()).unwrap();
+        assert!(!finger_undeclared(false, &req));
+        assert!(finger_undeclared(true, &req));
+        let mut req = req;
+        req.finger = true;
+        assert!(!finger_undeclared(true, &req));
+    }
+
+    #[test]
+    fn nat_test() {
+        let addr: SocketAddr = "[::ffff:1.2.3.4]:5000".parse().unwrap();
+        let mut request = NetPacket::new(vec![0u8; 12 + control_packet::NAT_TEST_LEN]).unwrap();
+        request.set_protocol(Protocol::Control);
+        request.set_transport_protocol(control_packet::Protocol::NatTestRequest.into());
+        let response = nat_test_response(&request, addr, 29873).unwrap();
+        assert_eq!(response.buffer().len(), request.buffer().len());
+        let nat_packet = control_packet::NatTestPacket::new(response.payload()).unwrap();
+        assert_eq!(nat_packet.ipv4(), Ipv4Addr::new(1, 2, 3, 4));
+        assert_eq!(nat_packet.port(), 5000);
+        assert_eq!(nat_packet.server_port(), 29873);
+        // 请求比响应短时不回应
+        let short = NetPacket::new(vec![0u8; 12 + 4]).unwrap();
+        assert!(nat_test_response(&short, addr, 29873).is_none());
+    }
+
+    #[test]
+    fn pre_handshake_hint() {
+        let gateway = Ipv4Addr::new(10, 26, 0, 1);
+        // 握手完成前发出的加密数据包
+        let mut request = NetPacket::new_encrypt(vec![0u8; 12 + 64 + ENCRYPTION_RESERVED]).unwrap();
+        request.set_protocol(Protocol::IpTurn);
+        request.set_source(Ipv4Addr::new(10, 26, 0, 2));
+        request.set_destination(gateway);
+        request.set_gateway_flag(true);
+        request.set_encrypt_flag(true);
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        for _ in 0..2 {
+            let hint = handshake_hint(Some(&limiter), &request, gateway).unwrap();
+            assert_eq!(hint.protocol(), Protocol::Error);
+            assert_eq!(
+                error_packet::Protocol::from(hint.transport_protocol()),
+                error_packet::Protocol::NoKey
+            );
+            assert_eq!(hint.destination(), Ipv4Addr::new(10, 26, 0, 2));
+            assert_eq!(hint.source(), gateway);
+            assert!(hint.is_gateway());
+            assert!(!hint.is_encrypt());
+            // 回应不比请求大
+            assert!(hint.buffer().len() <= request.buffer().len());
+        }
+        // 超过频率或关闭时丢弃
+        assert!(handshake_hint(Some(&limiter), &request, gateway).is_none());
+        assert!(handshake_hint(None, &request, gateway).is_none());
+    }
+
+    #[test]
+    fn blocked_version() {
+        let blocklist = vec!["1.2.5".to_string(), "1.2.6".to_string()];
+        assert!(check_version(&[], "1.2.5").is_ok());
+        assert!(check_version(&blocklist, "1.2.7").is_ok());
+        // 完全一致才拒绝
+        assert!(check_version(&blocklist, "1.2.50").is_ok());
+        match check_version(&blocklist, "1.2.6") {
+            Err(Error::Other(msg)) => assert_eq!(msg, "client version blocked"),
+            _ => panic!("blocked version accepted"),
+        }
+    }
+
+    fn reg_request(device_id: &str, name: &str, version: &str) -> RegistrationRequest {
+        let mut request = RegistrationRequest::new();
+        request.token = "group".into();
+        request.device_id = device_id.into();
+        request.name = name.into();
+        request.version = version.into();
+        request
+    }
+
+    #[test]
+    fn reg_field_limits() {
+        let mut limits = FieldLimits {
+            device_id: 8,
+            name: 8,
+            version: 4,
+            oversized: OversizedField::Reject,
+        };
+        let mut request = reg_request("d1", "pc", "1.2");
+        assert!(check_reg(&mut request, &limits).unwrap().is_empty());
+        let long = "x".repeat(1 << 20);
+        for request in [
+            &mut reg_request(&long, "pc", "1.2"),
+            &mut reg_request("d1", "pc", "1.2.10"),
+            &mut reg_request("d1", "", "1.2"),
+        ] {
+            match check_reg(request, &limits) {
+                Err(Error::Other(msg)) => assert!(msg.ends_with("length error")),
+                _ => panic!("oversized field accepted"),
+            }
+        }
+        // 伪造日志行和终端控制序列
+        match check_reg(&mut reg_request("d1", "pc\n[ERROR]", "1.2"), &limits) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "name invalid character"),
+            _ => panic!("control character accepted"),
+        }
+        let mut request = reg_request("d1", "pc", "1.2");
+        request.token = "a\u{1b}[2J".into();
+        assert!(check_reg(&mut request, &limits).is_err());
+
+        limits.oversized = OversizedField::Truncate;
+        let mut request = reg_request(&long, "电脑名称\u{7}", "1.2.10");
+        let truncated = check_reg(&mut request, &limits).unwrap();
+        assert_eq!(truncated, vec!["device_id", "name", "version"]);
+        assert_eq!(request.device_id, "xxxxxxxx");
+        // 不在多字节字符中间截断
+        assert_eq!(request.name, "电脑");
+        assert_eq!(request.version, "1.2.");
+        // 只有控制字符，去掉后为空
+        assert!(check_reg(&mut reg_request("\u{0}\u{7}", "pc", ""), &limits).is_err());
+    }
+
+    #[test]
+    fn reserved_group() {
+        let reserved: Vec<NamePattern> = vec!["admin".parse().unwrap(), "sys*".parse().unwrap()];
+        assert!(check_reserved_group(&[], "admin").is_ok());
+        assert!(check_reserved_group(&reserved, "team").is_ok());
+        assert!(check_reserved_group(&reserved, "admin2").is_ok());
+        assert!(check_reserved_group(&reserved, "ADMIN").is_err());
+        match check_reserved_group(&reserved, "system") {
+            Err(Error::Other(msg)) => assert_eq!(msg, "group name reserved"),
+            _ => panic!("reserved group accepted"),
+        }
+    }
+
+    #[test]
+    fn client_name_policy() {
+        let mut name = "anything".to_string();
+        assert_eq!(check_name(None, &mut name).unwrap(), None);
+        let reject = NamePolicy {
+            pattern: "site-*".parse().unwrap(),
+            default_name: None,
+        };
+        let mut name = "Site-01".to_string();
+        assert_eq!(check_name(Some(&reject), &mut name).unwrap(), None);
+        assert_eq!(name, "Site-01");
+        let mut name = "laptop".to_string();
+        match check_name(Some(&reject), &mut name) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "client name does not match site-*"),
+            _ => panic!("non-conforming name accepted"),
+        }
+        assert_eq!(name, "laptop");
+        let rename = NamePolicy {
+            default_name: Some("site-unnamed".into()),
+            ..reject
+        };
+        assert_eq!(
+            check_name(Some(&rename), &mut name).unwrap().as_deref(),
+            Some("laptop")
+        );
+        assert_eq!(name, "site-unnamed");
+        let mut name = "site-02".to_string();
+        assert_eq!(check_name(Some(&rename), &mut name).unwrap(), None);
+        assert_eq!(name, "site-02");
+    }
+
+    #[test]
+    fn effective_mtu_report() {
+        assert_eq!(effective_mtu(0), None);
+        assert_eq!(effective_mtu(575), None);
+        assert_eq!(effective_mtu(1400), Some(1400));
+        assert_eq!(effective_mtu(65536), None);
+    }
+
+    #[test]
+    fn capacity_limit() {
+        let max = Some(3);
+        let mut count = 0;
+        for _ in 0..3 {
+            assert!(check_capacity(count, max, true).is_ok());
+            count += 1;
+        }
+        match check_capacity(count, max, true) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "server full"),
+            _ => panic!("registration past the cap accepted"),
+        }
+        // 已有客户端重新注册不受限制
+        assert!(check_capacity(count, max, false).is_ok());
+        assert!(check_capacity(count, None, true).is_ok());
+    }
+
+    #[test]
+    fn subnet_overlap() {
+        let subnet = |network: &str, netmask: &str| {
+            let mut v = LocalSubnet::new();
+            v.network = network.parse::<Ipv4Addr>().unwrap().into();
+            v.netmask = netmask.parse::<Ipv4Addr>().unwrap().into();
+            v
+        };
+        let subnets = vec![
+            subnet("192.168.1.0", "255.255.255.0"),
+            // 包含虚拟网段
+            subnet("10.0.0.0", "255.0.0.0"),
+            subnet("0.0.0.0", "0.0.0.0"),
+        ];
+        let overlaps =
+            check_subnet_overlap(&subnets, NETWORK, 0xFFFFFF00, SubnetOverlap::Warn).unwrap();
+        assert_eq!(overlaps, vec!["10.0.0.0/8".parse().unwrap()]);
+        match check_subnet_overlap(&subnets, NETWORK, 0xFFFFFF00, SubnetOverlap::Reject) {
+            Err(Error::Other(msg)) => {
+                assert_eq!(
+                    msg,
+                    "local subnet 10.0.0.0/8 overlaps virtual network 10.26.0.0/24"
+                )
+            }
+            _ => panic!("overlapping subnet accepted"),
+        }
+        // 虚拟网段包含本地网段
+        let subnets = vec![subnet("10.26.0.128", "255.255.255.192")];
+        assert_eq!(
+            check_subnet_overlap(&subnets, NETWORK, 0xFFFFFF00, SubnetOverlap::Warn)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            check_subnet_overlap(&[], NETWORK, 0xFFFFFF00, SubnetOverlap::Reject)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn token_online_limit() {
+        let online = crate::core::store::token_online::TokenOnline::default();
+        let max_online = Some(2);
+        for _ in 0..2 {
+            assert!(check_token_online(online.get("a"), max_online, true).is_ok());
+            online.inc("a");
+        }
+        match check_token_online(online.get("a"), max_online, true) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "token online limit reached"),
+            _ => panic!("registration past max_online accepted"),
+        }
+        // 已在线的客户端重新注册不受影响，其他token不受影响
+        assert!(check_token_online(online.get("a"), max_online, false).is_ok());
+        assert!(check_token_online(online.get("b"), max_online, true).is_ok());
+        // 掉线后空出名额
+        online.dec("a", 1);
+        assert!(check_token_online(online.get("a"), max_online, true).is_ok());
+    }
+
+    #[test]
+    fn group_quota() {
+        assert!(check_quota(1, Some(2), true).is_ok());
+        match check_quota(2, Some(2), true) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "group full"),
+            _ => panic!("registration past the quota accepted"),
+        }
+        assert!(check_quota(2, Some(2), false).is_ok());
+        assert!(check_quota(100, None, true).is_ok());
+    }
+
+    #[test]
+    fn join_paused() {
+        let mut info = NetworkInfo::new(NETWORK, 0xFFFFFF00, NETWORK + 1);
+        info.clients
+            .insert(NETWORK + 2, online_client("a", "1.1.1.1:100"));
+        assert!(check_join_paused(&info, "b").is_ok());
+        info.join_paused = true;
+        match check_join_paused(&info, "b") {
+            Err(Error::Other(msg)) => assert_eq!(msg, "group paused"),
+            _ => panic!("new device joined a paused group"),
+        }
+        assert!(check_join_paused(&info, "a").is_ok());
+    }
+
+    #[test]
+    fn count_offline_yes() {
+        // 3个客户端注册，其中1个掉线，上限为3
+        let max = Some(3);
+        let total = 3;
+        // 新客户端被拒绝，掉线的客户端仍占着名额
+        assert!(check_capacity(total, max, takes_slot(CountOffline::Yes, true, false)).is_err());
+        // 掉线的客户端重新上线不受限制
+        assert!(check_capacity(total, max, takes_slot(CountOffline::Yes, false, false)).is_ok());
+        // 地址用完时按--on-exhaustion处理，默认不回收掉线客户端的ip
+        assert_eq!(
+            reclaim_policy(OnExhaustion::Reject, CountOffline::Yes),
+            OnExhaustion::Reject
+        );
+    }
+
+    #[test]
+    fn count_offline_no() {
+        // 3个客户端注册，其中1个掉线，上限为3
+        let max = Some(3);
+        let online = 2;
+        // 只计在线客户端，新客户端可以注册
+        assert!(check_capacity(online, max, takes_slot(CountOffline::No, true, false)).is_ok());
+        // 达到上限后掉线的客户端重新上线也需要名额
+        assert!(
+            check_capacity(online + 1, max, takes_slot(CountOffline::No, false, false)).is_err()
+        );
+        // 在线客户端重新注册(顶替)不受限制
+        assert!(check_capacity(online + 1, max, takes_slot(CountOffline::No, false, true)).is_ok());
+        // 地址用完时回收掉线客户端的ip
+        assert_eq!(
+            reclaim_policy(OnExhaustion::Reject, CountOffline::No),
+            OnExhaustion::ReclaimOldest
+        );
+    }
+
+    #[test]
+    fn resource_pressure() {
+        assert!(check_pressure(false, true).is_ok());
+        // 资源紧张时只拒绝新客户端，已有记录的客户端可以重新注册
+        assert!(check_pressure(true, false).is_ok());
+        match check_pressure(true, true) {
+            Err(Error::Other(msg)) => {
+                assert_eq!(msg, "server under resource pressure, retry after 5s")
+            }
+            _ => panic!("new client joined under resource pressure"),
+        }
+    }
+
+    #[test]
+    fn duplicate_device_takeover() {
+        let mut clients = HashMap::new();
+        clients.insert(NETWORK + 2, online_client("a", "1.1.1.1:100"));
+        // 崩溃重连后旧连接还在线，新连接从另一个端口注册
+        let new_addr = "1.1.1.1:200".parse().unwrap();
+        let old = check_duplicate(&clients, "a", new_addr, DuplicateDevice::Takeover).unwrap();
+        assert_eq!(old.unwrap().0, "1.1.1.1:100".parse().unwrap());
+        // 同一地址重复注册不需要顶替
+        let same_addr = "1.1.1.1:100".parse().unwrap();
+        let old = check_duplicate(&clients, "a", same_addr, DuplicateDevice::Takeover).unwrap();
+        assert!(old.is_none());
+        // 旧连接已离线
+        clients.get_mut(&(NETWORK + 2)).unwrap().online = false;
+        let old = check_duplicate(&clients, "a", new_addr, DuplicateDevice::Takeover).unwrap();
+        assert!(old.is_none());
+    }
+
+    #[test]
+    fn duplicate_device_reject() {
+        let mut clients = HashMap::new();
+        clients.insert(NETWORK + 2, online_client("a", "1.1.1.1:100"));
+        let new_addr = "1.1.1.1:200".parse().unwrap();
+        assert!(check_duplicate(&clients, "a", new_addr, DuplicateDevice::Reject).is_err());
+        assert!(
+            check_duplicate(&clients, "b", new_addr, DuplicateDevice::Reject)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn alloc_ip_sequential() {
+        let info = network_info(&[NETWORK + 2, NETWORK + 3]);
+        assert_eq!(
+            alloc_ip(IP_RANGE, &info, AllocStrategy::Sequential),
+            NETWORK + 4
+        );
+        let info = network_info(&[NETWORK + 2, NETWORK + 3, NETWORK + 4, NETWORK + 6]);
+        assert_eq!(
+            alloc_ip(IP_RANGE, &info, AllocStrategy::Sequential),
+            NETWORK + 5
+        );
+    }
+
+    #[test]
+    fn alloc_ip_random_near_full() {
+        let info = network_info(&[NETWORK + 2, NETWORK + 3, NETWORK + 5, NETWORK + 6]);
+        for _ in 0..100 {
+            assert_eq!(
+                alloc_ip(IP_RANGE, &info, AllocStrategy::Random),
+                NETWORK + 4
+            );
+        }
+    }
+
+    #[test]
+    fn alloc_ip_random_never_gateway() {
+        let info = network_info(&[]);
+        for _ in 0..100 {
+            let ip = alloc_ip(IP_RANGE, &info, AllocStrategy::Random);
+            assert!(ip != GATEWAY && IP_RANGE.contains(&ip));
+        }
+    }
+
+    fn offline_client(addr: &str, offline_time: i64) -> ClientInfo {
+        ClientInfo {
+            address: addr.parse().unwrap(),
+            offline_time,
+            ..Default::default()
+        }
+    }
+
+    fn full_network() -> NetworkInfo {
+        let mut info = network_info(&[]);
+        info.clients
+            .insert(NETWORK + 2, online_client("a", "1.1.1.1:100"));
+        info.clients
+            .insert(NETWORK + 3, offline_client("1.1.1.1:101", 300));
+        info.clients
+            .insert(NETWORK + 4, online_client("b", "1.1.1.1:102"));
+        info.clients
+            .insert(NETWORK + 5, offline_client("1.1.1.1:103", 100));
+        info.clients
+            .insert(NETWORK + 6, online_client("c", "1.1.1.1:104"));
+        info
+    }
+
+    #[test]
+    fn exhaustion_reject() {
+        let info = full_network();
+        assert_eq!(reclaim_candidate(&info, OnExhaustion::Reject), None);
+        assert_eq!(info.clients.len(), 5);
+        assert_eq!(alloc_ip(IP_RANGE, &info, AllocStrategy::Sequential), 0);
+    }
+
+    #[test]
+    fn exhaustion_reclaim_oldest() {
+        let mut info = full_network();
+        // 掉线最久的是NETWORK + 5，挑选时不修改组网
+        assert_eq!(
+            reclaim_candidate(&info, OnExhaustion::ReclaimOldest),
+            Some(NETWORK + 5)
+        );
+        assert_eq!(info.epoch, 0);
+        assert_eq!(
+            reclaim_ip(&mut info, NETWORK + 5),
+            Some((NETWORK + 5, "1.1.1.1:103".parse().unwrap()))
+        );
+        assert_eq!(info.epoch, 1);
+        assert_eq!(
+            alloc_ip(IP_RANGE, &info, AllocStrategy::Sequential),
+            NETWORK + 5
+        );
+        assert_eq!(
+            reclaim_candidate(&info, OnExhaustion::ReclaimOldest),
+            Some(NETWORK + 3)
+        );
+        assert_eq!(
+            reclaim_ip(&mut info, NETWORK + 3),
+            Some((NETWORK + 3, "1.1.1.1:101".parse().unwrap()))
+        );
+        // 在线的客户端不会被回收
+        assert_eq!(reclaim_candidate(&info, OnExhaustion::ReclaimOldest), None);
+        assert_eq!(info.clients.len(), 3);
+        assert!(info.clients.values().all(|v| v.online));
+    }
+
+    #[test]
+    fn rejected_registration_keeps_offline_client() {
+        // 4个在线客户端和1个掉线客户端占满了地址，只计在线客户端，上限为4
+        let mut info = network_info(&[]);
+        for (i, device_id) in ["a", "b", "c", "d"].iter().enumerate() {
+            info.clients.insert(
+                NETWORK + 2 + i as u32,
+                online_client(device_id, "1.1.1.1:100"),
+            );
+        }
+        info.clients
+            .insert(NETWORK + 6, offline_client("1.1.1.1:101", 100));
+        let reclaim = reclaim_candidate(
+            &info,
+            reclaim_policy(OnExhaustion::Reject, CountOffline::No),
+        );
+        assert_eq!(reclaim, Some(NETWORK + 6));
+        let mut admission = Admission {
+            count_offline: CountOffline::No,
+            count: 4,
+            max_total_clients: Some(4),
+            quota: None,
+            token_online: 0,
+            max_online: None,
+        };
+        match admit(&mut info, &admission, false, false, reclaim) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "server full"),
+            _ => panic!("registration past the cap accepted"),
+        }
+        // 被拒绝的注册不回收掉线的客户端
+        assert_eq!(info.clients.len(), 5);
+        assert!(info.clients.contains_key(&(NETWORK + 6)));
+        assert_eq!(info.epoch, 0);
+        admission.max_total_clients = Some(5);
+        assert_eq!(
+            admit(&mut info, &admission, false, false, reclaim).unwrap(),
+            Some((NETWORK + 6, "1.1.1.1:101".parse().unwrap()))
+        );
+        assert_eq!(info.clients.len(), 4);
+    }
+
+    #[test]
+    fn token_online_rejection_keeps_offline_client() {
+        let mut info = full_network();
+        let reclaim = reclaim_candidate(&info, OnExhaustion::ReclaimOldest);
+        assert_eq!(reclaim, Some(NETWORK + 5));
+        let admission = Admission {
+            count_offline: CountOffline::Yes,
+            count: 5,
+            max_total_clients: None,
+            quota: None,
+            token_online: 3,
+            max_online: Some(3),
+        };
+        match admit(&mut info, &admission, false, false, reclaim) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "token online limit reached"),
+            _ => panic!("registration past max_online accepted"),
+        }
+        assert_eq!(info.clients.len(), 5);
+        assert!(info.clients.contains_key(&(NETWORK + 5)));
+        assert_eq!(info.epoch, 0);
+    }
+
+    #[test]
+    fn alloc_ip_exhausted() {
+        let used = [
+            NETWORK + 2,
+            NETWORK + 3,
+            NETWORK + 4,
+            NETWORK + 5,
+            NETWORK + 6,
+        ];
+        let info = network_info(&used);
+        assert_eq!(alloc_ip(IP_RANGE, &info, AllocStrategy::Sequential), 0);
+        assert_eq!(alloc_ip(IP_RANGE, &info, AllocStrategy::Random), 0);
+        assert_eq!(alloc_ip(GATEWAY..GATEWAY, &info, AllocStrategy::Random), 0);
+    }
+}
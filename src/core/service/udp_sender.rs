@@ -0,0 +1,160 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use crate::core::entity::UdpSendStats;
+
+/// 重试前的等待时间
+const RETRY_DELAY: Duration = Duration::from_millis(1);
+/// 同时等待重发的数据包上限，超过则直接丢弃
+const MAX_PENDING: usize = 1024;
+
+pub trait TrySendTo: Send + Sync + 'static {
+    fn try_send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize>;
+}
+
+impl TrySendTo for UdpSocket {
+    fn try_send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        UdpSocket::try_send_to(self, buf, target)
+    }
+}
+
+/// 转发用的udp发送，发送缓冲区暂时满时放到后台短暂重试，不阻塞转发
+pub struct UdpSender<S = UdpSocket> {
+    socket: Arc<S>,
+    retries: u8,
+    pending: Arc<AtomicUsize>,
+    stats: Arc<UdpSendStats>,
+}
+
+impl<S> Clone for UdpSender<S> {
+    fn clone(&self) -> Self {
+        Self {
+            socket: self.socket.clone(),
+            retries: self.retries,
+            pending: self.pending.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl<S: TrySendTo> UdpSender<S> {
+    pub fn new(socket: Arc<S>, retries: u8, stats: Arc<UdpSendStats>) -> Self {
+        Self {
+            socket,
+            retries,
+            pending: Arc::new(AtomicUsize::new(0)),
+            stats,
+        }
+    }
+    pub fn send_to(&self, buf: &[u8], target: SocketAddr) {
+        match self.socket.try_send_to(buf, target) {
+            Ok(_) => {}
+            Err(e) if is_transient(&e) && self.retries > 0 => {
+                if self.pending.fetch_add(1, Ordering::Relaxed) >= MAX_PENDING {
+                    self.pending.fetch_sub(1, Ordering::Relaxed);
+                    incr(&self.stats.dropped);
+                    return;
+                }
+                incr(&self.stats.requeued);
+                let sender = self.clone();
+                let buf = buf.to_vec();
+                tokio::spawn(async move {
+                    sender.retry(buf, target).await;
+                    sender.pending.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+            Err(e) => {
+                log::debug!("udp发送失败 {} {:?}", target, e);
+                incr(&self.stats.dropped);
+            }
+        }
+    }
+    async fn retry(&self, buf: Vec<u8>, target: SocketAddr) {
+        for _ in 0..self.retries {
+            tokio::time::sleep(RETRY_DELAY).await;
+            match self.socket.try_send_to(&buf, target) {
+                Ok(_) => return,
+                Err(e) if is_transient(&e) => {}
+                Err(_) => break,
+            }
+        }
+        incr(&self.stats.dropped);
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+fn incr(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    /// 前几次发送返回WouldBlock
+    struct BusySocket {
+        busy: AtomicU32,
+        sent: AtomicU32,
+    }
+
+    impl TrySendTo for BusySocket {
+        fn try_send_to(&self, buf: &[u8], _target: SocketAddr) -> io::Result<usize> {
+            if self.busy.load(Ordering::Relaxed) > 0 {
+                self.busy.fetch_sub(1, Ordering::Relaxed);
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.sent.fetch_add(1, Ordering::Relaxed);
+            Ok(buf.len())
+        }
+    }
+
+    async fn send(busy: u32, retries: u8) -> (Arc<BusySocket>, Arc<UdpSendStats>) {
+        let socket = Arc::new(BusySocket {
+            busy: AtomicU32::new(busy),
+            sent: AtomicU32::new(0),
+        });
+        let stats = Arc::new(UdpSendStats::default());
+        let sender = UdpSender::new(socket.clone(), retries, stats.clone());
+        sender.send_to(&[1, 2, 3], "127.0.0.1:1".parse().unwrap());
+        while sender.pending.load(Ordering::Relaxed) > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        (socket, stats)
+    }
+
+    #[tokio::test]
+    async fn would_block_retry_ok() {
+        let (socket, stats) = send(2, 2).await;
+        assert_eq!(socket.sent.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.requeued.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn would_block_retry_exhausted() {
+        let (socket, stats) = send(3, 2).await;
+        assert_eq!(socket.sent.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.requeued.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn would_block_no_retry() {
+        let (socket, stats) = send(1, 0).await;
+        assert_eq!(socket.sent.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.requeued.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+    }
+}
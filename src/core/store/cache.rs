@@ -1,23 +1,125 @@
+use chrono::Local;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use parking_lot::RwLock;
 
 use crate::cipher::Aes256GcmCipher;
-use crate::core::entity::NetworkInfo;
+use crate::core::entity::{
+    AuthSession, ClientRole, ForwardStats, JoinHistogram, Metrics, NetworkInfo, OfflinePacketStats,
+    UdpSendStats,
+};
+use crate::core::service::event_socket::EventSocket;
+use crate::core::store::context_cache::ContextCache;
+use crate::core::store::debounce::Debouncer;
 use crate::core::store::expire_map::ExpireMap;
+use crate::core::store::group_route::GroupRoutes;
+use crate::core::store::ip_ban::IpBan;
+use crate::core::store::token_online::TokenOnline;
+use crate::ConfigInfo;
 
+/// AppCache中各类会话的过期时间
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    // 多久没有收到消息判定为掉线
+    pub heartbeat_timeout: Duration,
+    // 客户端和服务端之间的加密会话，持续通信时续期
+    pub cipher_session_ttl: Duration,
+    // 组网多久未使用则回收
+    pub group_idle: Duration,
+    // 注册后的最长会话时间，超过后需要重新握手和注册
+    pub max_session_age: Option<Duration>,
+    // 转发路径缓存连接上下文的刷新间隔，None表示不缓存
+    pub context_refresh: Option<Duration>,
+    // 最后一个客户端被移除后多久回收组网，None表示按group_idle回收
+    pub empty_group_evict: Option<Duration>,
+    // 各会话map的分片数
+    pub shards: usize,
+    // 认证失败多少次后封禁来源ip，None表示不封禁
+    pub ban_threshold: Option<u32>,
+    // 来源ip的封禁时长
+    pub ban_duration: Duration,
+    // web后台登录会话
+    #[cfg(feature = "web")]
+    pub auth_session_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_timeout: Duration::from_secs(20),
+            cipher_session_ttl: Duration::from_secs(120),
+            group_idle: Duration::from_secs(7 * 24 * 3600),
+            max_session_age: None,
+            context_refresh: None,
+            empty_group_evict: None,
+            shards: 1,
+            ban_threshold: None,
+            ban_duration: Duration::from_secs(600),
+            #[cfg(feature = "web")]
+            auth_session_ttl: Duration::from_secs(24 * 3600),
+        }
+    }
+}
+
+impl From<&ConfigInfo> for CacheConfig {
+    fn from(config: &ConfigInfo) -> Self {
+        Self {
+            cipher_session_ttl: config.cipher_session_ttl,
+            max_session_age: config.max_session_age,
+            context_refresh: config.context_refresh,
+            empty_group_evict: config.empty_group_evict,
+            shards: config.cache_shards,
+            ban_threshold: config.ban_threshold,
+            ban_duration: config.ban_duration,
+            ..Default::default()
+        }
+    }
+}
+
+/// 时间模型：各ExpireMap的过期时间使用单调时钟，只受挂起恢复造成的跳跃影响(见ExpireMap)；
+/// addr_session中的i64是注册时的系统时间(秒)，用于识别同一次注册和判断最长会话时间，
+/// 管理后台显示的时间也是系统时间，系统时间调整只影响显示和最长会话时间的判断
 #[derive(Clone)]
 pub struct AppCache {
     // group -> NetworkInfo
     pub virtual_network: ExpireMap<String, Arc<RwLock<NetworkInfo>>>,
     // (group,ip) -> addr
     pub ip_session: ExpireMap<(String, u32), SocketAddr>,
-    // addr -> (group，ip)
+    // addr -> (group，ip，注册时间)
     pub addr_session: ExpireMap<SocketAddr, (String, u32, i64)>,
     pub cipher_session: ExpireMap<SocketAddr, Arc<Aes256GcmCipher>>,
-    pub auth_map: ExpireMap<String, ()>,
+    pub auth_map: ExpireMap<String, Arc<AuthSession>>,
+    pub udp_send_stats: Arc<UdpSendStats>,
+    pub forward_stats: Arc<ForwardStats>,
+    pub offline_stats: Arc<OfflinePacketStats>,
+    // 版本被禁止而拒绝的注册数
+    pub version_blocked: Arc<AtomicU64>,
+    // 负载过高或资源紧张而拒绝的注册数
+    pub registration_shed: Arc<AtomicU64>,
+    // 进程内存或系统负载超过阈值，为true时拒绝新客户端注册
+    pub resource_pressure: Arc<AtomicBool>,
+    // 源虚拟ip伪造而丢弃的数据包数
+    pub spoof_dropped: Arc<AtomicU64>,
+    // 注册各阶段的耗时
+    pub join_histogram: Arc<JoinHistogram>,
+    // 所有组网的客户端总数
+    pub client_count: Arc<AtomicUsize>,
+    // 所有组网的在线客户端数
+    pub online_count: Arc<AtomicUsize>,
+    // 各token的在线客户端数
+    pub token_online: TokenOnline,
+    // 转发路径缓存的连接上下文
+    pub context_cache: ContextCache,
+    pub membership: MembershipLog,
+    // --event-socket输出的客户端事件，没有配置时不输出
+    pub events: EventSocket,
+    pub group_routes: GroupRoutes,
+    // 认证失败过多而临时封禁的来源ip
+    pub ip_ban: IpBan,
+    ttl: CacheConfig,
 }
 
 pub struct Context {
@@ -26,15 +128,53 @@ pub struct Context {
     pub virtual_ip: u32,
 }
 
+/// 移动客户端失败的原因
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    // 组网或在线客户端不存在
+    NotFound(&'static str),
+    // 目标组网中同一设备在线，或者没有可用的地址
+    Conflict(&'static str),
+}
+
 impl AppCache {
-    pub fn new() -> Self {
-        // 网段7天未使用则回收
+    pub fn new(
+        membership_log: bool,
+        membership_coalesce: Duration,
+        events: EventSocket,
+        ttl: CacheConfig,
+    ) -> Self {
+        let client_count = Arc::new(AtomicUsize::new(0));
+        let online_count = Arc::new(AtomicUsize::new(0));
+        let token_online = TokenOnline::default();
+        let client_count_ = client_count.clone();
+        let online_count_ = online_count.clone();
+        let token_online_ = token_online.clone();
+        // 网段长时间未使用则回收
         let virtual_network: ExpireMap<String, Arc<RwLock<NetworkInfo>>> =
-            ExpireMap::new(|_k, _v| {});
+            ExpireMap::with_shards(ttl.shards, move |k: String, v: Arc<RwLock<NetworkInfo>>| {
+                let guard = v.read();
+                let online = guard.clients.values().filter(|v| v.online).count();
+                client_count_.fetch_sub(guard.clients.len(), Ordering::Relaxed);
+                online_count_.fetch_sub(online, Ordering::Relaxed);
+                token_online_.dec(&k, online);
+            });
+        let membership = MembershipLog::new(
+            membership_log,
+            membership_coalesce,
+            events.clone(),
+            virtual_network.clone(),
+        );
+        let membership_ = membership.clone();
         let virtual_network_ = virtual_network.clone();
+        let client_count_ = client_count.clone();
+        let online_count_ = online_count.clone();
+        let token_online_ = token_online.clone();
+        let empty_group_evict = ttl.empty_group_evict;
         // ip一天未使用则回收
         let ip_session: ExpireMap<(String, u32), SocketAddr> =
-            ExpireMap::new(move |(group_id, ip), addr: SocketAddr| {
+            ExpireMap::with_shards(ttl.shards, move |(group_id, ip), addr: SocketAddr| {
                 log::info!(
                     "ip_session eviction group_id={},ip={},addr={}",
                     group_id,
@@ -42,19 +182,37 @@ impl AppCache {
                     addr
                 );
                 if let Some(v) = virtual_network_.get(&group_id) {
-                    let mut lock = v.write();
-                    if let Some(dev) = lock.clients.get(&ip) {
-                        if dev.address == addr {
-                            lock.clients.remove(&ip);
-                            lock.epoch += 1;
+                    if let Some(online) = ip_session_eviction(&v, ip, addr) {
+                        client_count_.fetch_sub(1, Ordering::Relaxed);
+                        if online {
+                            online_count_.fetch_sub(1, Ordering::Relaxed);
+                            token_online_.dec(&group_id, 1);
+                        }
+                        membership_.event(&group_id, "remove", ip, &v.read());
+                        if let Some(delay) = empty_group_evict {
+                            if v.read().clients.is_empty() {
+                                log::info!(
+                                    "组网已没有客户端，{:?}后回收 group_id={}",
+                                    delay,
+                                    group_id
+                                );
+                                virtual_network_.expire_after(&group_id, delay);
+                            }
                         }
                     }
                 }
             });
         let virtual_network_ = virtual_network.clone();
-        // 20秒钟没有收到消息则判定为掉线
-        let addr_session = ExpireMap::new(
+        let membership_ = membership.clone();
+        let online_count_ = online_count.clone();
+        let token_online_ = token_online.clone();
+        let context_cache = ContextCache::new(ttl.context_refresh);
+        let context_cache_ = context_cache.clone();
+        // 一段时间没有收到消息则判定为掉线
+        let addr_session = ExpireMap::with_shards(
+            ttl.shards,
             move |addr: SocketAddr, (group, virtual_ip, timestamp)| {
+                context_cache_.invalidate(&addr);
                 log::info!(
                     "addr_session eviction group={},virtual_ip={},addr={},timestamp={}",
                     group,
@@ -64,39 +222,62 @@ impl AppCache {
                 );
 
                 if let Some(v) = virtual_network_.get(&group) {
-                    let mut lock = v.write();
-                    if let Some(item) = lock.clients.get_mut(&virtual_ip) {
-                        if item.address != addr || item.timestamp != timestamp {
-                            log::info!(
-                                "无效信息 addr_session eviction group={},virtual_ip={},addr={},timestamp={}",
-                                group,
-                                Ipv4Addr::from(virtual_ip),
-                                addr,
-                                timestamp
-                            );
-                            return;
-                        }
-                        item.online = false;
-                        lock.epoch += 1;
+                    if addr_session_eviction(&v, virtual_ip, addr, timestamp) {
+                        online_count_.fetch_sub(1, Ordering::Relaxed);
+                        token_online_.dec(&group, 1);
+                        membership_.event(&group, "leave", virtual_ip, &v.read());
+                    } else {
+                        log::info!(
+                            "无效信息 addr_session eviction group={},virtual_ip={},addr={},timestamp={}",
+                            group,
+                            Ipv4Addr::from(virtual_ip),
+                            addr,
+                            timestamp
+                        );
                     }
                 }
             },
         );
-        let cipher_session = ExpireMap::new(|_k, _v| {});
-        let auth_map = ExpireMap::new(|_k, _v| {});
+        let cipher_session = ExpireMap::with_shards(ttl.shards, |_k, _v| {});
+        let auth_map = ExpireMap::with_shards(ttl.shards, |_k, _v| {});
         Self {
             virtual_network,
             ip_session,
             addr_session,
             cipher_session,
             auth_map,
+            udp_send_stats: Default::default(),
+            forward_stats: Default::default(),
+            offline_stats: Default::default(),
+            version_blocked: Default::default(),
+            registration_shed: Default::default(),
+            resource_pressure: Default::default(),
+            spoof_dropped: Default::default(),
+            join_histogram: Default::default(),
+            client_count,
+            online_count,
+            token_online,
+            context_cache,
+            membership,
+            events,
+            group_routes: Default::default(),
+            ip_ban: IpBan::new(ttl.ban_threshold, ttl.ban_duration),
+            ttl,
         }
     }
 }
 
 impl AppCache {
     pub fn get_context(&self, addr: &SocketAddr) -> Option<Context> {
-        if let Some((group, virtual_ip, _)) = self.addr_session.get(addr) {
+        if let Some((group, virtual_ip, timestamp)) = self.addr_session.get(addr) {
+            if session_expired(
+                timestamp,
+                Local::now().timestamp(),
+                self.ttl.max_session_age,
+            ) {
+                self.expire_session(addr, &group, virtual_ip, timestamp);
+                return None;
+            }
             let k = (group, virtual_ip);
             self.ip_session.get(&k)?;
             let (group, virtual_ip) = k;
@@ -112,19 +293,682 @@ impl AppCache {
         None
     }
 
+    /// 会话超过最长时间，删除会话并标记掉线，客户端需要重新握手和注册，token会重新校验
+    fn expire_session(&self, addr: &SocketAddr, group: &str, virtual_ip: u32, timestamp: i64) {
+        // 并发的数据包只处理一次
+        if self.addr_session.remove(addr).is_none() {
+            return;
+        }
+        self.cipher_session.remove(addr);
+        self.context_cache.invalidate(addr);
+        log::info!(
+            "会话超过最长时间，需要重新认证 group={},virtual_ip={},addr={},timestamp={}",
+            group,
+            Ipv4Addr::from(virtual_ip),
+            addr,
+            timestamp
+        );
+        if let Some(v) = self.virtual_network.get_val(&group.to_string()) {
+            if addr_session_eviction(&v, virtual_ip, *addr, timestamp) {
+                self.online_count.fetch_sub(1, Ordering::Relaxed);
+                self.token_online.dec(group, 1);
+                self.membership
+                    .event(group, "expire", virtual_ip, &v.read());
+            }
+        }
+    }
+
+    /// 组网的客户端数从before变为after
+    pub fn clients_changed(&self, before: usize, after: usize) {
+        if after > before {
+            self.client_count
+                .fetch_add(after - before, Ordering::Relaxed);
+        } else {
+            self.client_count
+                .fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+    /// 当前的运行指标
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = Metrics {
+            udp_requeued: self.udp_send_stats.requeued.load(Ordering::Relaxed),
+            udp_dropped: self.udp_send_stats.dropped.load(Ordering::Relaxed),
+            forward_packets: self.forward_stats.packets.load(Ordering::Relaxed),
+            forward_bytes: self.forward_stats.bytes.load(Ordering::Relaxed),
+            offline_dropped: self.offline_stats.dropped.load(Ordering::Relaxed),
+            offline_queued: self.offline_stats.queued.load(Ordering::Relaxed),
+            offline_flushed: self.offline_stats.flushed.load(Ordering::Relaxed),
+            offline_expired: self.offline_stats.expired.load(Ordering::Relaxed),
+            offline_rejected: self.offline_stats.rejected.load(Ordering::Relaxed),
+            version_blocked: self.version_blocked.load(Ordering::Relaxed),
+            registration_shed: self.registration_shed.load(Ordering::Relaxed),
+            spoof_dropped: self.spoof_dropped.load(Ordering::Relaxed),
+            join_timing: self.join_histogram.snapshot(),
+            ..Default::default()
+        };
+        for stats in [
+            self.virtual_network.send_stats(),
+            self.ip_session.send_stats(),
+            self.addr_session.send_stats(),
+            self.cipher_session.send_stats(),
+            self.auth_map.send_stats(),
+        ] {
+            metrics.expire_send_blocked += stats.blocked.load(Ordering::Relaxed);
+            metrics.expire_send_failed += stats.failed.load(Ordering::Relaxed);
+        }
+        for (_, network_info) in self.virtual_network.key_values() {
+            let guard = network_info.read();
+            metrics.group_num += 1;
+            metrics.client_num += guard.clients.len() as u64;
+            metrics.online_num += guard.clients.values().filter(|v| v.online).count() as u64;
+            metrics.paused_drop += guard.paused_drop.load(Ordering::Relaxed);
+        }
+        for (_, meter) in self.group_routes.list() {
+            metrics.route_forwarded += meter.forwarded.load(Ordering::Relaxed);
+            metrics.route_dropped += meter.dropped.load(Ordering::Relaxed);
+        }
+        metrics
+    }
+
+    /// 组网不存在时创建
+    pub async fn get_or_insert_network<F>(&self, group: String, f: F) -> Arc<RwLock<NetworkInfo>>
+    where
+        F: FnOnce() -> NetworkInfo,
+    {
+        self.virtual_network
+            .optionally_get_with(group, || (self.ttl.group_idle, Arc::new(RwLock::new(f()))))
+            .await
+    }
     pub async fn insert_cipher_session(&self, key: SocketAddr, value: Aes256GcmCipher) {
         self.cipher_session
-            .insert(key, Arc::new(value), Duration::from_secs(120))
+            .insert(key, Arc::new(value), self.ttl.cipher_session_ttl)
             .await
     }
-    pub async fn insert_ip_session(&self, key: (String, u32), value: SocketAddr) {
-        self.ip_session
-            .insert(key, value, Duration::from_secs(24 * 3600))
+    #[cfg(feature = "web")]
+    pub async fn insert_auth_session(&self, auth: String, session: AuthSession) {
+        self.auth_map
+            .insert(auth, Arc::new(session), self.ttl.auth_session_ttl)
             .await
     }
+    pub async fn insert_ip_session(&self, key: (String, u32), value: SocketAddr, lease: Duration) {
+        self.ip_session.insert(key, value, lease).await
+    }
     pub async fn insert_addr_session(&self, key: SocketAddr, value: (String, u32, i64)) {
         self.addr_session
-            .insert(key, value, Duration::from_secs(20))
+            .insert(key, value, self.ttl.heartbeat_timeout)
             .await
     }
+    /// 把在线客户端从from组网移到已存在的to组网，客户端不需要重新连接，
+    /// alloc在持有to组网写锁时为设备分配地址，gateway_device是to组网指定的网关设备，
+    /// 目标组网中同一设备掉线的旧记录会被替换，返回新的虚拟ip和客户端地址
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub async fn move_client<F>(
+        &self,
+        from: &str,
+        virtual_ip: u32,
+        to: &str,
+        gateway_device: Option<&str>,
+        lease: Duration,
+        alloc: F,
+    ) -> Result<(u32, SocketAddr), MoveError>
+    where
+        F: FnOnce(&NetworkInfo, &str) -> Option<u32>,
+    {
+        let src = self
+            .virtual_network
+            .get_val(&from.to_string())
+            .ok_or(MoveError::NotFound("no group found"))?;
+        let dst = self
+            .virtual_network
+            .get(&to.to_string())
+            .ok_or(MoveError::NotFound("no target group found"))?;
+        if Arc::ptr_eq(&src, &dst) {
+            return Err(MoveError::Conflict("client already in target group"));
+        }
+        // 按组网名的顺序加锁，并发的反向移动不会死锁，注册和转发同时只持有一个组网的锁
+        let (new_ip, addr, timestamp, stale_ip) = {
+            let (mut src_lock, mut dst_lock) = if from < to {
+                let src_lock = src.write();
+                (src_lock, dst.write())
+            } else {
+                let dst_lock = dst.write();
+                (src.write(), dst_lock)
+            };
+            let (device_id, addr, timestamp) = src_lock
+                .clients
+                .get(&virtual_ip)
+                .filter(|v| v.online)
+                .map(|v| (v.device_id.clone(), v.address, v.timestamp))
+                .ok_or(MoveError::NotFound("client not online"))?;
+            let stale = dst_lock
+                .clients
+                .iter()
+                .find(|(_, v)| v.device_id == device_id)
+                .map(|(ip, v)| (*ip, v.online));
+            let stale = match stale {
+                Some((_, true)) => {
+                    return Err(MoveError::Conflict("device already online in target group"))
+                }
+                Some((ip, false)) => dst_lock.clients.remove(&ip).map(|v| (ip, v)),
+                None => None,
+            };
+            let new_ip = match alloc(&dst_lock, &device_id) {
+                Some(new_ip) => new_ip,
+                None => {
+                    if let Some((ip, info)) = stale {
+                        dst_lock.clients.insert(ip, info);
+                    }
+                    return Err(MoveError::Conflict("no address available in target group"));
+                }
+            };
+            let before = src_lock.clients.len() + dst_lock.clients.len() + stale.is_some() as usize;
+            let mut client = src_lock.clients.remove(&virtual_ip).unwrap();
+            if src_lock.gateway_client == Some(virtual_ip) {
+                src_lock.gateway_client = None;
+            }
+            src_lock.epoch += 1;
+            client.virtual_ip = new_ip;
+            client.role = if gateway_device == Some(device_id.as_str()) {
+                dst_lock.gateway_client = Some(new_ip);
+                ClientRole::Gateway
+            } else {
+                ClientRole::Normal
+            };
+            client.reassigned.store(true, Ordering::Relaxed);
+            dst_lock.clients.insert(new_ip, client);
+            dst_lock.epoch += 1;
+            self.clients_changed(before, src_lock.clients.len() + dst_lock.clients.len());
+            self.token_online.dec(from, 1);
+            self.token_online.inc(to);
+            self.membership.event(from, "remove", virtual_ip, &src_lock);
+            self.membership.event(to, "join", new_ip, &dst_lock);
+            (new_ip, addr, timestamp, stale.map(|(ip, _)| ip))
+        };
+        // 会话在释放锁之后更新，期间到达的数据包找不到原来的客户端，按未注册处理
+        self.ip_session.remove(&(from.to_string(), virtual_ip));
+        if let Some(ip) = stale_ip {
+            self.ip_session.remove(&(to.to_string(), ip));
+        }
+        self.insert_ip_session((to.to_string(), new_ip), addr, lease)
+            .await;
+        // 保留注册时间，最长会话时间仍然从注册时开始计算
+        self.insert_addr_session(addr, (to.to_string(), new_ip, timestamp))
+            .await;
+        self.context_cache.invalidate(&addr);
+        Ok((new_ip, addr))
+    }
+}
+
+/// 组网成员变化事件的日志，开启合并时同一组网在窗口内的多次变化只输出一条update事件，
+/// 避免大量客户端同时重连时刷屏，epoch仍然每次变化都会增加，
+/// 开启--event-socket时每次变化都输出到事件socket，不受合并影响
+#[derive(Clone)]
+pub struct MembershipLog {
+    enabled: bool,
+    debouncer: Option<Debouncer>,
+    events: EventSocket,
+}
+
+impl MembershipLog {
+    fn new(
+        enabled: bool,
+        coalesce: Duration,
+        events: EventSocket,
+        virtual_network: ExpireMap<String, Arc<RwLock<NetworkInfo>>>,
+    ) -> Self {
+        let debouncer = if enabled && !coalesce.is_zero() {
+            Some(Debouncer::new(coalesce, move |group, changes| {
+                if let Some(v) = virtual_network.get_val(&group) {
+                    let guard = v.read();
+                    log::info!(
+                        target: "membership",
+                        "group={},event=update,changes={},online={},total={},epoch={}",
+                        group,
+                        changes,
+                        guard.clients.values().filter(|v| v.online).count(),
+                        guard.clients.len(),
+                        guard.epoch
+                    );
+                }
+            }))
+        } else {
+            None
+        };
+        Self {
+            enabled,
+            debouncer,
+            events,
+        }
+    }
+    pub fn event(&self, group: &str, event: &str, virtual_ip: u32, network_info: &NetworkInfo) {
+        self.events
+            .membership(group, event, virtual_ip, network_info);
+        if !self.enabled {
+            return;
+        }
+        match &self.debouncer {
+            Some(debouncer) => debouncer.notify(group),
+            None => membership_event(group, event, virtual_ip, network_info),
+        }
+    }
+}
+
+/// 组网成员变化事件，target为membership，可以在log4rs.yaml中单独输出到文件
+pub fn membership_event(group: &str, event: &str, virtual_ip: u32, network_info: &NetworkInfo) {
+    log::info!(
+        target: "membership",
+        "group={},event={},virtual_ip={},online={},total={},epoch={}",
+        group,
+        event,
+        Ipv4Addr::from(virtual_ip),
+        network_info.clients.values().filter(|v| v.online).count(),
+        network_info.clients.len(),
+        network_info.epoch
+    );
+}
+
+/// ip_session过期，来源地址一致时才移除客户端，移除时返回客户端是否在线
+fn ip_session_eviction(
+    network_info: &RwLock<NetworkInfo>,
+    ip: u32,
+    addr: SocketAddr,
+) -> Option<bool> {
+    let mut lock = network_info.write();
+    if let Some(dev) = lock.clients.get(&ip) {
+        if dev.address == addr {
+            let online = dev.online;
+            lock.clients.remove(&ip);
+            lock.epoch += 1;
+            return Some(online);
+        }
+    }
+    None
+}
+
+/// 注册时间为timestamp(秒)的会话在now时是否超过最长会话时间，系统时间往回调整时不会过期
+fn session_expired(timestamp: i64, now: i64, max_age: Option<Duration>) -> bool {
+    match max_age {
+        Some(max_age) => now.saturating_sub(timestamp) >= max_age.as_secs() as i64,
+        None => false,
+    }
+}
+
+/// addr_session过期，地址和时间戳都一致时才判定掉线，返回是否掉线
+fn addr_session_eviction(
+    network_info: &RwLock<NetworkInfo>,
+    virtual_ip: u32,
+    addr: SocketAddr,
+    timestamp: i64,
+) -> bool {
+    let mut lock = network_info.write();
+    if let Some(item) = lock.clients.get_mut(&virtual_ip) {
+        if item.address != addr || item.timestamp != timestamp {
+            return false;
+        }
+        item.online = false;
+        item.offline_time = Local::now().timestamp();
+        lock.epoch += 1;
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::entity::ClientInfo;
+
+    const IP: u32 = 0x0A1A0002;
+
+    fn network_info(addr: SocketAddr, timestamp: i64) -> RwLock<NetworkInfo> {
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        let client = ClientInfo {
+            address: addr,
+            online: true,
+            virtual_ip: IP,
+            timestamp,
+            ..Default::default()
+        };
+        info.clients.insert(IP, client);
+        RwLock::new(info)
+    }
+
+    #[test]
+    fn addr_session_eviction_offline() {
+        let addr = "1.2.3.4:5000".parse().unwrap();
+        let info = network_info(addr, 10);
+        assert!(addr_session_eviction(&info, IP, addr, 10));
+        let guard = info.read();
+        assert!(!guard.clients[&IP].online);
+        assert_eq!(guard.epoch, 1);
+    }
+
+    #[test]
+    fn addr_session_eviction_stale() {
+        let addr = "1.2.3.4:5000".parse().unwrap();
+        let info = network_info(addr, 10);
+        // 客户端已经重新注册，旧的过期事件无效
+        assert!(!addr_session_eviction(&info, IP, addr, 9));
+        assert!(!addr_session_eviction(
+            &info,
+            IP,
+            "1.2.3.4:5001".parse().unwrap(),
+            10
+        ));
+        let guard = info.read();
+        assert!(guard.clients[&IP].online);
+        assert_eq!(guard.epoch, 0);
+    }
+
+    #[test]
+    fn session_max_age() {
+        assert!(!session_expired(0, i64::MAX, None));
+        let max_age = Some(Duration::from_secs(3600));
+        assert!(!session_expired(1000, 1000 + 3599, max_age));
+        assert!(session_expired(1000, 1000 + 3600, max_age));
+    }
+
+    #[tokio::test]
+    async fn expire_session_on_access() {
+        let ttl = CacheConfig {
+            max_session_age: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let cache = AppCache::new(false, Duration::ZERO, Default::default(), ttl);
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        let now = Local::now().timestamp();
+        for (group, timestamp) in [("fresh", now), ("old", now - 3600)] {
+            let group = group.to_string();
+            cache
+                .virtual_network
+                .insert(
+                    group.clone(),
+                    Arc::new(network_info(addr, timestamp)),
+                    Duration::from_secs(60),
+                )
+                .await;
+            cache
+                .insert_ip_session((group, IP), addr, Duration::from_secs(60))
+                .await;
+        }
+        cache.online_count.store(1, Ordering::Relaxed);
+        cache.token_online.inc("old");
+        cache
+            .insert_addr_session(addr, ("fresh".to_string(), IP, now))
+            .await;
+        assert!(cache.get_context(&addr).is_some());
+        // 会话开始时间在一小时前，访问时失效并标记掉线
+        cache
+            .insert_addr_session(addr, ("old".to_string(), IP, now - 3600))
+            .await;
+        assert!(cache.get_context(&addr).is_none());
+        assert!(cache.addr_session.get_val(&addr).is_none());
+        let info = cache.virtual_network.get_val(&"old".to_string()).unwrap();
+        assert!(!info.read().clients[&IP].online);
+        assert_eq!(cache.online_count.load(Ordering::Relaxed), 0);
+        assert_eq!(cache.token_online.get("old"), 0);
+    }
+
+    #[tokio::test]
+    async fn token_online_on_leave() {
+        let ttl = CacheConfig {
+            heartbeat_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let cache = AppCache::new(false, Duration::ZERO, Default::default(), ttl);
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        cache
+            .virtual_network
+            .insert(
+                "a".to_string(),
+                Arc::new(network_info(addr, 10)),
+                Duration::from_secs(60),
+            )
+            .await;
+        cache.online_count.store(1, Ordering::Relaxed);
+        cache.token_online.inc("a");
+        cache
+            .insert_addr_session(addr, ("a".to_string(), IP, 10))
+            .await;
+        assert_eq!(cache.token_online.get("a"), 1);
+        // 心跳超时掉线
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(cache.token_online.get("a"), 0);
+        assert_eq!(cache.online_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn evict_empty_group() {
+        for (evict, remains) in [(Some(Duration::ZERO), false), (None, true)] {
+            let ttl = CacheConfig {
+                empty_group_evict: evict,
+                ..Default::default()
+            };
+            let cache = AppCache::new(false, Duration::ZERO, Default::default(), ttl);
+            let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+            cache
+                .virtual_network
+                .insert(
+                    "a".to_string(),
+                    Arc::new(network_info(addr, 10)),
+                    Duration::from_secs(60),
+                )
+                .await;
+            cache.client_count.store(1, Ordering::Relaxed);
+            cache
+                .ip_session
+                .insert(("a".to_string(), IP), addr, Duration::from_millis(50))
+                .await;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            assert_eq!(cache.client_count.load(Ordering::Relaxed), 0);
+            assert_eq!(
+                cache.virtual_network.get_val(&"a".to_string()).is_some(),
+                remains
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_ttl() {
+        let ttl = CacheConfig {
+            heartbeat_timeout: Duration::from_millis(100),
+            cipher_session_ttl: Duration::from_millis(100),
+            group_idle: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let cache = AppCache::new(false, Duration::ZERO, Default::default(), ttl);
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        cache
+            .get_or_insert_network("a".to_string(), || {
+                NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001)
+            })
+            .await;
+        cache
+            .insert_addr_session(addr, ("a".to_string(), IP, 0))
+            .await;
+        cache
+            .insert_cipher_session(
+                addr,
+                Aes256GcmCipher::new([0; 32], crate::cipher::Finger::new("a")),
+            )
+            .await;
+        assert_eq!(cache.virtual_network.size(), 1);
+        assert_eq!(cache.addr_session.size(), 1);
+        assert_eq!(cache.cipher_session.size(), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(cache.virtual_network.size(), 0);
+        assert_eq!(cache.addr_session.size(), 0);
+        assert_eq!(cache.cipher_session.size(), 0);
+    }
+
+    #[test]
+    fn ip_session_eviction_remove() {
+        let addr = "1.2.3.4:5000".parse().unwrap();
+        let info = network_info(addr, 10);
+        assert_eq!(ip_session_eviction(&info, IP, addr), Some(true));
+        let guard = info.read();
+        assert!(!guard.clients.contains_key(&IP));
+        assert_eq!(guard.epoch, 1);
+    }
+
+    #[test]
+    fn ip_session_eviction_other_addr() {
+        let addr = "1.2.3.4:5000".parse().unwrap();
+        let info = network_info(addr, 10);
+        assert!(ip_session_eviction(&info, IP, "5.6.7.8:5000".parse().unwrap()).is_none());
+        let guard = info.read();
+        assert!(guard.clients.contains_key(&IP));
+        assert_eq!(guard.epoch, 0);
+    }
+
+    #[tokio::test]
+    async fn move_client_sessions() {
+        let cache = AppCache::new(
+            false,
+            Duration::ZERO,
+            Default::default(),
+            Default::default(),
+        );
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        let lease = Duration::from_secs(60);
+        let a = Arc::new(network_info(addr, 10));
+        a.write().clients.get_mut(&IP).unwrap().device_id = "d1".into();
+        // 目标组网中同一设备掉线的旧记录，以及占用了原ip的其他设备
+        let b = Arc::new(network_info("1.2.3.5:5000".parse().unwrap(), 10));
+        b.write().clients.get_mut(&IP).unwrap().device_id = "d2".into();
+        let stale = ClientInfo {
+            device_id: "d1".into(),
+            virtual_ip: IP + 5,
+            ..Default::default()
+        };
+        b.write().clients.insert(IP + 5, stale);
+        for (group, info) in [("a", &a), ("b", &b)] {
+            cache
+                .virtual_network
+                .insert(group.to_string(), info.clone(), lease)
+                .await;
+        }
+        cache.client_count.store(3, Ordering::Relaxed);
+        cache.token_online.inc("a");
+        cache.token_online.inc("b");
+        cache
+            .insert_ip_session(("a".to_string(), IP), addr, lease)
+            .await;
+        cache
+            .insert_ip_session(("b".to_string(), IP + 5), addr, lease)
+            .await;
+        cache
+            .insert_addr_session(addr, ("a".to_string(), IP, 10))
+            .await;
+        let alloc =
+            |info: &NetworkInfo, _: &str| (IP..IP + 10).find(|v| !info.clients.contains_key(v));
+        assert_eq!(
+            cache
+                .move_client("a", IP + 1, "b", None, lease, alloc)
+                .await,
+            Err(MoveError::NotFound("client not online"))
+        );
+        assert_eq!(
+            cache
+                .move_client("a", IP, "b", None, lease, |_, _| None)
+                .await,
+            Err(MoveError::Conflict("no address available in target group"))
+        );
+        // 失败时不改变目标组网
+        assert_eq!(b.read().clients[&(IP + 5)].device_id, "d1");
+
+        let (new_ip, moved_addr) = cache
+            .move_client("a", IP, "b", Some("d1"), lease, alloc)
+            .await
+            .unwrap();
+        assert_eq!((new_ip, moved_addr), (IP + 1, addr));
+        assert!(a.read().clients.is_empty());
+        assert_eq!(a.read().epoch, 1);
+        {
+            let guard = b.read();
+            assert_eq!(guard.clients.len(), 2);
+            let client = &guard.clients[&new_ip];
+            assert_eq!(client.device_id, "d1");
+            assert_eq!(client.virtual_ip, new_ip);
+            assert!(client.online && client.reassigned.load(Ordering::Relaxed));
+            assert_eq!(client.role, ClientRole::Gateway);
+            assert_eq!(guard.gateway_client, Some(new_ip));
+            assert_eq!(guard.epoch, 1);
+        }
+        // 会话都指向新的组网和ip
+        assert!(cache.ip_session.get_val(&("a".to_string(), IP)).is_none());
+        assert!(cache
+            .ip_session
+            .get_val(&("b".to_string(), IP + 5))
+            .is_none());
+        assert_eq!(
+            cache.ip_session.get_val(&("b".to_string(), new_ip)),
+            Some(addr)
+        );
+        assert_eq!(
+            cache.addr_session.get_val(&addr),
+            Some(("b".to_string(), new_ip, 10))
+        );
+        let context = cache.get_context(&addr).unwrap();
+        assert_eq!((context.group.as_str(), context.virtual_ip), ("b", new_ip));
+        // 替换了旧记录，在线数不变
+        assert_eq!(cache.client_count.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.token_online.get("a"), 0);
+        assert_eq!(cache.token_online.get("b"), 2);
+        // 已经在目标组网
+        assert_eq!(
+            cache
+                .move_client("b", new_ip, "b", None, lease, alloc)
+                .await,
+            Err(MoveError::Conflict("client already in target group"))
+        );
+    }
+
+    /// 比较转发路径每个数据包完整查询上下文和使用--context-cache-ms缓存的速度，单个发送方，
+    /// 运行 cargo test --release context_lookup -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn context_lookup() {
+        const OPS: u32 = 2_000_000;
+        for refresh in [None, Some(Duration::from_secs(60))] {
+            let ttl = CacheConfig {
+                context_refresh: refresh,
+                ..Default::default()
+            };
+            let cache = AppCache::new(false, Duration::ZERO, Default::default(), ttl);
+            let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+            let now = Local::now().timestamp();
+            cache
+                .virtual_network
+                .insert(
+                    "a".to_string(),
+                    Arc::new(network_info(addr, now)),
+                    Duration::from_secs(60),
+                )
+                .await;
+            cache
+                .insert_ip_session(("a".to_string(), IP), addr, Duration::from_secs(60))
+                .await;
+            cache
+                .insert_addr_session(addr, ("a".to_string(), IP, now))
+                .await;
+            let start = std::time::Instant::now();
+            for _ in 0..OPS {
+                // 和ClientPacketHandler::context相同的查询
+                if cache.context_cache.get(&addr).is_none() {
+                    let context = Arc::new(cache.get_context(&addr).unwrap());
+                    cache.cipher_session.renew(&addr);
+                    cache.context_cache.insert(addr, context);
+                }
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "context_refresh={:?},ops={},elapsed={:?},ops_per_sec={}",
+                refresh,
+                OPS,
+                elapsed,
+                (OPS as f64 / elapsed.as_secs_f64()) as u64
+            );
+        }
+    }
 }
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+
+use crate::core::store::cache::Context;
+
+// 上下文和上次完整查询的时间
+type Entry = (Arc<Context>, Instant);
+
+/// 转发路径缓存的连接上下文，避免每个数据包都查询并续期addr_session、ip_session和virtual_network，
+/// 超过刷新间隔后重新走完整的查询，会话的续期也随之降到每个间隔一次；
+/// 使用前还要确认发送方在组网中仍然在线且地址不变，会话被回收后不会继续转发
+#[derive(Clone)]
+pub struct ContextCache {
+    // 刷新间隔，None表示不缓存
+    refresh: Option<Duration>,
+    map: Arc<RwLock<HashMap<SocketAddr, Entry>>>,
+}
+
+impl ContextCache {
+    pub fn new(refresh: Option<Duration>) -> Self {
+        Self {
+            refresh,
+            map: Default::default(),
+        }
+    }
+    /// 在刷新间隔内的上下文，并确认发送方的会话仍然有效
+    pub fn get(&self, addr: &SocketAddr) -> Option<Arc<Context>> {
+        let refresh = self.refresh?;
+        let context = {
+            let guard = self.map.read();
+            let (context, time) = guard.get(addr)?;
+            if time.elapsed() >= refresh {
+                return None;
+            }
+            context.clone()
+        };
+        let valid = context
+            .network_info
+            .read()
+            .clients
+            .get(&context.virtual_ip)
+            .map(|v| v.online && v.address == *addr)
+            .unwrap_or(false);
+        if valid {
+            Some(context)
+        } else {
+            self.invalidate(addr);
+            None
+        }
+    }
+    pub fn insert(&self, addr: SocketAddr, context: Arc<Context>) {
+        if self.refresh.is_some() {
+            self.map.write().insert(addr, (context, Instant::now()));
+        }
+    }
+    pub fn invalidate(&self, addr: &SocketAddr) {
+        if self.refresh.is_some() {
+            self.map.write().remove(addr);
+        }
+    }
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.map.read().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::entity::{ClientInfo, NetworkInfo};
+
+    const IP: u32 = 0x0A1A0002;
+
+    fn context(addr: SocketAddr) -> Arc<Context> {
+        let mut info = NetworkInfo::new(0x0A1A0000, 0xFFFFFF00, 0x0A1A0001);
+        info.clients.insert(
+            IP,
+            ClientInfo {
+                address: addr,
+                online: true,
+                virtual_ip: IP,
+                ..Default::default()
+            },
+        );
+        Arc::new(Context {
+            network_info: Arc::new(RwLock::new(info)),
+            group: "a".to_string(),
+            virtual_ip: IP,
+        })
+    }
+
+    #[test]
+    fn refresh_and_evict() {
+        let addr: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+        let disabled = ContextCache::new(None);
+        disabled.insert(addr, context(addr));
+        assert!(disabled.get(&addr).is_none());
+        assert_eq!(disabled.len(), 0);
+
+        let cache = ContextCache::new(Some(Duration::from_millis(50)));
+        let context = context(addr);
+        cache.insert(addr, context.clone());
+        assert!(cache.get(&addr).is_some());
+        // 超过刷新间隔需要重新查询
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get(&addr).is_none());
+        // 会话被回收(掉线)后不再使用缓存
+        cache.insert(addr, context.clone());
+        context
+            .network_info
+            .write()
+            .clients
+            .get_mut(&IP)
+            .unwrap()
+            .online = false;
+        assert!(cache.get(&addr).is_none());
+        assert_eq!(cache.len(), 0);
+        // 地址被其他连接顶替
+        context
+            .network_info
+            .write()
+            .clients
+            .get_mut(&IP)
+            .unwrap()
+            .online = true;
+        cache.insert("1.2.3.4:5001".parse().unwrap(), context.clone());
+        assert!(cache.get(&"1.2.3.4:5001".parse().unwrap()).is_none());
+        cache.insert(addr, context);
+        cache.invalidate(&addr);
+        assert!(cache.get(&addr).is_none());
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+/// 按key合并短时间内的多次通知，窗口结束时只回调一次(key,合并的次数)
+#[derive(Clone)]
+pub struct Debouncer {
+    window: Duration,
+    pending: Arc<Mutex<HashMap<String, usize>>>,
+    call: Arc<dyn Fn(String, usize) + Send + Sync>,
+}
+
+impl Debouncer {
+    pub fn new<F>(window: Duration, call: F) -> Self
+    where
+        F: Fn(String, usize) + Send + Sync + 'static,
+    {
+        Self {
+            window,
+            pending: Default::default(),
+            call: Arc::new(call),
+        }
+    }
+    pub fn notify(&self, key: &str) {
+        {
+            let mut pending = self.pending.lock();
+            if let Some(count) = pending.get_mut(key) {
+                *count += 1;
+                return;
+            }
+            pending.insert(key.to_string(), 1);
+        }
+        // 窗口内的第一次通知负责在窗口结束时回调
+        let debouncer = self.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(debouncer.window).await;
+            let count = debouncer.pending.lock().remove(&key);
+            if let Some(count) = count {
+                (debouncer.call)(key, count);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn coalesce() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls_ = calls.clone();
+        let debouncer = Debouncer::new(Duration::from_millis(50), move |key, count| {
+            calls_.lock().push((key, count));
+        });
+        for _ in 0..100 {
+            debouncer.notify("a");
+        }
+        debouncer.notify("b");
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let mut list = calls.lock().clone();
+        list.sort();
+        assert_eq!(list, vec![("a".to_string(), 100), ("b".to_string(), 1)]);
+        // 窗口结束后的通知重新计数
+        debouncer.notify("a");
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(calls.lock().len(), 3);
+    }
+}
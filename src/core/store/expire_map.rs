@@ -1,20 +1,42 @@
 #![allow(dead_code)]
 use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
 use std::collections::{BinaryHeap, HashMap};
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crossbeam_utils::atomic::AtomicCell;
+use crossbeam_utils::CachePadded;
 use parking_lot::RwLock;
-use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
+/// 到期任务超过这个时间还没执行，说明过期任务没有在运行(虚拟机挂起恢复、进程被暂停等)，
+/// 这段时间客户端无法续期，不能当作超时处理
+const TIME_JUMP_THRESHOLD: Duration = Duration::from_secs(10);
+
+/// 带过期时间的map，到期后执行回调，如果多个元素同时到期则按加入的顺序执行
+///
+/// 过期时间使用单调时钟(Instant)，不受系统时间调整的影响；
+/// 检测到时间跳跃时，已经到期的元素额外获得一个过期周期，有机会续期，而不是立即全部过期；
+/// 可以按key的hash分成多个分片，每个分片单独加锁，减少客户端很多时的锁竞争
 #[derive(Clone)]
 pub struct ExpireMap<K, V> {
-    base: Arc<RwLock<HashMap<K, Value<V>>>>,
+    base: Arc<Shards<K, V>>,
     sender: Sender<DelayedTask<K>>,
+    // 过期任务的序号，同时到期时按加入的顺序执行回调
+    seq: Arc<AtomicU64>,
+    send_stats: Arc<SendStats>,
+}
+
+/// 投递过期任务的统计，通道满需要等待、过期任务已退出导致投递失败
+#[derive(Default)]
+pub struct SendStats {
+    pub blocked: AtomicU64,
+    pub failed: AtomicU64,
 }
 
 struct Value<V> {
@@ -23,8 +45,46 @@ struct Value<V> {
     expire: Duration,
 }
 
+type Shard<K, V> = RwLock<HashMap<K, Value<V>>>;
+
+/// 按key的hash分片的map，只有一个分片时和单个RwLock<HashMap>相同，
+/// 各分片的锁按缓存行对齐，避免不同分片的锁互相影响
+struct Shards<K, V> {
+    shards: Box<[CachePadded<Shard<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K: Hash, V> Shards<K, V> {
+    fn new(count: usize) -> Self {
+        let count = count.max(1);
+        let capacity = (128 / count).max(8);
+        Self {
+            shards: (0..count)
+                .map(|_| CachePadded::new(RwLock::new(HashMap::with_capacity(capacity))))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+    fn shard(&self, k: &K) -> &Shard<K, V> {
+        if self.shards.len() == 1 {
+            return &self.shards[0];
+        }
+        let index = self.hasher.hash_one(k) as usize % self.shards.len();
+        &self.shards[index]
+    }
+}
+
 impl<K, V> ExpireMap<K, V> {
     pub fn new<F>(call: F) -> ExpireMap<K, V>
+    where
+        F: Fn(K, V) + Send + 'static,
+        K: Eq + Hash + Clone + Sync + Send + 'static,
+        V: Clone + Sync + Send + 'static,
+    {
+        Self::with_shards(1, call)
+    }
+    /// 分成shards个分片，过期任务仍然只有一个
+    pub fn with_shards<F>(shards: usize, call: F) -> ExpireMap<K, V>
     where
         F: Fn(K, V) + Send + 'static,
         K: Eq + Hash + Clone + Sync + Send + 'static,
@@ -32,8 +92,10 @@ impl<K, V> ExpireMap<K, V> {
     {
         let (sender, receiver) = channel(100);
         let map = ExpireMap {
-            base: Arc::new(RwLock::new(HashMap::with_capacity(128))),
+            base: Arc::new(Shards::new(shards)),
             sender,
+            seq: Arc::new(AtomicU64::new(0)),
+            send_stats: Default::default(),
         };
         let map1 = map.clone();
         tokio::spawn(async move { expire_task(receiver, map1, call).await });
@@ -47,12 +109,12 @@ where
     V: Clone,
 {
     pub fn size(&self) -> usize {
-        self.base.read().len()
+        self.base.shards.iter().map(|v| v.read().len()).sum()
     }
     pub async fn insert(&self, k: K, val: V, expire: Duration) {
         let instant = Instant::now().add(expire);
         {
-            let mut write_guard = self.base.write();
+            let mut write_guard = self.base.shard(&k).write();
             let value = Value {
                 val,
                 deadline: AtomicCell::new(instant),
@@ -61,13 +123,10 @@ where
             write_guard.insert(k.clone(), value);
         }
         //投入过期监听
-        self.sender
-            .send(DelayedTask { k, time: instant })
-            .await
-            .unwrap();
+        self.schedule(k, instant).await;
     }
     pub fn get(&self, k: &K) -> Option<V> {
-        if let Some(v) = self.base.read().get(k) {
+        if let Some(v) = self.base.shard(k).read().get(k) {
             // 延长过期时间
             v.deadline.store(Instant::now().add(v.expire));
             Some(v.val.clone())
@@ -75,13 +134,113 @@ where
             None
         }
     }
+    /// 只延长过期时间，返回key是否存在
+    pub fn renew(&self, k: &K) -> bool {
+        if let Some(v) = self.base.shard(k).read().get(k) {
+            v.deadline.store(Instant::now().add(v.expire));
+            true
+        } else {
+            false
+        }
+    }
+    /// 把过期时间提前到delay之后，不改变续期的周期，期间被get/renew会恢复正常的过期时间，返回key是否存在
+    pub fn expire_after(&self, k: &K, delay: Duration) -> bool
+    where
+        K: Send + 'static,
+    {
+        let instant = Instant::now().add(delay);
+        match self.base.shard(k).read().get(k) {
+            Some(v) => v.deadline.store(instant),
+            None => return false,
+        }
+        let task = self.task(k.clone(), instant);
+        // 在过期回调中调用时不能等待，通道满时交给其他任务发送
+        match self.sender.try_send(task) {
+            Ok(()) => {}
+            Err(TrySendError::Full(task)) => {
+                self.send_stats
+                    .blocked
+                    .fetch_add(1, AtomicOrdering::Relaxed);
+                let sender = self.sender.clone();
+                let send_stats = self.send_stats.clone();
+                tokio::spawn(async move {
+                    if sender.send(task).await.is_err() {
+                        send_stats.failed.fetch_add(1, AtomicOrdering::Relaxed);
+                    }
+                });
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.send_stats.failed.fetch_add(1, AtomicOrdering::Relaxed);
+            }
+        }
+        true
+    }
+    /// 投递过期任务，不持有锁；通道满时等待并计数，过期任务已退出时只计数，
+    /// 元素留在map中，由purge_expired清理
+    async fn schedule(&self, k: K, time: Instant) {
+        let task = match self.sender.try_send(self.task(k, time)) {
+            Ok(()) => return,
+            Err(TrySendError::Full(task)) => task,
+            Err(TrySendError::Closed(_)) => {
+                self.send_failed();
+                return;
+            }
+        };
+        self.send_stats
+            .blocked
+            .fetch_add(1, AtomicOrdering::Relaxed);
+        if self.sender.send(task).await.is_err() {
+            self.send_failed();
+        }
+    }
+    fn send_failed(&self) {
+        let count = self.send_stats.failed.fetch_add(1, AtomicOrdering::Relaxed);
+        if count == 0 {
+            log::error!("过期任务已退出，新元素不会自动过期");
+        }
+    }
+    pub fn send_stats(&self) -> &SendStats {
+        &self.send_stats
+    }
+    /// 移除now时已经到期的元素，不执行过期回调，返回移除的数量，
+    /// 用于过期任务没有收到的元素
+    pub fn purge_expired(&self, now: Instant) -> usize {
+        let mut count = 0;
+        for shard in self.base.shards.iter() {
+            let mut write_guard = shard.write();
+            let before = write_guard.len();
+            write_guard.retain(|_, v| v.deadline.load() >= now);
+            count += before - write_guard.len();
+        }
+        count
+    }
+    /// 直接移除，不执行过期回调
+    pub fn remove(&self, k: &K) -> Option<V> {
+        self.base.shard(k).write().remove(k).map(|v| v.val)
+    }
+    fn task(&self, k: K, time: Instant) -> DelayedTask<K> {
+        let seq = self.seq.fetch_add(1, AtomicOrdering::Relaxed);
+        DelayedTask { k, time, seq }
+    }
     pub fn get_val(&self, k: &K) -> Option<V> {
-        self.base.read().get(k).map(|v| v.val.clone())
+        self.base.shard(k).read().get(k).map(|v| v.val.clone())
+    }
+    /// 时间跳跃后，把now时已经到期的元素的过期时间延长一个周期，返回延长的数量
+    fn grant_grace(&self, now: Instant) -> usize {
+        let mut count = 0;
+        for shard in self.base.shards.iter() {
+            for v in shard.read().values() {
+                if v.deadline.load() < now {
+                    v.deadline.store(now.add(v.expire));
+                    count += 1;
+                }
+            }
+        }
+        count
     }
-    fn expire_call(&self, k: &K) -> Op<K, V> {
-        let mut write_guard = self.base.write();
+    fn expire_call(&self, k: &K, now: Instant) -> Op<K, V> {
+        let mut write_guard = self.base.shard(k).write();
         if let Some(v) = write_guard.get(k) {
-            let now = Instant::now();
             let instant = v.deadline.load();
             if instant >= now {
                 // 过期时间更新了
@@ -101,7 +260,7 @@ where
         F: FnOnce() -> (Duration, V),
     {
         let (v, time) = {
-            let mut write_guard = self.base.write();
+            let mut write_guard = self.base.shard(&k).write();
             if let Some(v) = write_guard.get(&k) {
                 // 延长过期时间
                 v.deadline.store(Instant::now().add(v.expire));
@@ -118,17 +277,19 @@ where
                 (val, Some(instant))
             }
         };
+        // 写锁已经释放，等待通道时不阻塞其他操作
         if let Some(time) = time {
-            self.sender.send(DelayedTask { k, time }).await.unwrap();
+            self.schedule(k, time).await;
         }
         v
     }
+    /// 逐个分片读取，不是整个map的快照
     pub fn key_values(&self) -> Vec<(K, V)> {
-        self.base
-            .read()
-            .iter()
-            .map(|(k, v)| (k.clone(), v.val.clone()))
-            .collect()
+        let mut list = Vec::with_capacity(self.size());
+        for shard in self.base.shards.iter() {
+            list.extend(shard.read().iter().map(|(k, v)| (k.clone(), v.val.clone())));
+        }
+        list
     }
 }
 
@@ -145,9 +306,20 @@ where
     F: Fn(K, V),
 {
     let mut binary_heap = BinaryHeap::<DelayedTask<K>>::with_capacity(32);
+    // 上一次宽限的时间，在这之前到期的任务已经处理过跳跃
+    let mut last_grace: Option<Instant> = None;
     loop {
         while let Some(task) = binary_heap.peek() {
             let now = Instant::now();
+            if is_time_jump(task.time, now, last_grace) {
+                let count = map.grant_grace(now);
+                last_grace = Some(now);
+                log::warn!(
+                    "检测到时间跳跃，到期的元素延长一个周期 delay={:?},count={}",
+                    now - task.time,
+                    count
+                );
+            }
             if now < task.time {
                 //需要等待对应时间
                 let duration = task.time - now;
@@ -165,10 +337,9 @@ where
                 }
             } else if let Some(mut task) = binary_heap.pop() {
                 //执行过期逻辑
-                match map.expire_call(&task.k) {
+                match map.expire_call(&task.k, now) {
                     Op::Reset(time) => {
-                        //没有过期，重新加入监听
-
+                        //没有过期，重新加入监听，保留原来的序号
                         task.time = time;
                         binary_heap.push(task);
                     }
@@ -208,28 +379,248 @@ where
     }
 }
 
+/// 到期时间为deadline的任务在now时才执行，延迟超过阈值视为时间跳跃，
+/// 上一次宽限之前到期的任务不再重复判断
+fn is_time_jump(deadline: Instant, now: Instant, last_grace: Option<Instant>) -> bool {
+    if let Some(last_grace) = last_grace {
+        if deadline <= last_grace {
+            return false;
+        }
+    }
+    now.saturating_duration_since(deadline) > TIME_JUMP_THRESHOLD
+}
+
 struct DelayedTask<K> {
     k: K,
     time: Instant,
+    // 到期时间相同时按序号从小到大执行
+    seq: u64,
 }
 
 impl<K> Eq for DelayedTask<K> {}
 
 impl<K> PartialEq for DelayedTask<K> {
     fn eq(&self, other: &Self) -> bool {
-        self.time.eq(&other.time)
+        self.time.eq(&other.time) && self.seq.eq(&other.seq)
     }
 }
 
-#[allow(clippy::non_canonical_partial_ord_impl)]
 impl<K> PartialOrd for DelayedTask<K> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.time.partial_cmp(&other.time).map(|ord| ord.reverse())
+        Some(self.cmp(other))
     }
 }
 
 impl<K> Ord for DelayedTask<K> {
+    /// BinaryHeap是大顶堆，反转后最早到期的先出堆
     fn cmp(&self, other: &Self) -> Ordering {
-        self.time.cmp(&other.time).reverse()
+        self.time
+            .cmp(&other.time)
+            .then(self.seq.cmp(&other.seq))
+            .reverse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_deadline_in_insertion_order() {
+        let now = Instant::now();
+        let mut heap = BinaryHeap::new();
+        for (seq, k) in [(3, "d"), (0, "a"), (2, "c"), (1, "b")] {
+            heap.push(DelayedTask { k, time: now, seq });
+        }
+        heap.push(DelayedTask {
+            k: "early",
+            time: now - Duration::from_millis(1),
+            seq: 4,
+        });
+        let order: Vec<&str> = std::iter::from_fn(|| heap.pop().map(|v| v.k)).collect();
+        assert_eq!(order, vec!["early", "a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn time_jump_grace() {
+        let map: ExpireMap<&str, u32> = ExpireMap::new(|_k, _v| {});
+        map.insert("a", 1, Duration::from_secs(20)).await;
+        map.insert("b", 2, Duration::from_secs(3600)).await;
+        // 模拟挂起1分钟后恢复
+        let now = Instant::now();
+        let resumed = now + Duration::from_secs(80);
+        let deadline = now + Duration::from_secs(20);
+        assert!(is_time_jump(deadline, resumed, None));
+        assert!(!is_time_jump(
+            deadline,
+            deadline + Duration::from_secs(1),
+            None
+        ));
+        assert_eq!(map.grant_grace(resumed), 1);
+        // 宽限后还有一个周期可以续期
+        assert!(matches!(map.expire_call(&"a", resumed), Op::Reset(_)));
+        assert!(matches!(map.expire_call(&"b", resumed), Op::Reset(_)));
+        // 宽限之前到期的任务不再触发
+        assert!(!is_time_jump(deadline, resumed, Some(resumed)));
+        // 宽限期内没有续期则正常过期
+        let later = resumed + Duration::from_secs(21);
+        assert!(!is_time_jump(
+            resumed + Duration::from_secs(20),
+            later,
+            Some(resumed)
+        ));
+        assert!(matches!(map.expire_call(&"a", later), Op::Remove("a", 1)));
+    }
+
+    #[tokio::test]
+    async fn renew_keeps_active_key() {
+        let expired = Arc::new(AtomicU64::new(0));
+        let expired_ = expired.clone();
+        let map: ExpireMap<&str, u32> = ExpireMap::new(move |_k, _v| {
+            expired_.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        map.insert("a", 1, Duration::from_millis(100)).await;
+        // 持续活跃，远超过期时间也不会过期
+        for _ in 0..10 {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            assert!(map.renew(&"a"));
+        }
+        assert_eq!(map.get_val(&"a"), Some(1));
+        assert_eq!(expired.load(std::sync::atomic::Ordering::Relaxed), 0);
+        // 不再续期后到期移除
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(map.get_val(&"a"), None);
+        assert!(!map.renew(&"a"));
+        assert_eq!(expired.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn stress_full_channel() {
+        let expired = Arc::new(AtomicU64::new(0));
+        let expired_ = expired.clone();
+        let map: ExpireMap<u32, u32> = ExpireMap::new(move |_k, _v| {
+            expired_.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        // 单线程运行时中连续插入，超过通道容量后必须等待过期任务取走
+        for i in 0..1000 {
+            map.optionally_get_with(i, || (Duration::from_millis(50), i))
+                .await;
+        }
+        let mut tasks = Vec::new();
+        for n in 1..5 {
+            let map = map.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..500 {
+                    map.insert(n * 1000 + i, i, Duration::from_millis(50)).await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert!(map.send_stats().blocked.load(AtomicOrdering::Relaxed) > 0);
+        assert_eq!(map.send_stats().failed.load(AtomicOrdering::Relaxed), 0);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(map.size(), 0);
+        assert_eq!(expired.load(std::sync::atomic::Ordering::Relaxed), 3000);
+
+        map.insert(1, 1, Duration::from_secs(60)).await;
+        assert_eq!(map.purge_expired(Instant::now()), 0);
+        assert_eq!(
+            map.purge_expired(Instant::now() + Duration::from_secs(61)),
+            1
+        );
+        assert_eq!(map.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn sharded_map() {
+        let expired = Arc::new(AtomicU64::new(0));
+        let expired_ = expired.clone();
+        let map: ExpireMap<u32, u32> = ExpireMap::with_shards(16, move |_k, _v| {
+            expired_.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        for i in 0..100 {
+            map.insert(i, i * 2, Duration::from_millis(100)).await;
+        }
+        map.insert(1000, 0, Duration::from_secs(60)).await;
+        assert_eq!(map.size(), 101);
+        // 元素分布到多个分片
+        assert!(
+            map.base
+                .shards
+                .iter()
+                .filter(|v| !v.read().is_empty())
+                .count()
+                > 1
+        );
+        let mut key_values = map.key_values();
+        key_values.sort();
+        assert_eq!(key_values[7], (7, 14));
+        assert_eq!(map.get_val(&99), Some(198));
+        assert_eq!(map.remove(&99), Some(198));
+        assert!(!map.renew(&99));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        // 所有分片的元素都由同一个过期任务处理
+        assert_eq!(map.size(), 1);
+        assert_eq!(expired.load(std::sync::atomic::Ordering::Relaxed), 99);
+        assert_eq!(
+            map.purge_expired(Instant::now() + Duration::from_secs(61)),
+            1
+        );
+    }
+
+    /// 比较1个和16个分片的锁竞争，多个线程按9:1读写同一个map，
+    /// 运行 cargo test --release shard_contention -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn shard_contention() {
+        const THREADS: u32 = 8;
+        const KEYS: u32 = 10_000;
+        const OPS: u32 = 1_000_000;
+        for count in [1, 16] {
+            let shards: Arc<Shards<u32, u32>> = Arc::new(Shards::new(count));
+            for k in 0..KEYS {
+                shards.shard(&k).write().insert(
+                    k,
+                    Value {
+                        val: k,
+                        deadline: AtomicCell::new(Instant::now()),
+                        expire: Duration::from_secs(60),
+                    },
+                );
+            }
+            let start = Instant::now();
+            let threads: Vec<_> = (0..THREADS)
+                .map(|n| {
+                    let shards = shards.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..OPS {
+                            let k = (i * 7919 + n) % KEYS;
+                            let shard = shards.shard(&k);
+                            if i % 10 == 0 {
+                                if let Some(v) = shard.write().get_mut(&k) {
+                                    v.val = i;
+                                }
+                            } else if let Some(v) = shard.read().get(&k) {
+                                v.deadline.store(Instant::now());
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "shards={},threads={},ops={},elapsed={:?},ns_per_op={}",
+                count,
+                THREADS,
+                THREADS * OPS,
+                elapsed,
+                elapsed.as_nanos() / (THREADS * OPS) as u128
+            );
+        }
     }
 }
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::util::GroupRoute;
+
+/// 一条组网间路由的计数
+#[derive(Default)]
+pub struct RouteMeter {
+    // 转发的数据包数
+    pub forwarded: AtomicU64,
+    // 地址冲突或目标不可达而丢弃的数据包数
+    pub dropped: AtomicU64,
+}
+
+// (源组网,源ip,目标ip) -> (目标组网,计数)
+type RouteTable = HashMap<(String, u32, u32), (String, Arc<RouteMeter>)>;
+
+/// 组网间的转发表
+#[derive(Clone, Default)]
+pub struct GroupRoutes {
+    routes: Arc<RwLock<RouteTable>>,
+    // 没有路由时转发路径不需要加锁
+    enabled: Arc<AtomicBool>,
+}
+
+impl GroupRoutes {
+    pub fn add(&self, route: GroupRoute) -> bool {
+        let mut guard = self.routes.write();
+        let key = (route.src_group, route.src_ip.into(), route.dst_ip.into());
+        let added = guard
+            .insert(key, (route.dst_group, Default::default()))
+            .is_none();
+        self.enabled.store(true, Ordering::Release);
+        added
+    }
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn remove(&self, route: &GroupRoute) -> bool {
+        let mut guard = self.routes.write();
+        let key = (
+            route.src_group.clone(),
+            route.src_ip.into(),
+            route.dst_ip.into(),
+        );
+        let removed = match guard.get(&key) {
+            Some((dst_group, _)) if dst_group == &route.dst_group => {
+                guard.remove(&key);
+                true
+            }
+            _ => false,
+        };
+        self.enabled.store(!guard.is_empty(), Ordering::Release);
+        removed
+    }
+    /// 查找源组网中src_ip访问dst_ip时对应的目标组网
+    pub fn get(
+        &self,
+        src_group: &str,
+        src_ip: u32,
+        dst_ip: u32,
+    ) -> Option<(String, Arc<RouteMeter>)> {
+        if !self.enabled.load(Ordering::Acquire) {
+            return None;
+        }
+        self.routes
+            .read()
+            .get(&(src_group.to_string(), src_ip, dst_ip))
+            .cloned()
+    }
+    pub fn list(&self) -> Vec<(GroupRoute, Arc<RouteMeter>)> {
+        self.routes
+            .read()
+            .iter()
+            .map(|((src_group, src_ip, dst_ip), (dst_group, meter))| {
+                let route = GroupRoute {
+                    src_group: src_group.clone(),
+                    src_ip: (*src_ip).into(),
+                    dst_group: dst_group.clone(),
+                    dst_ip: (*dst_ip).into(),
+                };
+                (route, meter.clone())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_get_remove() {
+        let routes = GroupRoutes::default();
+        let route: GroupRoute = "a,10.26.0.2,b,10.26.0.3".parse().unwrap();
+        assert!(routes.get("a", 0x0A1A0002, 0x0A1A0003).is_none());
+        assert!(routes.add(route.clone()));
+        assert!(!routes.add(route.clone()));
+        let (dst_group, _) = routes.get("a", 0x0A1A0002, 0x0A1A0003).unwrap();
+        assert_eq!(dst_group, "b");
+        // 单向
+        assert!(routes.get("b", 0x0A1A0003, 0x0A1A0002).is_none());
+        let other: GroupRoute = "a,10.26.0.2,c,10.26.0.3".parse().unwrap();
+        assert!(!routes.remove(&other));
+        assert!(routes.remove(&route));
+        assert!(routes.get("a", 0x0A1A0002, 0x0A1A0003).is_none());
+        assert!(routes.list().is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Local;
+
+use crate::core::store::expire_map::ExpireMap;
+use crate::util::canonical_ip;
+
+/// 认证失败(token错误、web登录失败)次数过多的来源ip临时封禁，数据端口的注册和web后台共用，
+/// 失败计数在最后一次失败后duration内没有新的失败时清零
+#[derive(Clone)]
+pub struct IpBan {
+    // 为None时不封禁
+    threshold: Option<u32>,
+    duration: Duration,
+    failures: ExpireMap<IpAddr, Arc<AtomicU32>>,
+    // ip -> 解封时间(秒)，查询不续期，到期自动解封
+    bans: ExpireMap<IpAddr, i64>,
+}
+
+impl IpBan {
+    pub fn new(threshold: Option<u32>, duration: Duration) -> Self {
+        Self {
+            threshold,
+            duration,
+            failures: ExpireMap::new(|_k, _v| {}),
+            bans: ExpireMap::new(|ip, _v| log::info!("解除封禁 ip={}", ip)),
+        }
+    }
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.threshold.is_some() && self.bans.get_val(&canonical_ip(ip)).is_some()
+    }
+    /// 记录一次认证失败，达到阈值时封禁，返回是否因此被封禁
+    pub async fn fail(&self, ip: IpAddr) -> bool {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let ip = canonical_ip(ip);
+        let count = self
+            .failures
+            .optionally_get_with(ip, || (self.duration, Arc::new(AtomicU32::new(0))))
+            .await;
+        if count.fetch_add(1, Ordering::Relaxed) + 1 < threshold {
+            return false;
+        }
+        self.failures.remove(&ip);
+        let unban_time = Local::now().timestamp() + self.duration.as_secs() as i64;
+        self.bans.insert(ip, unban_time, self.duration).await;
+        log::warn!(
+            "认证失败{}次，封禁来源ip={}，{:?}后解封",
+            threshold,
+            ip,
+            self.duration
+        );
+        true
+    }
+    /// 当前封禁的ip和解封时间(秒)，按ip排序
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn list(&self) -> Vec<(IpAddr, i64)> {
+        let mut list = self.bans.key_values();
+        list.sort();
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ban_on_threshold() {
+        let ip_ban = IpBan::new(Some(3), Duration::from_secs(60));
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        assert!(!ip_ban.fail(ip).await);
+        assert!(!ip_ban.fail(ip).await);
+        assert!(!ip_ban.is_banned(ip));
+        // 其他ip的失败不计入
+        assert!(!ip_ban.fail("1.2.3.5".parse().unwrap()).await);
+        assert!(ip_ban.fail(ip).await);
+        assert!(ip_ban.is_banned(ip));
+        // ipv4映射的地址视为同一个ip
+        assert!(ip_ban.is_banned("::ffff:1.2.3.4".parse().unwrap()));
+        assert!(!ip_ban.is_banned("1.2.3.5".parse().unwrap()));
+        let list = ip_ban.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].0, ip);
+        assert!(list[0].1 > Local::now().timestamp());
+
+        // 没有配置阈值时不封禁
+        let disabled = IpBan::new(None, Duration::from_secs(60));
+        for _ in 0..10 {
+            assert!(!disabled.fail(ip).await);
+        }
+        assert!(!disabled.is_banned(ip));
+    }
+
+    #[tokio::test]
+    async fn auto_expiry() {
+        let ip_ban = IpBan::new(Some(1), Duration::from_millis(100));
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        assert!(ip_ban.fail(ip).await);
+        // 查询不会延长封禁时间
+        for _ in 0..3 {
+            assert!(ip_ban.is_banned(ip));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!ip_ban.is_banned(ip));
+        assert!(ip_ban.list().is_empty());
+        // 解封后重新计数
+        assert!(ip_ban.fail(ip).await);
+    }
+}
@@ -1,2 +1,7 @@
 pub mod cache;
+pub mod context_cache;
+pub mod debounce;
 pub mod expire_map;
+pub mod group_route;
+pub mod ip_ban;
+pub mod token_online;
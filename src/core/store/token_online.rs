@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+/// 各token(即组网)的在线客户端数，用于--token-meta的max_online限制，
+/// 和AppCache.online_count在同样的位置增减
+#[derive(Clone, Default)]
+pub struct TokenOnline(Arc<Mutex<HashMap<String, usize>>>);
+
+impl TokenOnline {
+    pub fn inc(&self, token: &str) {
+        *self.0.lock().entry(token.to_string()).or_insert(0) += 1;
+    }
+    /// 减到0时删除，避免组网回收后残留
+    pub fn dec(&self, token: &str, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let mut guard = self.0.lock();
+        if let Some(count) = guard.get_mut(token) {
+            *count = count.saturating_sub(n);
+            if *count == 0 {
+                guard.remove(token);
+            }
+        }
+    }
+    pub fn get(&self, token: &str) -> usize {
+        self.0.lock().get(token).copied().unwrap_or(0)
+    }
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn list(&self) -> Vec<(String, usize)> {
+        let mut list: Vec<(String, usize)> =
+            self.0.lock().iter().map(|(k, v)| (k.clone(), *v)).collect();
+        list.sort();
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inc_dec() {
+        let online = TokenOnline::default();
+        online.inc("a");
+        online.inc("a");
+        online.inc("b");
+        assert_eq!(online.get("a"), 2);
+        online.dec("a", 1);
+        assert_eq!(online.get("a"), 1);
+        online.dec("a", 5);
+        assert_eq!(online.get("a"), 0);
+        online.dec("c", 1);
+        assert_eq!(online.list(), vec![("b".to_string(), 1)]);
+    }
+}
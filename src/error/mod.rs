@@ -25,8 +25,51 @@ pub enum Error {
     IpAlreadyExists,
     #[error("Invalid Ip")]
     InvalidIp,
+    #[error("Finger Error")]
+    FingerError,
     #[error("Other")]
     Other(String),
 }
 
+impl Error {
+    /// 客户端原因导致的错误(token错误、来源未注册、数据包异常等)，属于正常的拒绝，不需要error级别的日志
+    pub fn is_client_fault(&self) -> bool {
+        match self {
+            Error::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::InvalidData
+                    | io::ErrorKind::InvalidInput
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::Channel(_) => false,
+            Error::Protobuf(_) => true,
+            Error::Disconnect
+            | Error::NoKey
+            | Error::AddressExhausted
+            | Error::TokenError
+            | Error::IpAlreadyExists
+            | Error::InvalidIp
+            | Error::FingerError
+            | Error::Other(_) => true,
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_fault() {
+        assert!(Error::TokenError.is_client_fault());
+        assert!(Error::Disconnect.is_client_fault());
+        assert!(Error::FingerError.is_client_fault());
+        assert!(
+            Error::Io(io::Error::new(io::ErrorKind::InvalidData, "len != 4")).is_client_fault()
+        );
+        assert!(!Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "send")).is_client_fault());
+        assert!(!Error::Channel(RecvError).is_client_fault());
+    }
+}
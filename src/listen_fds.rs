@@ -0,0 +1,174 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+
+/// systemd传入的第一个fd，0-2是标准输入输出
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// systemd socket激活时传入的监听socket数量，LISTEN_PID不是当前进程时忽略，
+/// 读取后清除环境变量，避免传给子进程
+fn count_from_env() -> Option<usize> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    let count = listen_fds(pid.as_deref(), fds.as_deref(), std::process::id());
+    if pid.is_some() || fds.is_some() {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+    count
+}
+
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> Option<usize> {
+    if pid?.trim().parse::<u32>().ok()? != own_pid {
+        return None;
+    }
+    fds?.trim().parse::<usize>().ok().filter(|v| *v > 0)
+}
+
+/// 继承的监听socket，按端口匹配，没有匹配的正常创建
+#[derive(Default)]
+pub struct ListenFds {
+    count: usize,
+    tcp: Vec<TcpListener>,
+    udp: Vec<UdpSocket>,
+    // 无法使用的fd，不关闭
+    skipped: Vec<(i32, io::Error)>,
+}
+
+impl ListenFds {
+    /// 接管systemd传入的fd，需要在创建运行时之前调用，否则传入的fd号可能已经被关闭重用，
+    /// 不是tcp监听或udp的fd忽略
+    #[cfg(unix)]
+    pub fn from_env() -> Self {
+        let mut listen_fds = Self::default();
+        if let Some(count) = count_from_env() {
+            listen_fds.count = count;
+            for fd in LISTEN_FDS_START..LISTEN_FDS_START + count as i32 {
+                if let Err(e) = listen_fds.adopt_fd(fd) {
+                    listen_fds.skipped.push((fd, e));
+                }
+            }
+        }
+        listen_fds
+    }
+    #[cfg(not(unix))]
+    pub fn from_env() -> Self {
+        let _ = count_from_env();
+        Self::default()
+    }
+    #[cfg(unix)]
+    fn adopt_fd(&mut self, fd: i32) -> io::Result<()> {
+        use std::os::fd::FromRawFd;
+        // fd由systemd传入，只在这里接管一次，失败时不关闭
+        let socket = std::mem::ManuallyDrop::new(unsafe { socket2::Socket::from_raw_fd(fd) });
+        let ty = socket.r#type()?;
+        if ty != socket2::Type::STREAM && ty != socket2::Type::DGRAM {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported socket type {:?}", ty),
+            ));
+        }
+        socket.local_addr()?;
+        // 不再传给子进程
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+        let socket = std::mem::ManuallyDrop::into_inner(socket);
+        if ty == socket2::Type::STREAM {
+            self.tcp.push(socket.into());
+        } else {
+            self.udp.push(socket.into());
+        }
+        Ok(())
+    }
+    /// 输出接管的socket，日志初始化之后调用
+    pub fn log(&self) {
+        if self.count == 0 {
+            return;
+        }
+        println!("systemd socket激活，传入{}个socket", self.count);
+        log::info!(
+            "systemd socket激活 count={},tcp={:?},udp={:?}",
+            self.count,
+            self.tcp
+                .iter()
+                .filter_map(|v| v.local_addr().ok())
+                .collect::<Vec<_>>(),
+            self.udp
+                .iter()
+                .filter_map(|v| v.local_addr().ok())
+                .collect::<Vec<_>>()
+        );
+        for (fd, e) in &self.skipped {
+            log::warn!("忽略systemd传入的fd={},{:?}", fd, e);
+        }
+    }
+    /// 取出监听地址匹配的tcp socket，监听地址为0.0.0.0或[::]时只匹配端口
+    pub fn take_tcp(&mut self, addr: SocketAddr) -> Option<TcpListener> {
+        let index = self.tcp.iter().position(|v| {
+            v.local_addr()
+                .map(|local| addr_matches(addr, local))
+                .unwrap_or(false)
+        })?;
+        Some(self.tcp.remove(index))
+    }
+    pub fn take_udp(&mut self, port: u16) -> Option<UdpSocket> {
+        let index = self.udp.iter().position(|v| {
+            v.local_addr()
+                .map(|local| local.port() == port)
+                .unwrap_or(false)
+        })?;
+        Some(self.udp.remove(index))
+    }
+    /// 没有用到的socket，配置和systemd的socket单元不一致
+    pub fn unused(&self) -> Vec<SocketAddr> {
+        let tcp = self.tcp.iter().filter_map(|v| v.local_addr().ok());
+        let udp = self.udp.iter().filter_map(|v| v.local_addr().ok());
+        tcp.chain(udp).collect()
+    }
+}
+
+fn addr_matches(addr: SocketAddr, local: SocketAddr) -> bool {
+    addr.port() == local.port()
+        && (addr.ip().is_unspecified() || local.ip().is_unspecified() || addr.ip() == local.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_fds_env() {
+        assert_eq!(listen_fds(Some("100"), Some("2"), 100), Some(2));
+        // 传给其他进程的
+        assert_eq!(listen_fds(Some("101"), Some("2"), 100), None);
+        assert_eq!(listen_fds(None, Some("2"), 100), None);
+        assert_eq!(listen_fds(Some("100"), Some("0"), 100), None);
+        assert_eq!(listen_fds(Some("100"), Some("x"), 100), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn take_inherited() {
+        use std::os::fd::IntoRawFd;
+        let mut listen_fds = ListenFds::default();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp_port = udp.local_addr().unwrap().port();
+        listen_fds.adopt_fd(tcp.into_raw_fd()).unwrap();
+        listen_fds.adopt_fd(udp.into_raw_fd()).unwrap();
+        assert_eq!(listen_fds.unused().len(), 2);
+        assert!(listen_fds.take_udp(udp_port + 1).is_none());
+        // 指定了其他ip的不匹配
+        let other: SocketAddr = format!("127.0.0.2:{}", tcp_addr.port()).parse().unwrap();
+        assert!(listen_fds.take_tcp(other).is_none());
+        let any: SocketAddr = format!("[::]:{}", tcp_addr.port()).parse().unwrap();
+        assert_eq!(
+            listen_fds.take_tcp(any).unwrap().local_addr().unwrap(),
+            tcp_addr
+        );
+        assert!(listen_fds.take_udp(udp_port).is_some());
+        assert!(listen_fds.unused().is_empty());
+    }
+}
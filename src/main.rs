@@ -1,20 +1,31 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
 use std::io;
 use std::io::Write;
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 
 use crate::cipher::RsaCipher;
+use crate::listen_fds::ListenFds;
+use crate::util::{
+    Cidr, GroupRoute, HttpUrl, NamePattern, SharedTokenSet, TokenMetaEntry, TokenMetaMap,
+};
+#[cfg(feature = "web")]
+use crate::util::{WebCapability, WebListen};
 
 mod cipher;
+mod config_history;
 mod core;
 mod error;
 mod generated_serial_number;
+mod listen_fds;
+#[cfg(unix)]
+mod privilege;
 mod proto;
 mod protocol;
+mod util;
 pub const VNT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// 默认网关信息
@@ -32,63 +43,568 @@ pub struct StartArgs {
     /// token白名单，例如 --white-token 1234 --white-token 123
     #[arg(short, long)]
     white_token: Option<Vec<String>>,
+    /// 只有一个组网时的简写，白名单只包含这一个token，和--white-token不能同时使用，例如 --token 1234
+    #[arg(long, conflicts_with = "white_token")]
+    token: Option<String>,
+    /// token白名单只在内存中保存sha256，白名单很大或token很长时减少内存占用，也不常驻明文
+    #[arg(long, default_value_t = false)]
+    token_hash: bool,
+    /// token对应的客户信息，标签会出现在日志、指标和管理后台中，quota限制该组网的客户端数，
+    /// max_online限制同时在线的客户端数，例如 --token-meta token=abc,customer=ACME,label=prod,quota=50,max_online=20，
+    /// 没有配置的token标签为空
+    #[arg(long)]
+    token_meta: Option<Vec<TokenMetaEntry>>,
+    /// 注册时通过外部服务校验token，只支持http，例如 --auth-webhook http://127.0.0.1:8080/auth，
+    /// 响应2xx表示允许，401/403表示拒绝，结果缓存30秒，和--white-token同时设置时两者都要通过
+    #[arg(long)]
+    auth_webhook: Option<HttpUrl>,
+    /// --auth-webhook的请求超时时间(毫秒)，范围100~10000，默认1000
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(100..=10000))]
+    auth_webhook_timeout_ms: u64,
+    /// --auth-webhook无法访问、超时或响应其他状态码时的处理方式，deny:拒绝注册，allow:允许注册，默认deny
+    #[arg(long, value_enum, default_value_t = AuthFallback::Deny)]
+    auth_webhook_fallback: AuthFallback,
+    /// 允许注册的客户端来源网段，支持ipv4和ipv6，例如 --client-allow-cidr 192.168.0.0/16 --client-allow-cidr fd00::/8
+    #[arg(long)]
+    client_allow_cidr: Option<Vec<String>>,
+    /// 禁止注册的客户端版本，和客户端上报的版本完全一致时拒绝，用于屏蔽有问题的版本，
+    /// 例如 --block-client-version 1.2.5 --block-client-version 1.2.6
+    #[arg(long)]
+    block_client_version: Option<Vec<String>>,
+    /// 保留的组网名(token)，客户端不能使用，避免和运维使用的名称混淆，支持*和?通配符，不区分大小写，
+    /// 例如 --reserved-group admin --reserved-group 'sys*'
+    #[arg(long)]
+    reserved_group: Option<Vec<NamePattern>>,
+    /// 客户端上报的device_id的最大长度(字节)，范围1~1024，默认128
+    #[arg(long, default_value_t = 128, value_parser = clap::value_parser!(u16).range(1..=1024))]
+    max_device_id_len: u16,
+    /// 客户端上报的设备名称的最大长度(字节)，范围1~1024，默认128
+    #[arg(long, default_value_t = 128, value_parser = clap::value_parser!(u16).range(1..=1024))]
+    max_name_len: u16,
+    /// 客户端上报的版本号的最大长度(字节)，范围1~1024，默认64
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u16).range(1..=1024))]
+    max_version_len: u16,
+    /// 客户端上报的device_id、名称、版本号超长或包含控制字符时的处理方式，
+    /// reject:拒绝注册，truncate:去掉控制字符并截断到最大长度，默认reject
+    #[arg(long, value_enum, default_value_t = OversizedField::Reject)]
+    oversized_field: OversizedField,
+    /// 组网间转发规则(单向)，允许源组网的源ip访问目标组网的目标ip，格式为 源组网,源ip,目标组网,目标ip，
+    /// 例如 --group-route a,10.26.0.2,b,10.26.0.3 --group-route b,10.26.0.3,a,10.26.0.2
+    #[arg(long)]
+    group_route: Option<Vec<String>>,
+    /// 组网的网关客户端，格式为 组网,设备id，目标不在虚拟网段内的数据包转发给该客户端，并告知组网内的其他客户端，
+    /// 每个组网一个，例如 --gateway-client a,device1
+    #[arg(long)]
+    gateway_client: Option<Vec<String>>,
+    /// 不转发广播和组播的组网，用于不需要局域网发现的组网，单播不受影响，
+    /// 例如 --no-broadcast-group a --no-broadcast-group b
+    #[arg(long)]
+    no_broadcast_group: Option<Vec<String>>,
+    /// 组网的客户端名称规则，格式为 组网,名称规则[,默认名称]，规则支持*和?通配符，不区分大小写，
+    /// 名称不符合规则时有默认名称则改为默认名称，否则拒绝注册，每个组网一个，
+    /// 例如 --name-policy a,site-* --name-policy b,pc-*,pc-unnamed
+    #[arg(long)]
+    name_policy: Option<Vec<String>>,
+    /// 对外公布的服务端地址，服务端在NAT或容器后面时告诉客户端实际可达的地址，例如 --announce-public-ip 1.2.3.4
+    #[arg(long)]
+    announce_public_ip: Option<IpAddr>,
+    /// 对外公布的服务端端口，默认和--port相同，需要配合--announce-public-ip使用
+    #[arg(long)]
+    announce_port: Option<u16>,
     /// 网关，例如 --gateway 10.10.0.1
     #[arg(short, long)]
     gateway: Option<String>,
     /// 子网掩码，例如 --netmask 255.255.255.0
     #[arg(short = 'm', long)]
     netmask: Option<String>,
+    /// ip分配策略，sequential:从小到大分配，random:随机分配，默认sequential
+    #[arg(long, value_enum, default_value_t = AllocStrategy::Sequential)]
+    alloc_strategy: AllocStrategy,
+    /// 同一设备在线时从不同地址重新注册的处理方式，takeover:顶替旧连接，reject:拒绝新注册，默认takeover
+    #[arg(long, value_enum, default_value_t = DuplicateDevice::Takeover)]
+    duplicate_device: DuplicateDevice,
+    /// 地址用完时的处理方式，reject:拒绝注册，reclaim-oldest:回收掉线最久的客户端的ip，默认reject
+    #[arg(long, value_enum, default_value_t = OnExhaustion::Reject)]
+    on_exhaustion: OnExhaustion,
+    /// 客户端上报的本地网段和虚拟网段重叠时的处理方式，重叠时该客户端的路由会出错，
+    /// warn:记录日志并允许注册，reject:拒绝注册并告知客户端重叠的网段，默认warn
+    #[arg(long, value_enum, default_value_t = SubnetOverlap::Warn)]
+    subnet_overlap: SubnetOverlap,
+    /// 发往已注册但掉线的客户端的数据包的处理方式，drop:丢弃，queue:暂存并在客户端重新上线时补发，
+    /// reject:丢弃并通知发送方目标不可达，默认drop
+    #[arg(long, value_enum, default_value_t = OfflinePacket::Drop)]
+    offline_packet: OfflinePacket,
+    /// --offline-packet queue时的宽限时间(秒)，掉线超过该时间或暂存超过该时间的数据包会被丢弃，默认10
+    #[arg(long, default_value_t = 10)]
+    offline_grace_secs: u64,
+    /// 所有组网的客户端总数上限，达到后拒绝新客户端注册(server full)，已有客户端不受影响，默认不限制
+    #[arg(long)]
+    max_total_clients: Option<usize>,
+    /// 掉线但ip租期未到的客户端是否占用名额，yes:租期到期前一直占用，no:只有在线客户端计入--max-total-clients，地址用完时回收掉线最久的客户端的ip，默认yes
+    #[arg(long, value_enum, default_value_t = CountOffline::Yes)]
+    count_offline: CountOffline,
+    /// 同时处理中的注册数上限，超过时拒绝新的注册(server busy, try again later)让客户端稍后重试，
+    /// 已连接客户端的转发不受影响，默认不限制
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_pending_registrations: Option<u32>,
+    /// 进程占用内存(RSS)的上限(MB)，超过时拒绝新客户端注册，已注册的客户端(包括重新连接)不受影响，
+    /// 降到上限的90%以下时恢复，用于避免小内存服务器被OOM杀掉，只支持linux，默认不限制
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    mem_limit_mb: Option<u64>,
+    /// 系统1分钟平均负载的上限，超过时和--mem-limit-mb一样拒绝新客户端注册，只支持linux，默认不限制
+    #[arg(long)]
+    load_limit: Option<f32>,
+    /// 注册耗时(包括加密握手、token校验、等待组网锁和分配ip)超过该值(毫秒)时输出info日志，
+    /// 否则只输出debug日志，范围1~60000，默认1000
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..=60000))]
+    slow_join_ms: u64,
+    /// 客户端可申请的最短ip租期(秒)，默认600
+    #[arg(long, default_value_t = 600)]
+    min_lease_secs: u32,
+    /// 客户端可申请的最长ip租期(秒)，客户端未指定时为一天，默认604800
+    #[arg(long, default_value_t = 7 * 24 * 3600)]
+    max_lease_secs: u32,
     ///开启指纹校验，开启后只会转发指纹正确的客户端数据包，增强安全性，这会损失一部分性能
     #[arg(short, long, default_value_t = false)]
     finger: bool,
+    /// 丢弃源虚拟ip和服务端分配给该客户端的ip不一致的数据包，防止冒充同组网的其他客户端，默认开启，--anti-spoof false关闭
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    anti_spoof: bool,
+    /// tcp发送数据的超时时间(秒)，客户端长时间不接收数据时断开连接，默认30
+    #[arg(long, default_value_t = 30)]
+    tcp_write_timeout_secs: u64,
+    /// tcp连接的最大帧长(字节)，决定每个连接的读缓冲区大小，超过的帧会断开连接，范围1500~65536，默认65536
+    #[arg(long, default_value_t = 65536, value_parser = clap::value_parser!(u32).range(1500..=65536))]
+    tcp_max_frame: u32,
+    /// 客户端和服务端之间加密会话的过期时间(秒)，客户端持续通信时会一直续期，过期后需要重新握手，范围30~86400，默认120
+    #[arg(long, default_value_t = 120, value_parser = clap::value_parser!(u64).range(30..=86400))]
+    cipher_session_secs: u64,
+    /// 注册后的最长会话时间(秒)，超过后不论是否活跃都要重新握手和注册，使移出白名单的token对已连接的客户端生效，最小60，默认不限制
+    #[arg(long, value_parser = clap::value_parser!(u64).range(60..))]
+    max_session_secs: Option<u64>,
+    /// 组网的最后一个客户端被移除(ip长时间未使用而回收)后，经过多少秒回收该组网，0表示立即回收，
+    /// 范围0~86400，默认不开启，空组网7天未使用才回收
+    #[arg(long, value_parser = clap::value_parser!(u64).range(0..=86400))]
+    empty_group_evict_secs: Option<u64>,
+    /// udp转发时发送缓冲区暂时满的重试次数，0表示不重试直接丢弃，最大5，默认2
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=5))]
+    udp_send_retries: u8,
+    /// udp socket的接收缓冲区大小(字节)，pps很高时避免内核在读取前丢包，最小4096，默认使用系统设置，
+    /// 实际大小受系统限制(linux为net.core.rmem_max)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(4096..))]
+    udp_recv_buffer: Option<u32>,
+    /// udp socket的发送缓冲区大小(字节)，最小4096，默认使用系统设置，实际大小受系统限制(linux为net.core.wmem_max)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(4096..))]
+    udp_send_buffer: Option<u32>,
+    /// NAT测试的第二个udp端口，客户端向两个端口发送探测包，比较服务端看到的来源端口判断NAT类型，默认不开启
+    #[arg(long)]
+    nat_test_port: Option<u16>,
+    /// 转发路径缓存连接上下文的刷新间隔(毫秒)，间隔内转发数据包不再逐包查询和续期会话，pps很高时降低开销，
+    /// 范围0~5000，默认0不缓存
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u64).range(0..=5000))]
+    context_cache_ms: u64,
+    /// 会话缓存(组网、ip和地址会话、加密会话等)按key分成的分片数，每个分片单独加锁，客户端很多且cpu核数多时减少锁竞争，
+    /// 范围1~256，默认1不分片
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=256))]
+    cache_shards: u16,
+    /// 每个客户端每秒最多转发的广播包数，超过的丢弃，防止广播风暴在组网内放大，默认不限制
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    broadcast_rate: Option<u32>,
+    /// 通过数据端口查询运行指标的令牌，设置后开启，长度8~128，每秒最多响应10次，令牌错误时不回应
+    #[arg(long)]
+    udp_metrics_token: Option<String>,
+    /// 握手完成前收到加密数据包时，每秒最多回应多少次NoKey提示客户端重新握手，超过的直接丢弃，防止被用于放大攻击，
+    /// 0表示不回应，默认20
+    #[arg(long, default_value_t = 20)]
+    handshake_hint_rate: u32,
+    /// 同一来源ip认证失败(token错误、web登录失败)达到多少次后临时封禁，封禁期间拒绝该ip的注册和web请求，默认不开启
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    ban_threshold: Option<u32>,
+    /// 封禁的时长(秒)，也是失败计数的窗口，最后一次失败后这段时间内没有新的失败时计数清零，默认600
+    #[arg(long, default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..))]
+    ban_secs: u64,
+    /// 记录组网成员变化事件(加入/离开)，target为membership，生成的log4rs.yaml会单独输出到membership.log
+    #[arg(long, default_value_t = false)]
+    membership_log: bool,
+    /// 合并组网成员变化事件的时间窗口(毫秒)，窗口内同一组网的多次变化只记录一条update事件，0表示不合并，默认0
+    #[arg(long, default_value_t = 0)]
+    membership_coalesce_ms: u64,
+    /// 把客户端事件(加入、离开、转发出错等)以每行一个json的格式输出到unix socket，本地工具连接该socket读取，
+    /// 可以有多个消费者，消费者断开后可以重新连接，例如 --event-socket /run/vnts/events.sock，仅unix
+    #[arg(long)]
+    event_socket: Option<PathBuf>,
+    /// 定时输出运行概况的间隔(秒)，包括组网数、客户端数、转发和丢弃的数据包数、缓存大小，默认不输出
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    summary_interval_secs: Option<u64>,
+    /// 定时输出各组网转发的字节数的间隔(秒)，每个有新流量的组网输出一行，用于按组网计费或绘制流量图，默认不输出，
+    /// 累计值也可以从web后台的/group_info和指标vnts_group_bytes_total获取
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    group_bytes_log_secs: Option<u64>,
+    /// tokio运行时的工作线程数，包括数据转发和web后台，用于在共享的主机上限制占用的cpu核数，默认为cpu核数
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    worker_threads: Option<u32>,
     /// log路径，默认为当前程序路径，为/dev/null时表示不输出log
     #[arg(short, long)]
     log_path: Option<String>,
+    /// 不自动生成log4rs.yaml，配置文件不存在时使用内置的默认日志配置(输出到stderr)
+    #[arg(long, default_value_t = false)]
+    no_log_config_gen: bool,
+    /// 执行加解密自检(aes-gcm和rsa密钥)后退出，成功时退出码为0，正常启动时也会先执行一次
+    #[arg(long, default_value_t = false)]
+    self_test: bool,
+    /// 密钥(key/private_key.pem的修改时间)超过该天数时在启动时和每天输出警告，提示轮换密钥，默认不检查
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=36500))]
+    key_max_age_days: Option<u32>,
+    /// 密钥超过--key-max-age-days时拒绝启动
+    #[arg(long, default_value_t = false, requires = "key_max_age_days")]
+    enforce_key_age: bool,
+    #[cfg(unix)]
+    /// 绑定端口后切换到的用户(用户名或uid)，需要以root启动，日志、密钥等文件在切换前创建，需要保证该用户有写日志目录的权限
+    #[arg(long)]
+    user: Option<String>,
+    #[cfg(unix)]
+    /// 绑定端口后切换到的组(组名或gid)，默认为--user的主组
+    #[arg(long)]
+    group: Option<String>,
     #[cfg(feature = "web")]
     ///web后台端口，默认29870，如果设置为0则表示不启动web后台
     #[arg(short = 'P', long)]
     web_port: Option<u16>,
     #[cfg(feature = "web")]
-    /// web后台用户名，默认为admin
+    /// 额外的web监听，格式为 地址:端口[,权限]，权限为admin(全部接口)、readonly(只读接口和页面)、metrics(只有指标)，默认admin，
+    /// 例如 --web-listen 127.0.0.1:29871,admin --web-listen 0.0.0.0:29880,readonly，
+    /// 设置后不再默认监听29870，需要时用--web-port指定
+    #[arg(long)]
+    web_listen: Option<Vec<WebListen>>,
+    #[cfg(feature = "web")]
+    /// web后台用户名，默认为admin，为空时禁用登录(返回web management disabled)
     #[arg(short = 'U', long)]
     username: Option<String>,
     #[cfg(feature = "web")]
-    /// web后台用户密码，默认为admin
+    /// web后台用户密码，默认为admin，为空时禁用登录(返回web management disabled)
     #[arg(short = 'W', long)]
     password: Option<String>,
+    #[cfg(feature = "web")]
+    /// web后台查询组信息时等待读锁的超时时间(毫秒)，超时返回busy，默认1000
+    #[arg(long, default_value_t = 1000)]
+    web_lock_timeout_ms: u64,
+    #[cfg(feature = "web")]
+    /// web后台只开放接口，不提供页面(包括/dash)，页面可以单独部署
+    #[arg(long)]
+    web_api_only: bool,
+    #[cfg(feature = "web")]
+    /// web后台页面的静态文件目录，设置后从该目录读取页面，修改页面不需要重新编译，默认使用编译时内置的页面
+    #[arg(long)]
+    web_static_dir: Option<PathBuf>,
+    #[cfg(feature = "web")]
+    /// 检查编译时内置的web页面(不为空且包含index.html)后退出，成功时退出码为0，使用内置页面启动时也会检查并输出警告
+    #[arg(long, default_value_t = false)]
+    check_web_assets: bool,
+    #[cfg(feature = "web")]
+    /// 记录web后台每个请求的方法、路径、状态码、来源ip和耗时，target为web_access，
+    /// 生成的log4rs.yaml会单独输出到web_access.log，不记录请求体
+    #[arg(long)]
+    web_access_log: bool,
+    #[cfg(feature = "web")]
+    /// web后台/log_tail一次最多返回的日志行数，范围1~10000，默认1000
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..=10000))]
+    log_tail_max_lines: u32,
+    #[cfg(feature = "web")]
+    /// web后台/logs一次最多返回的日志大小(KB)，范围1~4096，默认256
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..=4096))]
+    logs_max_kb: u64,
+    #[cfg(feature = "geoip")]
+    /// GeoLite2-City数据库路径，指定后web后台显示客户端来源地址所在的国家和城市
+    #[arg(long)]
+    geoip_db: Option<PathBuf>,
+}
+
+/// 虚拟ip分配策略
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocStrategy {
+    /// 从小到大分配未使用的ip
+    Sequential,
+    /// 随机分配未使用的ip
+    Random,
+}
+
+/// auth webhook不可用时的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFallback {
+    /// 拒绝注册
+    Deny,
+    /// 允许注册
+    Allow,
+}
+
+/// 同一设备在线时从不同地址重新注册的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateDevice {
+    /// 顶替旧连接，沿用原来的ip
+    Takeover,
+    /// 拒绝新的注册
+    Reject,
+}
+
+/// 地址用完时的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnExhaustion {
+    /// 拒绝注册
+    Reject,
+    /// 回收掉线最久的客户端的ip
+    ReclaimOldest,
+}
+
+/// 掉线但ip租期未到的客户端是否占用名额
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountOffline {
+    /// 租期到期前一直占用名额
+    Yes,
+    /// 不占用名额，地址用完时可以回收给新客户端
+    No,
+}
+
+/// 客户端本地网段和虚拟网段重叠时的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubnetOverlap {
+    /// 记录日志并允许注册
+    Warn,
+    /// 拒绝注册
+    Reject,
+}
+
+/// 发往掉线客户端的数据包的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfflinePacket {
+    /// 直接丢弃
+    Drop,
+    /// 在宽限时间内暂存，客户端重新上线时补发
+    Queue,
+    /// 丢弃并通知发送方目标不可达
+    Reject,
+}
+
+/// 客户端上报的字符串超长或包含控制字符时的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedField {
+    /// 拒绝注册
+    Reject,
+    /// 去掉控制字符并截断
+    Truncate,
+}
+
+/// 客户端上报的字符串的长度限制(字节)
+#[derive(Debug, Clone, Copy)]
+pub struct FieldLimits {
+    pub device_id: usize,
+    pub name: usize,
+    pub version: usize,
+    pub oversized: OversizedField,
+}
+
+/// 组网的客户端名称规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamePolicy {
+    pub pattern: NamePattern,
+    // 名称不符合规则时使用的名称，为None时拒绝注册
+    pub default_name: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConfigInfo {
     pub port: u16,
-    pub white_token: Option<HashSet<String>>,
+    pub announce_addr: Option<SocketAddr>,
+    pub white_token: Option<SharedTokenSet>,
+    pub token_meta: TokenMetaMap,
+    pub auth_webhook: Option<HttpUrl>,
+    pub auth_webhook_timeout: std::time::Duration,
+    pub auth_webhook_fallback: AuthFallback,
+    pub client_allow_cidr: Option<Vec<Cidr>>,
+    pub block_client_version: Vec<String>,
+    pub reserved_group: Vec<NamePattern>,
+    pub field_limits: FieldLimits,
+    pub group_routes: Vec<GroupRoute>,
+    // 组网 -> 网关客户端的设备id
+    pub gateway_client: BTreeMap<String, String>,
+    pub no_broadcast_group: BTreeSet<String>,
+    // 组网 -> 客户端名称规则
+    pub name_policy: BTreeMap<String, NamePolicy>,
     pub gateway: Ipv4Addr,
     pub broadcast: Ipv4Addr,
     pub netmask: Ipv4Addr,
+    pub alloc_strategy: AllocStrategy,
+    pub duplicate_device: DuplicateDevice,
+    pub on_exhaustion: OnExhaustion,
+    pub subnet_overlap: SubnetOverlap,
+    pub offline_packet: OfflinePacket,
+    pub offline_grace: std::time::Duration,
+    pub max_total_clients: Option<usize>,
+    pub max_pending_registrations: Option<usize>,
+    pub mem_limit_mb: Option<u64>,
+    pub load_limit: Option<f32>,
+    pub slow_join: std::time::Duration,
+    pub count_offline: CountOffline,
+    pub min_lease_secs: u32,
+    pub max_lease_secs: u32,
     pub check_finger: bool,
+    pub anti_spoof: bool,
+    pub tcp_write_timeout: std::time::Duration,
+    pub tcp_max_frame: usize,
+    pub cipher_session_ttl: std::time::Duration,
+    pub max_session_age: Option<std::time::Duration>,
+    pub empty_group_evict: Option<std::time::Duration>,
+    pub udp_send_retries: u8,
+    pub udp_recv_buffer: Option<usize>,
+    pub udp_send_buffer: Option<usize>,
+    pub nat_test_port: Option<u16>,
+    pub context_refresh: Option<std::time::Duration>,
+    pub cache_shards: usize,
+    pub broadcast_rate: Option<u32>,
+    pub udp_metrics_token: Option<String>,
+    pub handshake_hint_rate: u32,
+    pub ban_threshold: Option<u32>,
+    pub ban_duration: std::time::Duration,
+    pub membership_log: bool,
+    pub membership_coalesce: std::time::Duration,
+    pub event_socket: Option<PathBuf>,
+    pub summary_interval: Option<std::time::Duration>,
+    pub group_bytes_log: Option<std::time::Duration>,
+    // 启动时计算的配置hash，不参与hash计算
+    pub config_hash: String,
     #[cfg(feature = "web")]
     pub username: String,
     #[cfg(feature = "web")]
     pub password: String,
+    #[cfg(feature = "web")]
+    pub web_lock_timeout: std::time::Duration,
+    #[cfg(feature = "web")]
+    pub web_api_only: bool,
+    #[cfg(feature = "web")]
+    pub web_static_dir: Option<PathBuf>,
+    #[cfg(feature = "web")]
+    pub web_access_log: bool,
+    #[cfg(feature = "web")]
+    pub log_tail_max_lines: usize,
+    #[cfg(feature = "web")]
+    pub logs_max_kb: u64,
+    // 当前的日志文件，不参与hash计算
+    #[cfg(feature = "web")]
+    pub log_file: Option<PathBuf>,
+    #[cfg(feature = "geoip")]
+    pub geoip_db: Option<PathBuf>,
+}
+
+/// 解析--gateway-client，格式为 组网,设备id，同一组网只能配置一次
+fn gateway_client_map(list: Vec<String>) -> Result<BTreeMap<String, String>, String> {
+    let mut map = BTreeMap::new();
+    for item in list {
+        let (group, device_id) = match item.split_once(',') {
+            Some((group, device_id)) => (group.trim(), device_id.trim()),
+            None => return Err(format!("{} 格式应为 组网,设备id", item)),
+        };
+        if group.is_empty() || device_id.is_empty() {
+            return Err(format!("{} 组网和设备id不能为空", item));
+        }
+        if map
+            .insert(group.to_string(), device_id.to_string())
+            .is_some()
+        {
+            return Err(format!("{} 组网重复配置", item));
+        }
+    }
+    Ok(map)
+}
+
+/// 解析--name-policy，格式为 组网,名称规则[,默认名称]，默认名称必须符合规则，同一组网只能配置一次
+fn name_policy_map(list: Vec<String>) -> Result<BTreeMap<String, NamePolicy>, String> {
+    let mut map = BTreeMap::new();
+    for item in list {
+        let mut parts = item.splitn(3, ',').map(str::trim);
+        let group = parts.next().unwrap_or_default();
+        let pattern = match parts.next() {
+            Some(pattern) => pattern,
+            None => return Err(format!("{} 格式应为 组网,名称规则[,默认名称]", item)),
+        };
+        if group.is_empty() {
+            return Err(format!("{} 组网不能为空", item));
+        }
+        let pattern: NamePattern = pattern.parse().map_err(|e| format!("{} {}", item, e))?;
+        let default_name = match parts.next() {
+            Some(default_name) if !pattern.matches(default_name) => {
+                return Err(format!("{} 默认名称不符合规则", item));
+            }
+            Some(default_name) => Some(default_name.to_string()),
+            None => None,
+        };
+        let policy = NamePolicy {
+            pattern,
+            default_name,
+        };
+        if map.insert(group.to_string(), policy).is_some() {
+            return Err(format!("{} 组网重复配置", item));
+        }
+    }
+    Ok(map)
+}
+
+/// --token是只包含一个token的白名单
+fn white_token_list(
+    white_token: Option<Vec<String>>,
+    token: Option<String>,
+) -> Option<Vec<String>> {
+    match token {
+        Some(token) => Some(vec![token]),
+        None => white_token,
+    }
 }
 
-fn log_init(root_path: PathBuf, log_path: Option<String>) {
+/// 返回日志文件vnts.log的路径，不输出日志或者输出到stderr时返回None
+fn log_init(
+    root_path: PathBuf,
+    log_path: Option<String>,
+    no_log_config_gen: bool,
+) -> Option<PathBuf> {
     let log_path = match log_path {
         None => root_path.join("log"),
         Some(log_path) => {
             if &log_path == "/dev/null" {
-                return;
+                return None;
             }
             PathBuf::from(log_path)
         }
     };
-    if !log_path.exists() {
-        let _ = std::fs::create_dir(&log_path);
+    let log_config = log_path.join("log4rs.yaml");
+    if no_log_config_gen {
+        if !log_config.exists() {
+            eprintln!(
+                "Warning 日志配置文件不存在:{:?}，使用内置的默认日志配置",
+                log_config
+            );
+            if let Err(e) = log_init_default() {
+                eprintln!("Warning 日志初始化失败，将不输出日志:{}", e);
+            }
+            return None;
+        }
+    } else {
+        if !log_path.exists() {
+            let _ = std::fs::create_dir(&log_path);
+        }
+        write_log_config(&log_path, &log_config);
+    }
+    if let Err(e) = log4rs::init_file(&log_config, Default::default()) {
+        eprintln!(
+            "Warning 加载日志配置失败:{:?},{}，将不输出日志",
+            log_config, e
+        );
+        return None;
     }
+    Some(log_path.join("vnts.log"))
+}
 
-    let log_config = log_path.join("log4rs.yaml");
+/// 不存在时生成默认的log4rs.yaml
+fn write_log_config(log_path: &Path, log_config: &Path) {
     if !log_config.exists() {
-        if let Ok(mut f) = std::fs::File::create(&log_config) {
+        if let Ok(mut f) = std::fs::File::create(log_config) {
             let log_path = log_path.to_str().unwrap();
             let c = format!(
                 "refresh_rate: 30 seconds
@@ -109,17 +625,82 @@ appenders:
         pattern: {}/vnts.{{}}.log
         base: 1
         count: 5
+  membership_file:
+    kind: rolling_file
+    path: {}/membership.log
+    append: true
+    encoder:
+      pattern: \"{{d}} {{m}}{{n}}\"
+    policy:
+      kind: compound
+      trigger:
+        kind: size
+        limit: 10 mb
+      roller:
+        kind: fixed_window
+        pattern: {}/membership.{{}}.log
+        base: 1
+        count: 5
+  web_access_file:
+    kind: rolling_file
+    path: {}/web_access.log
+    append: true
+    encoder:
+      pattern: \"{{d}} {{m}}{{n}}\"
+    policy:
+      kind: compound
+      trigger:
+        kind: size
+        limit: 10 mb
+      roller:
+        kind: fixed_window
+        pattern: {}/web_access.{{}}.log
+        base: 1
+        count: 5
 
 root:
   level: info
   appenders:
-    - rolling_file",
-                log_path, log_path
+    - rolling_file
+
+loggers:
+  membership:
+    level: info
+    appenders:
+      - membership_file
+  web_access:
+    level: info
+    additive: false
+    appenders:
+      - web_access_file",
+                log_path, log_path, log_path, log_path, log_path, log_path
             );
             let _ = f.write_all(c.as_bytes());
         }
     }
-    let _ = log4rs::init_file(log_config, Default::default());
+}
+
+/// 内置的默认日志配置，输出到stderr
+fn log_init_default() -> Result<(), String> {
+    use log4rs::append::console::{ConsoleAppender, Target};
+    use log4rs::config::{Appender, Config, Root};
+    use log4rs::encode::pattern::PatternEncoder;
+    let stderr = ConsoleAppender::builder()
+        .target(Target::Stderr)
+        .encoder(Box::new(PatternEncoder::new(
+            "{d} [{f}:{L}] {h({l})} {M}:{m}{n}",
+        )))
+        .build();
+    let config = Config::builder()
+        .appender(Appender::builder().build("stderr", Box::new(stderr)))
+        .build(
+            Root::builder()
+                .appender("stderr")
+                .build(log::LevelFilter::Info),
+        )
+        .map_err(|e| format!("{}", e))?;
+    log4rs::init_config(config).map_err(|e| format!("{}", e))?;
+    Ok(())
 }
 
 pub fn app_root() -> PathBuf {
@@ -139,33 +720,214 @@ pub fn app_root() -> PathBuf {
     }
 }
 
-#[tokio::main]
-async fn main() {
+/// --worker-threads没有设置时使用cpu核数
+fn worker_threads(worker_threads: Option<u32>) -> usize {
+    match worker_threads {
+        Some(n) => n as usize,
+        None => std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+    }
+}
+
+fn main() {
     println!("version: {}", VNT_VERSION);
     println!("Serial: {}", generated_serial_number::SERIAL_NUMBER);
     let args = StartArgs::parse();
+    let worker_threads = worker_threads(args.worker_threads);
+    // 修改环境变量和接管fd需要在创建运行时之前
+    let listen_fds = ListenFds::from_env();
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            println!("创建运行时失败: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(run(args, worker_threads, listen_fds));
+}
+
+async fn run(args: StartArgs, worker_threads: usize, mut listen_fds: ListenFds) {
     let root_path = app_root();
-    log_init(root_path.clone(), args.log_path);
+    #[cfg_attr(not(feature = "web"), allow(unused_variables))]
+    let log_file = log_init(root_path.clone(), args.log_path, args.no_log_config_gen);
+    println!("工作线程数: {}", worker_threads);
+    log::info!("工作线程数 worker_threads={}", worker_threads);
+    if args.self_test {
+        let rs = RsaCipher::new(root_path).and_then(|rsa| cipher::self_test::run(Some(&rsa)));
+        match rs {
+            Ok(_) => {
+                println!("自检通过");
+                return;
+            }
+            Err(e) => {
+                println!("自检失败: {}", e);
+                log::error!("自检失败 {:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(feature = "web")]
+    if args.check_web_assets {
+        match core::check_web_assets() {
+            Ok(count) => {
+                println!("web页面检查通过，共{}个文件", count);
+                return;
+            }
+            Err(e) => {
+                println!("web页面检查失败: {}", e);
+                log::error!("web页面检查失败 {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
     let port = args.port.unwrap_or(29872);
     #[cfg(feature = "web")]
-    let web_port = {
-        let web_port = args.web_port.unwrap_or(29870);
+    let web_listen = {
+        // 只配置了--web-listen时不再默认监听29870
+        let default_port = if args.web_listen.is_some() { 0 } else { 29870 };
+        let web_port = args.web_port.unwrap_or(default_port);
         println!("端口: {}", port);
+        let mut web_listen = Vec::new();
         if web_port != 0 {
-            println!("web端口: {}", web_port);
-            if web_port == port {
-                panic!("web-port == port");
-            }
-        } else {
+            web_listen.push(WebListen {
+                addr: format!("[::]:{}", web_port).parse().unwrap(),
+                capability: WebCapability::Admin,
+            });
+        }
+        web_listen.extend(args.web_listen.unwrap_or_default());
+        if web_listen.is_empty() {
             println!("不启用web后台")
         }
-        web_port
+        for listen in &web_listen {
+            println!("web监听: {}", listen);
+            if listen.addr.port() == port {
+                panic!("web端口和服务端口相同 {}", listen);
+            }
+        }
+        web_listen
     };
+    if args.nat_test_port == Some(port) {
+        println!("nat测试端口和服务端口相同: {}", port);
+        return;
+    }
+    #[cfg(feature = "web")]
+    if let Some(dir) = &args.web_static_dir {
+        if !dir.is_dir() {
+            println!("web静态文件目录不存在: {:?}", dir);
+            log::error!("web静态文件目录不存在 web_static_dir={:?}", dir);
+            return;
+        }
+        println!("web静态文件目录: {:?}", dir);
+    }
 
-    let white_token = args
-        .white_token
-        .map(|white_token| HashSet::from_iter(white_token.into_iter()));
+    let announce_addr = match (args.announce_public_ip, args.announce_port) {
+        (Some(ip), port_opt) => {
+            if !util::is_usable_unicast(ip) {
+                println!("公布的地址无效，必须为可用的单播地址: {}", ip);
+                log::error!("公布的地址无效 announce_public_ip={}", ip);
+                return;
+            }
+            let addr = SocketAddr::new(util::canonical_ip(ip), port_opt.unwrap_or(port));
+            println!("对外公布的地址: {}", addr);
+            Some(addr)
+        }
+        (None, Some(_)) => {
+            println!("--announce-port需要配合--announce-public-ip使用");
+            return;
+        }
+        (None, None) => None,
+    };
+    let white_token = white_token_list(args.white_token, args.token)
+        .map(|white_token| SharedTokenSet::new(white_token, args.token_hash));
     println!("token白名单: {:?}", white_token);
+    let token_meta = TokenMetaMap::new(args.token_meta.unwrap_or_default());
+    if !token_meta.is_empty() {
+        println!("token客户信息: {:?}", token_meta);
+    }
+    if let Some(url) = &args.auth_webhook {
+        println!(
+            "auth webhook: {}，不可用时{:?}",
+            url, args.auth_webhook_fallback
+        );
+    }
+    let client_allow_cidr = args.client_allow_cidr.map(|list| {
+        list.iter()
+            .map(|cidr| match cidr.parse::<Cidr>() {
+                Ok(cidr) => cidr,
+                Err(e) => {
+                    log::error!("来源网段错误 client_allow_cidr={},e={}", cidr, e);
+                    panic!("来源网段错误 {}", e)
+                }
+            })
+            .collect::<Vec<Cidr>>()
+    });
+    if let Some(list) = &client_allow_cidr {
+        println!(
+            "允许的来源网段: {}",
+            list.iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        );
+    }
+    let mut group_routes = Vec::new();
+    for route in args.group_route.unwrap_or_default() {
+        match route.parse::<GroupRoute>() {
+            Ok(route) => group_routes.push(route),
+            Err(e) => {
+                println!("组网间转发规则错误 {}", e);
+                log::error!("组网间转发规则错误 group_route={},e={}", route, e);
+                return;
+            }
+        }
+    }
+    if !group_routes.is_empty() {
+        println!(
+            "组网间转发规则: {}",
+            group_routes
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        );
+    }
+    let gateway_client = match gateway_client_map(args.gateway_client.unwrap_or_default()) {
+        Ok(map) => map,
+        Err(e) => {
+            println!("网关客户端配置错误 {}", e);
+            log::error!("网关客户端配置错误 e={}", e);
+            return;
+        }
+    };
+    if !gateway_client.is_empty() {
+        println!("网关客户端: {:?}", gateway_client);
+    }
+    let name_policy = match name_policy_map(args.name_policy.unwrap_or_default()) {
+        Ok(map) => map,
+        Err(e) => {
+            println!("客户端名称规则配置错误 {}", e);
+            log::error!("客户端名称规则配置错误 e={}", e);
+            return;
+        }
+    };
+    if let Some(load_limit) = args.load_limit {
+        if load_limit.is_nan() || load_limit <= 0.0 {
+            println!("--load-limit必须大于0");
+            log::error!("--load-limit必须大于0 load_limit={}", load_limit);
+            return;
+        }
+    }
+    for (group, policy) in &name_policy {
+        println!(
+            "客户端名称规则: group={},pattern={},default_name={:?}",
+            group, policy.pattern, policy.default_name
+        );
+    }
     let gateway = if let Some(gateway) = args.gateway {
         match gateway.parse::<Ipv4Addr>() {
             Ok(ip) => ip,
@@ -216,6 +978,7 @@ async fn main() {
         NETMASK
     };
     println!("子网掩码: {:?}", netmask);
+    println!("ip分配策略: {:?}", args.alloc_strategy);
     if netmask.is_broadcast()
         || netmask.is_unspecified()
         || !(!u32::from_be_bytes(netmask.octets()) + 1).is_power_of_two()
@@ -225,25 +988,157 @@ async fn main() {
         return;
     }
 
+    if util::usable_hosts(gateway.into(), netmask.into()) == 0 {
+        println!("子网中没有可分配给客户端的地址，不支持/31和/32掩码");
+        log::error!(
+            "子网中没有可分配的地址 gateway={},netmask={}",
+            gateway,
+            netmask
+        );
+        return;
+    }
+
+    if args.min_lease_secs == 0 || args.min_lease_secs > args.max_lease_secs {
+        println!("ip租期范围错误，需要满足 0 < --min-lease-secs <= --max-lease-secs");
+        log::error!(
+            "ip租期范围错误 min_lease_secs={},max_lease_secs={}",
+            args.min_lease_secs,
+            args.max_lease_secs
+        );
+        return;
+    }
+
+    if let Some(token) = &args.udp_metrics_token {
+        if token.len() < 8 || token.len() > 128 {
+            println!("--udp-metrics-token 长度需要在8~128之间");
+            log::error!("udp_metrics_token长度错误 len={}", token.len());
+            return;
+        }
+    }
+
     let broadcast = (!u32::from_be_bytes(netmask.octets())) | u32::from_be_bytes(gateway.octets());
     let broadcast = Ipv4Addr::from(broadcast);
     let check_finger = args.finger;
     if check_finger {
         println!("转发校验数据指纹，客户端必须增加--finger参数");
     }
-    let config = ConfigInfo {
+    let mut config = ConfigInfo {
         port,
+        announce_addr,
         white_token,
+        token_meta,
+        auth_webhook: args.auth_webhook,
+        auth_webhook_timeout: std::time::Duration::from_millis(args.auth_webhook_timeout_ms),
+        auth_webhook_fallback: args.auth_webhook_fallback,
+        client_allow_cidr,
+        block_client_version: args.block_client_version.unwrap_or_default(),
+        reserved_group: args.reserved_group.unwrap_or_default(),
+        field_limits: FieldLimits {
+            device_id: args.max_device_id_len as usize,
+            name: args.max_name_len as usize,
+            version: args.max_version_len as usize,
+            oversized: args.oversized_field,
+        },
+        group_routes,
+        gateway_client,
+        no_broadcast_group: args
+            .no_broadcast_group
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| v.trim().to_string())
+            .collect(),
+        name_policy,
         gateway,
         broadcast,
         netmask,
+        alloc_strategy: args.alloc_strategy,
+        duplicate_device: args.duplicate_device,
+        on_exhaustion: args.on_exhaustion,
+        subnet_overlap: args.subnet_overlap,
+        offline_packet: args.offline_packet,
+        offline_grace: std::time::Duration::from_secs(args.offline_grace_secs),
+        max_total_clients: args.max_total_clients,
+        max_pending_registrations: args.max_pending_registrations.map(|v| v as usize),
+        mem_limit_mb: args.mem_limit_mb,
+        load_limit: args.load_limit,
+        slow_join: std::time::Duration::from_millis(args.slow_join_ms),
+        count_offline: args.count_offline,
+        min_lease_secs: args.min_lease_secs,
+        max_lease_secs: args.max_lease_secs,
         check_finger,
+        anti_spoof: args.anti_spoof,
+        tcp_write_timeout: std::time::Duration::from_secs(args.tcp_write_timeout_secs),
+        tcp_max_frame: args.tcp_max_frame as usize,
+        cipher_session_ttl: std::time::Duration::from_secs(args.cipher_session_secs),
+        max_session_age: args.max_session_secs.map(std::time::Duration::from_secs),
+        empty_group_evict: args
+            .empty_group_evict_secs
+            .map(std::time::Duration::from_secs),
+        udp_send_retries: args.udp_send_retries,
+        udp_recv_buffer: args.udp_recv_buffer.map(|v| v as usize),
+        udp_send_buffer: args.udp_send_buffer.map(|v| v as usize),
+        nat_test_port: args.nat_test_port,
+        context_refresh: if args.context_cache_ms == 0 {
+            None
+        } else {
+            Some(std::time::Duration::from_millis(args.context_cache_ms))
+        },
+        cache_shards: args.cache_shards as usize,
+        broadcast_rate: args.broadcast_rate,
+        udp_metrics_token: args.udp_metrics_token,
+        handshake_hint_rate: args.handshake_hint_rate,
+        ban_threshold: args.ban_threshold,
+        ban_duration: std::time::Duration::from_secs(args.ban_secs),
+        membership_log: args.membership_log,
+        membership_coalesce: std::time::Duration::from_millis(args.membership_coalesce_ms),
+        event_socket: args.event_socket,
+        summary_interval: args
+            .summary_interval_secs
+            .map(std::time::Duration::from_secs),
+        group_bytes_log: args
+            .group_bytes_log_secs
+            .map(std::time::Duration::from_secs),
+        config_hash: String::new(),
         #[cfg(feature = "web")]
         username: args.username.unwrap_or_else(|| "admin".into()),
         #[cfg(feature = "web")]
         password: args.password.unwrap_or_else(|| "admin".into()),
+        #[cfg(feature = "web")]
+        web_lock_timeout: std::time::Duration::from_millis(args.web_lock_timeout_ms),
+        #[cfg(feature = "web")]
+        web_api_only: args.web_api_only,
+        #[cfg(feature = "web")]
+        web_static_dir: args.web_static_dir,
+        #[cfg(feature = "web")]
+        web_access_log: args.web_access_log,
+        #[cfg(feature = "web")]
+        log_tail_max_lines: args.log_tail_max_lines as usize,
+        #[cfg(feature = "web")]
+        logs_max_kb: args.logs_max_kb,
+        #[cfg(feature = "web")]
+        log_file,
+        #[cfg(feature = "geoip")]
+        geoip_db: args.geoip_db,
     };
-    let rsa = match RsaCipher::new(root_path) {
+    match config_history::record(&root_path.join("config_history.jsonl"), &config) {
+        Ok(hash) => {
+            println!("配置hash: {}", hash);
+            config.config_hash = hash;
+        }
+        Err(e) => {
+            log::warn!("记录配置变更失败:{:?}", e);
+        }
+    }
+    #[cfg(feature = "web")]
+    if (config.username.is_empty() || config.password.is_empty())
+        && web_listen
+            .iter()
+            .any(|v| v.capability == WebCapability::Admin)
+    {
+        println!("web后台用户名或密码为空，登录已禁用，管理接口不可用");
+        log::error!("web后台用户名或密码为空，登录已禁用，管理接口不可用");
+    }
+    let rsa = match RsaCipher::new(root_path.clone()) {
         Ok(rsa) => {
             println!("密钥指纹: {}", rsa.finger());
             Some(rsa)
@@ -253,28 +1148,122 @@ async fn main() {
             panic!("获取密钥错误:{}", e);
         }
     };
+    // 开始接收数据前确认加解密正常
+    if let Err(e) = cipher::self_test::run(rsa.as_ref()) {
+        println!("加解密自检失败: {}", e);
+        log::error!("加解密自检失败 {:?}", e);
+        std::process::exit(1);
+    }
+    if let Some(days) = args.key_max_age_days {
+        let max_age = std::time::Duration::from_secs(days as u64 * 86400);
+        match cipher::key_age::check(&root_path, max_age) {
+            Ok(None) => {}
+            Ok(Some(age)) => {
+                println!(
+                    "密钥已使用{}天，超过{}天，请轮换密钥",
+                    age.as_secs() / 86400,
+                    days
+                );
+                if args.enforce_key_age {
+                    log::error!("密钥超过--key-max-age-days，拒绝启动");
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                println!("获取密钥创建时间失败: {}", e);
+                log::warn!("获取密钥创建时间失败 {:?}", e);
+                if args.enforce_key_age {
+                    std::process::exit(1);
+                }
+            }
+        }
+        tokio::spawn(cipher::key_age::watch(root_path, max_age));
+    }
     log::info!("config:{:?}", config);
-    let udp = create_udp(port).unwrap();
+    // systemd socket激活时使用传入的socket，没有传入的端口正常创建
+    listen_fds.log();
+    let udp = listen_fds
+        .take_udp(port)
+        .map_or_else(
+            || create_udp(port, config.udp_recv_buffer, config.udp_send_buffer),
+            Ok,
+        )
+        .unwrap();
     log::info!("监听udp端口: {:?}", port);
     println!("监听udp端口: {:?}", port);
-    let tcp = create_tcp(port).unwrap();
+    let tcp_addr = format!("[::]:{}", port).parse().unwrap();
+    let tcp = listen_fds
+        .take_tcp(tcp_addr)
+        .map_or_else(|| create_tcp(tcp_addr), Ok)
+        .unwrap();
     log::info!("监听tcp端口: {:?}", port);
     println!("监听tcp端口: {:?}", port);
+    let nat_test = config.nat_test_port.map(|nat_test_port| {
+        let udp = listen_fds
+            .take_udp(nat_test_port)
+            .map_or_else(|| create_udp(nat_test_port, None, None), Ok)
+            .unwrap();
+        log::info!("监听nat测试端口: {:?}", nat_test_port);
+        println!("监听nat测试端口: {:?}", nat_test_port);
+        udp
+    });
     #[cfg(feature = "web")]
-    let http = if web_port != 0 {
-        let http = create_tcp(web_port).unwrap();
-        log::info!("监听http端口: {:?}", web_port);
-        println!("监听http端口: {:?}", web_port);
-        Some(http)
-    } else {
-        None
+    let http: Vec<_> = web_listen
+        .iter()
+        .map(|listen| {
+            let http = listen_fds
+                .take_tcp(listen.addr)
+                .map_or_else(|| create_tcp(listen.addr), Ok)
+                .unwrap();
+            log::info!("监听http: {}", listen);
+            println!("监听http: {}", listen);
+            (http, listen.capability)
+        })
+        .collect();
+    // 和端口一样在切换用户前创建，socket文件所在的目录通常只有root可写
+    let events = match &config.event_socket {
+        Some(path) => match core::EventSocket::bind(path) {
+            Ok(events) => {
+                log::info!("事件socket: {:?}", path);
+                println!("事件socket: {:?}", path);
+                events
+            }
+            Err(e) => {
+                println!("创建事件socket失败 {:?} {}", path, e);
+                log::error!("创建事件socket失败 {:?} {:?}", path, e);
+                return;
+            }
+        },
+        None => core::EventSocket::default(),
     };
+    let unused = listen_fds.unused();
+    if !unused.is_empty() {
+        log::warn!("systemd传入的socket没有匹配的监听端口 {:?}", unused);
+    }
+    drop(listen_fds);
+    #[cfg(unix)]
+    if args.user.is_some() || args.group.is_some() {
+        if let Err(e) = privilege::drop_privileges(args.user.as_deref(), args.group.as_deref()) {
+            println!("切换用户失败 {}", e);
+            log::error!(
+                "切换用户失败 user={:?},group={:?},e={:?}",
+                args.user,
+                args.group,
+                e
+            );
+            return;
+        }
+        println!("已切换用户 user={:?},group={:?}", args.user, args.group);
+        log::info!("已切换用户 user={:?},group={:?}", args.user, args.group);
+    }
     let config = config.clone();
     if let Err(e) = core::start(
         udp,
         tcp,
+        nat_test,
         #[cfg(feature = "web")]
         http,
+        events,
         config,
         rsa,
     )
@@ -284,16 +1273,21 @@ async fn main() {
     }
 }
 
-fn create_tcp(port: u16) -> io::Result<std::net::TcpListener> {
-    let address: std::net::SocketAddr = format!("[::]:{}", port).parse().unwrap();
+fn create_tcp(address: std::net::SocketAddr) -> io::Result<std::net::TcpListener> {
     let socket = io_convert(
-        socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::STREAM, None),
-        |e| format!("new IPV6 STREAM {:?}", e),
+        socket2::Socket::new(
+            socket2::Domain::for_address(address),
+            socket2::Type::STREAM,
+            None,
+        ),
+        |e| format!("new STREAM {:?},{:?}", address, e),
     )?;
 
-    io_convert(socket.set_only_v6(false), |e| {
-        format!("set_only_v6 {:?}", e)
-    })?;
+    if address.is_ipv6() {
+        io_convert(socket.set_only_v6(false), |e| {
+            format!("set_only_v6 {:?}", e)
+        })?;
+    }
     io_convert(socket.set_reuse_address(true), |e| {
         format!("set_reuse_address {:?}", e)
     })?;
@@ -309,7 +1303,11 @@ fn create_tcp(port: u16) -> io::Result<std::net::TcpListener> {
     Ok(socket.into())
 }
 
-fn create_udp(port: u16) -> io::Result<std::net::UdpSocket> {
+fn create_udp(
+    port: u16,
+    recv_buffer: Option<usize>,
+    send_buffer: Option<usize>,
+) -> io::Result<std::net::UdpSocket> {
     let address: std::net::SocketAddr = format!("[::]:{}", port).parse().unwrap();
     let socket = io_convert(
         socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::DGRAM, None),
@@ -325,12 +1323,47 @@ fn create_udp(port: u16) -> io::Result<std::net::UdpSocket> {
     io_convert(socket.set_nonblocking(true), |e| {
         format!("set_nonblocking {:?}", e)
     })?;
+    if let Some(size) = recv_buffer {
+        io_convert(socket.set_recv_buffer_size(size), |e| {
+            format!("set_recv_buffer_size {} {:?}", size, e)
+        })?;
+        log_buffer_size("接收", size, socket.recv_buffer_size());
+    }
+    if let Some(size) = send_buffer {
+        io_convert(socket.set_send_buffer_size(size), |e| {
+            format!("set_send_buffer_size {} {:?}", size, e)
+        })?;
+        log_buffer_size("发送", size, socket.send_buffer_size());
+    }
     io_convert(socket.bind(&address.into()), |e| {
         format!("bind {:?},{:?}", address, e)
     })?;
     Ok(socket.into())
 }
 
+/// 内核可能会调整设置的大小(linux会翻倍，超过系统上限时截断)，输出实际生效的大小
+fn log_buffer_size(kind: &str, requested: usize, applied: io::Result<usize>) {
+    match applied {
+        Ok(applied) => {
+            log::info!("udp{}缓冲区 设置={},实际={}", kind, requested, applied);
+            println!("udp{}缓冲区: 设置={},实际={}", kind, requested, applied);
+            if applied < requested {
+                log::warn!(
+                    "udp{}缓冲区小于设置的大小，超过了系统限制 设置={},实际={}",
+                    kind,
+                    requested,
+                    applied
+                );
+                println!(
+                    "警告: udp{}缓冲区超过了系统限制，实际只有{}字节",
+                    kind, applied
+                );
+            }
+        }
+        Err(e) => log::warn!("读取udp{}缓冲区大小失败 {:?}", kind, e),
+    }
+}
+
 #[inline]
 pub fn io_convert<T, R: Display, F: FnOnce(&io::Error) -> R>(
     rs: io::Result<T>,
@@ -338,3 +1371,79 @@ pub fn io_convert<T, R: Display, F: FnOnce(&io::Error) -> R>(
 ) -> io::Result<T> {
     rs.map_err(|e| io::Error::new(e.kind(), format!("{},internal error:{:?}", f(&e), e)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_token() {
+        let args = StartArgs::try_parse_from(["vnts", "--token", "abc"]).unwrap();
+        assert_eq!(
+            white_token_list(args.white_token, args.token),
+            Some(vec!["abc".to_string()])
+        );
+        let args = StartArgs::try_parse_from(["vnts", "--white-token", "a", "--white-token", "b"])
+            .unwrap();
+        assert_eq!(
+            white_token_list(args.white_token, args.token)
+                .unwrap()
+                .len(),
+            2
+        );
+        let args = StartArgs::try_parse_from(["vnts"]).unwrap();
+        assert_eq!(white_token_list(args.white_token, args.token), None);
+        assert!(
+            StartArgs::try_parse_from(["vnts", "--token", "abc", "--white-token", "a"]).is_err()
+        );
+    }
+
+    #[test]
+    fn name_policy() {
+        let map = name_policy_map(vec!["a, site-*".into(), "b,pc-*,pc-unnamed".into()]).unwrap();
+        assert!(map["a"].pattern.matches("site-1"));
+        assert_eq!(map["a"].default_name, None);
+        assert_eq!(map["b"].default_name.as_deref(), Some("pc-unnamed"));
+        assert!(name_policy_map(vec!["a".into()]).is_err());
+        assert!(name_policy_map(vec!["a,".into()]).is_err());
+        assert!(name_policy_map(vec![",site-*".into()]).is_err());
+        // 默认名称不符合规则
+        assert!(name_policy_map(vec!["a,site-*,pc-1".into()]).is_err());
+        assert!(name_policy_map(vec!["a,site-*".into(), "a,pc-*".into()]).is_err());
+    }
+
+    #[test]
+    fn gateway_client() {
+        let map = gateway_client_map(vec!["a, d1".into(), "b,d2".into()]).unwrap();
+        assert_eq!(map.get("a").unwrap(), "d1");
+        assert_eq!(map.len(), 2);
+        assert!(gateway_client_map(vec!["a".into()]).is_err());
+        assert!(gateway_client_map(vec!["a,".into()]).is_err());
+        assert!(gateway_client_map(vec!["a,d1".into(), "a,d2".into()]).is_err());
+    }
+
+    #[test]
+    fn worker_threads_arg() {
+        let args = StartArgs::try_parse_from(["vnts", "--worker-threads", "2"]).unwrap();
+        assert_eq!(worker_threads(args.worker_threads), 2);
+        assert!(worker_threads(None) >= 1);
+        assert!(StartArgs::try_parse_from(["vnts", "--worker-threads", "0"]).is_err());
+    }
+
+    #[test]
+    fn enforce_key_age_requires_max_age() {
+        assert!(StartArgs::try_parse_from(["vnts", "--enforce-key-age"]).is_err());
+        let args =
+            StartArgs::try_parse_from(["vnts", "--key-max-age-days", "90", "--enforce-key-age"])
+                .unwrap();
+        assert_eq!(args.key_max_age_days, Some(90));
+        assert!(args.enforce_key_age);
+    }
+
+    #[test]
+    fn anti_spoof_default_on() {
+        assert!(StartArgs::try_parse_from(["vnts"]).unwrap().anti_spoof);
+        let args = StartArgs::try_parse_from(["vnts", "--anti-spoof", "false"]).unwrap();
+        assert!(!args.anti_spoof);
+    }
+}
@@ -0,0 +1,96 @@
+use std::ffi::CString;
+use std::io;
+
+/// 用户名或者数字id对应的uid，以及该用户的主组
+fn resolve_user(user: &str) -> io::Result<(libc::uid_t, Option<libc::gid_t>)> {
+    if let Ok(uid) = user.parse::<libc::uid_t>() {
+        return Ok((uid, None));
+    }
+    let name = CString::new(user).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // 只在启动时调用一次，此时还没有其他线程查询用户信息
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("user not found {}", user),
+        ));
+    }
+    let passwd = unsafe { &*passwd };
+    Ok((passwd.pw_uid, Some(passwd.pw_gid)))
+}
+
+/// 组名或者数字id对应的gid
+fn resolve_group(group: &str) -> io::Result<libc::gid_t> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let group_info = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group_info.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("group not found {}", group),
+        ));
+    }
+    Ok(unsafe { (*group_info).gr_gid })
+}
+
+/// 绑定端口后切换到指定的用户和组，只指定用户时使用该用户的主组，
+/// 先清空附加组再设置gid，最后设置uid，任何一步失败都返回错误
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    if user.is_none() && group.is_none() {
+        return Ok(());
+    }
+    let (uid, user_gid) = match user {
+        Some(user) => {
+            let (uid, gid) = resolve_user(user)?;
+            (Some(uid), gid)
+        }
+        None => (None, None),
+    };
+    let gid = match group {
+        Some(group) => Some(resolve_group(group)?),
+        None => user_gid,
+    };
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "--user/--group requires starting as root",
+        ));
+    }
+    if let Some(gid) = gid {
+        if unsafe { libc::setgroups(1, &gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::setgid(gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if let Some(uid) = uid {
+        if unsafe { libc::setuid(uid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // 确认不能再切回root
+        if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::other("privileges can still be regained"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        assert_eq!(resolve_user("0").unwrap(), (0, None));
+        let (uid, gid) = resolve_user("root").unwrap();
+        assert_eq!(uid, 0);
+        assert!(gid.is_some());
+        assert_eq!(resolve_group("0").unwrap(), 0);
+        assert!(resolve_user("vnts-no-such-user").is_err());
+        assert!(resolve_group("vnts-no-such-group").is_err());
+        assert!(drop_privileges(None, None).is_ok());
+    }
+}
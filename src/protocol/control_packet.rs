@@ -22,6 +22,19 @@ pub enum Protocol {
     ///获取对端看到的地址
     AddrRequest,
     AddrResponse,
+    /// NAT测试，服务端在主端口和--nat-test-port上都回应看到的来源地址，
+    /// 客户端比较两个端口看到的来源端口判断NAT的端口映射方式，请求的载荷不能短于响应(8字节)
+    NatTestRequest,
+    /*
+     0                   1                   2                   3
+     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    |                             ipv4                              |
+    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    |              port             |          server_port          |
+    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    */
+    NatTestResponse,
     Unknown(u8),
 }
 
@@ -34,6 +47,8 @@ impl From<u8> for Protocol {
             4 => Protocol::PunchResponse,
             5 => Protocol::AddrRequest,
             6 => Protocol::AddrResponse,
+            7 => Protocol::NatTestRequest,
+            8 => Protocol::NatTestResponse,
             val => Protocol::Unknown(val),
         }
     }
@@ -48,6 +63,8 @@ impl From<Protocol> for u8 {
             Protocol::PunchResponse => 4,
             Protocol::AddrRequest => 5,
             Protocol::AddrResponse => 6,
+            Protocol::NatTestRequest => 7,
+            Protocol::NatTestResponse => 8,
             Protocol::Unknown(val) => val,
         }
     }
@@ -60,6 +77,8 @@ pub enum ControlPacket<B> {
     PunchResponse,
     AddrRequest,
     AddrResponse(AddrPacket<B>),
+    NatTestRequest,
+    NatTestResponse(NatTestPacket<B>),
 }
 
 impl<B: AsRef<[u8]>> ControlPacket<B> {
@@ -71,6 +90,10 @@ impl<B: AsRef<[u8]>> ControlPacket<B> {
             Protocol::PunchResponse => Ok(ControlPacket::PunchResponse),
             Protocol::AddrRequest => Ok(ControlPacket::AddrRequest),
             Protocol::AddrResponse => Ok(ControlPacket::AddrResponse(AddrPacket::new(buffer)?)),
+            Protocol::NatTestRequest => Ok(ControlPacket::NatTestRequest),
+            Protocol::NatTestResponse => {
+                Ok(ControlPacket::NatTestResponse(NatTestPacket::new(buffer)?))
+            }
             Protocol::Unknown(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported")),
         }
     }
@@ -158,3 +181,52 @@ impl<B: AsRef<[u8]>> fmt::Debug for AddrPacket<B> {
             .finish()
     }
 }
+
+pub const NAT_TEST_LEN: usize = 8;
+
+/// NAT测试响应，看到的来源地址和收到请求的服务端端口
+pub struct NatTestPacket<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> NatTestPacket<B> {
+    pub fn new(buffer: B) -> io::Result<NatTestPacket<B>> {
+        let len = buffer.as_ref().len();
+        if len != NAT_TEST_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "len != 8"));
+        }
+        Ok(NatTestPacket { buffer })
+    }
+    pub fn ipv4(&self) -> Ipv4Addr {
+        let buf = self.buffer.as_ref();
+        Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3])
+    }
+    pub fn port(&self) -> u16 {
+        u16::from_be_bytes(self.buffer.as_ref()[4..6].try_into().unwrap())
+    }
+    pub fn server_port(&self) -> u16 {
+        u16::from_be_bytes(self.buffer.as_ref()[6..8].try_into().unwrap())
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> NatTestPacket<B> {
+    pub fn set_ipv4(&mut self, ip: Ipv4Addr) {
+        self.buffer.as_mut()[..4].copy_from_slice(&ip.octets())
+    }
+    pub fn set_port(&mut self, port: u16) {
+        self.buffer.as_mut()[4..6].copy_from_slice(&port.to_be_bytes())
+    }
+    pub fn set_server_port(&mut self, port: u16) {
+        self.buffer.as_mut()[6..8].copy_from_slice(&port.to_be_bytes())
+    }
+}
+
+impl<B: AsRef<[u8]>> fmt::Debug for NatTestPacket<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NatTestPacket")
+            .field("ipv4", &self.ipv4())
+            .field("port", &self.port())
+            .field("server_port", &self.server_port())
+            .finish()
+    }
+}
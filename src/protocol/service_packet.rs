@@ -15,6 +15,9 @@ pub enum Protocol {
     SecretHandshakeResponse,
     /// 客户端上报状态
     ClientStatusInfo,
+    /// 查询运行指标
+    MetricsRequest,
+    MetricsResponse,
     Unknown(u8),
 }
 
@@ -30,6 +33,8 @@ impl From<u8> for Protocol {
             7 => Self::SecretHandshakeRequest,
             8 => Self::SecretHandshakeResponse,
             9 => Self::ClientStatusInfo,
+            10 => Self::MetricsRequest,
+            11 => Self::MetricsResponse,
             val => Self::Unknown(val),
         }
     }
@@ -47,6 +52,8 @@ impl From<Protocol> for u8 {
             Protocol::SecretHandshakeRequest => 7,
             Protocol::SecretHandshakeResponse => 8,
             Protocol::ClientStatusInfo => 9,
+            Protocol::MetricsRequest => 10,
+            Protocol::MetricsResponse => 11,
             Protocol::Unknown(val) => val,
         }
     }
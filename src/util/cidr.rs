@@ -0,0 +1,110 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::util::canonical_ip;
+
+/// 网段，例如 10.0.0.0/8、fd00::/8，不带前缀长度时表示单个地址，
+/// 匹配时ipv4映射的ipv6地址按ipv4处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        Self { addr, prefix }
+    }
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, canonical_ip(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|e| format!("{} {}", s, e))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .map_err(|e| format!("{} {}", s, e))?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(format!("{} prefix > {}", s, max));
+        }
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains_ipv4() {
+        let cidr: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&ip("192.168.1.2")));
+        assert!(!cidr.contains(&ip("192.169.1.2")));
+        assert!(!cidr.contains(&ip("fd00::1")));
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&ip("8.8.8.8")));
+        let one: Cidr = "10.0.0.1".parse().unwrap();
+        assert!(one.contains(&ip("10.0.0.1")));
+        assert!(!one.contains(&ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn contains_ipv4_mapped() {
+        let cidr: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&ip("::ffff:192.168.3.4")));
+        assert!(!cidr.contains(&ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn contains_ipv6() {
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains(&ip("fd12::1")));
+        assert!(!cidr.contains(&ip("fe80::1")));
+        assert!(!cidr.contains(&ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn parse_error() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fd00::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/a".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// 组网间的转发规则，允许源组网的src_ip访问目标组网的dst_ip(单向)，
+/// 格式为 源组网,源ip,目标组网,目标ip
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GroupRoute {
+    pub src_group: String,
+    pub src_ip: Ipv4Addr,
+    pub dst_group: String,
+    pub dst_ip: Ipv4Addr,
+}
+
+impl GroupRoute {
+    pub fn new(
+        src_group: String,
+        src_ip: Ipv4Addr,
+        dst_group: String,
+        dst_ip: Ipv4Addr,
+    ) -> Result<Self, String> {
+        if src_group.is_empty() || dst_group.is_empty() {
+            return Err("组网不能为空".into());
+        }
+        if src_group == dst_group {
+            return Err("源组网和目标组网相同".into());
+        }
+        Ok(GroupRoute {
+            src_group,
+            src_ip,
+            dst_group,
+            dst_ip,
+        })
+    }
+}
+
+impl FromStr for GroupRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let items: Vec<&str> = s.split(',').map(|v| v.trim()).collect();
+        if items.len() != 4 {
+            return Err(format!("{} 格式应为 源组网,源ip,目标组网,目标ip", s));
+        }
+        let parse_ip = |ip: &str| ip.parse::<Ipv4Addr>().map_err(|e| format!("{} {}", s, e));
+        GroupRoute::new(
+            items[0].to_string(),
+            parse_ip(items[1])?,
+            items[2].to_string(),
+            parse_ip(items[3])?,
+        )
+        .map_err(|e| format!("{} {}", s, e))
+    }
+}
+
+impl fmt::Display for GroupRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.src_group, self.src_ip, self.dst_group, self.dst_ip
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let route: GroupRoute = "a, 10.26.0.2 ,b,10.26.0.3".parse().unwrap();
+        assert_eq!(route.src_group, "a");
+        assert_eq!(route.src_ip, Ipv4Addr::new(10, 26, 0, 2));
+        assert_eq!(route.dst_group, "b");
+        assert_eq!(route.dst_ip, Ipv4Addr::new(10, 26, 0, 3));
+        assert_eq!(route.to_string(), "a,10.26.0.2,b,10.26.0.3");
+        assert!("a,10.26.0.2,b".parse::<GroupRoute>().is_err());
+        assert!("a,10.26.0.2,a,10.26.0.3".parse::<GroupRoute>().is_err());
+        assert!("a,10.26.0.256,b,10.26.0.3".parse::<GroupRoute>().is_err());
+        assert!(",10.26.0.2,b,10.26.0.3".parse::<GroupRoute>().is_err());
+    }
+}
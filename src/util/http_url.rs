@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// http地址，只支持http://，格式为 http://host[:port][/path]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Err(format!("{} 只支持http://", s)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        // ipv6地址需要用[]括起来
+        let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+            match v6.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => match port.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(format!("{} 地址格式错误", s)),
+                },
+                None => return Err(format!("{} 地址格式错误", s)),
+            }
+        } else {
+            match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("{} 缺少主机", s));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|e| format!("{} 端口错误 {}", s, e))?,
+            None => 80,
+        };
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "http://[{}]:{}{}", self.host, self.port, self.path)
+        } else {
+            write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let url: HttpUrl = "http://auth.local/check?v=1".parse().unwrap();
+        assert_eq!(url.host, "auth.local");
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/check?v=1");
+        let url: HttpUrl = "http://127.0.0.1:8080".parse().unwrap();
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/");
+        let url: HttpUrl = "http://[::1]:8080/auth".parse().unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.to_string(), "http://[::1]:8080/auth");
+        assert!("https://auth.local/".parse::<HttpUrl>().is_err());
+        assert!("http://:8080/".parse::<HttpUrl>().is_err());
+        assert!("http://auth.local:99999/".parse::<HttpUrl>().is_err());
+    }
+}
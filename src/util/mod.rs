@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+
+mod cidr;
+mod group_route;
+mod http_url;
+mod name_pattern;
+mod rate_limit;
+mod token_meta;
+mod token_set;
+#[cfg(feature = "web")]
+mod web_listen;
+
+pub use cidr::Cidr;
+pub use group_route::GroupRoute;
+pub use http_url::HttpUrl;
+pub use name_pattern::NamePattern;
+pub use rate_limit::RateLimiter;
+pub use token_meta::{TokenMeta, TokenMetaEntry, TokenMetaMap};
+pub use token_set::SharedTokenSet;
+#[cfg(feature = "web")]
+pub use web_listen::{WebCapability, WebListen};
+
+/// ipv4映射的ipv6地址还原成ipv4
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ipv6) => {
+            if let Some(ipv4) = ipv6.to_ipv4_mapped() {
+                IpAddr::V4(ipv4)
+            } else {
+                ip
+            }
+        }
+    }
+}
+
+/// 可以对外公布的单播地址
+pub fn is_usable_unicast(ip: IpAddr) -> bool {
+    match canonical_ip(ip) {
+        IpAddr::V4(ipv4) => {
+            !(ipv4.is_unspecified()
+                || ipv4.is_loopback()
+                || ipv4.is_multicast()
+                || ipv4.is_broadcast()
+                || ipv4.is_link_local())
+        }
+        IpAddr::V6(ipv6) => {
+            !(ipv6.is_unspecified()
+                || ipv6.is_loopback()
+                || ipv6.is_multicast()
+                || (ipv6.segments()[0] & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// 可分配给客户端的地址数，不含网络地址、广播地址和网关
+pub fn usable_hosts(gateway: u32, netmask: u32) -> u32 {
+    let network = gateway & netmask;
+    let broadcast = gateway | !netmask;
+    let hosts = (broadcast - network).saturating_sub(1);
+    if gateway > network && gateway < broadcast {
+        hosts - 1
+    } else {
+        hosts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn hosts(gateway: &str, netmask: &str) -> u32 {
+        let gateway: Ipv4Addr = gateway.parse().unwrap();
+        let netmask: Ipv4Addr = netmask.parse().unwrap();
+        usable_hosts(gateway.into(), netmask.into())
+    }
+
+    #[test]
+    fn usable_hosts_small_subnet() {
+        assert_eq!(hosts("10.26.0.1", "255.255.255.0"), 253);
+        assert_eq!(hosts("10.26.0.1", "255.255.255.252"), 1);
+        assert_eq!(hosts("10.26.0.1", "255.255.255.254"), 0);
+        assert_eq!(hosts("10.26.0.0", "255.255.255.254"), 0);
+        assert_eq!(hosts("10.26.0.1", "255.255.255.255"), 0);
+    }
+
+    #[test]
+    fn usable_unicast() {
+        let usable = |ip: &str| is_usable_unicast(ip.parse().unwrap());
+        assert!(usable("1.2.3.4"));
+        assert!(usable("192.168.1.10"));
+        assert!(usable("2001:db8::1"));
+        assert!(usable("::ffff:1.2.3.4"));
+        assert!(!usable("0.0.0.0"));
+        assert!(!usable("127.0.0.1"));
+        assert!(!usable("224.0.0.1"));
+        assert!(!usable("255.255.255.255"));
+        assert!(!usable("169.254.1.1"));
+        assert!(!usable("::"));
+        assert!(!usable("::1"));
+        assert!(!usable("ff02::1"));
+        assert!(!usable("fe80::1"));
+        assert!(!usable("::ffff:127.0.0.1"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// 名称匹配规则，不含通配符时完全匹配，支持*(任意个字符)和?(一个字符)，不区分大小写
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamePattern {
+    pattern: Vec<char>,
+}
+
+impl NamePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().flat_map(char::to_lowercase).collect();
+        glob(&self.pattern, &name)
+    }
+}
+
+/// 通配符匹配，*匹配失败时回溯到上一个*，不会出现指数级的回溯
+fn glob(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // 上一个*的位置和它匹配到的名称位置
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|v| *v == '*')
+}
+
+impl FromStr for NamePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("名称不能为空".into());
+        }
+        Ok(NamePattern {
+            pattern: s.chars().flat_map(char::to_lowercase).collect(),
+        })
+    }
+}
+
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern.iter().collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> NamePattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn exact() {
+        let admin = pattern("admin");
+        assert!(admin.matches("admin"));
+        assert!(admin.matches("Admin"));
+        assert!(!admin.matches("admin1"));
+        assert!(!admin.matches("admi"));
+        assert!("  ".parse::<NamePattern>().is_err());
+    }
+
+    #[test]
+    fn wildcard() {
+        let sys = pattern("sys*");
+        assert!(sys.matches("sys"));
+        assert!(sys.matches("system"));
+        assert!(!sys.matches("mysys"));
+        let any = pattern("*admin*");
+        assert!(any.matches("my-admin-group"));
+        assert!(!any.matches("adm-in"));
+        let one = pattern("ops-?");
+        assert!(one.matches("ops-1"));
+        assert!(!one.matches("ops-12"));
+        assert!(!one.matches("ops-"));
+        assert!(pattern("a*b*c").matches("aXbYbZc"));
+        assert!(!pattern("a*b*c").matches("aXbYbZ"));
+        assert!(pattern("*").matches(""));
+    }
+}
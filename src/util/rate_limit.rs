@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// 固定窗口限流，每个窗口内最多放行max次
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    state: Mutex<(Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            state: Mutex::new((Instant::now(), 0)),
+        }
+    }
+    /// 放行返回true
+    pub fn check(&self) -> bool {
+        self.check_at(Instant::now())
+    }
+    fn check_at(&self, now: Instant) -> bool {
+        let mut guard = self.state.lock();
+        let (start, count) = &mut *guard;
+        if now.saturating_duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.max {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_limit() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(1));
+        let now = Instant::now();
+        assert!(limiter.check_at(now));
+        assert!(limiter.check_at(now));
+        assert!(!limiter.check_at(now + Duration::from_millis(500)));
+        assert!(limiter.check_at(now + Duration::from_secs(1)));
+    }
+}
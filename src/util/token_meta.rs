@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::token_set::token_hash;
+
+/// token对应的客户信息，标签用于指标和管理后台分组
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMeta {
+    pub customer: String,
+    pub label: String,
+    // 组网内客户端数上限
+    pub quota: Option<usize>,
+    // 同时在线的客户端数上限
+    pub max_online: Option<usize>,
+}
+
+/// 一条--token-meta配置，格式为 token=abc,customer=ACME,label=prod,quota=50,max_online=20，
+/// 除token外都可以省略
+#[derive(Clone, PartialEq, Eq)]
+pub struct TokenMetaEntry {
+    pub token: String,
+    pub meta: TokenMeta,
+}
+
+impl FromStr for TokenMetaEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut token = None;
+        let mut meta = TokenMeta::default();
+        for item in s.split(',') {
+            let (key, value) = match item.split_once('=') {
+                Some(kv) => kv,
+                None => return Err(format!("{} 格式错误，应为key=value", item)),
+            };
+            match key.trim() {
+                "token" => token = Some(value.to_string()),
+                "customer" => meta.customer = value.trim().to_string(),
+                "label" => meta.label = value.trim().to_string(),
+                "quota" => meta.quota = Some(parse_limit("quota", value)?),
+                "max_online" => meta.max_online = Some(parse_limit("max_online", value)?),
+                key => return Err(format!("未知的字段 {}", key)),
+            }
+        }
+        match token {
+            Some(token) if !token.is_empty() => Ok(TokenMetaEntry { token, meta }),
+            _ => Err("缺少token".into()),
+        }
+    }
+}
+
+fn parse_limit(key: &str, value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(0) => Err(format!("{}不能为0", key)),
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("{}错误 {} {}", key, value, e)),
+    }
+}
+
+/// 不输出token
+impl fmt::Debug for TokenMetaEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.meta.fmt(f)
+    }
+}
+
+/// token到客户信息的映射，只保存token的sha256，没有配置的token标签为空
+#[derive(Clone, Default)]
+pub struct TokenMetaMap(Arc<HashMap<[u8; 32], Arc<TokenMeta>>>);
+
+impl TokenMetaMap {
+    pub fn new(entries: Vec<TokenMetaEntry>) -> Self {
+        Self(Arc::new(
+            entries
+                .into_iter()
+                .map(|entry| (token_hash(&entry.token), Arc::new(entry.meta)))
+                .collect(),
+        ))
+    }
+    pub fn get(&self, token: &str) -> Option<Arc<TokenMeta>> {
+        if self.0.is_empty() {
+            return None;
+        }
+        self.0.get(&token_hash(token)).cloned()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// 按客户和标签排序，保证输出稳定
+    pub fn sorted(&self) -> Vec<TokenMeta> {
+        let mut list: Vec<TokenMeta> = self.0.values().map(|v| v.as_ref().clone()).collect();
+        list.sort_by(|a, b| {
+            (&a.customer, &a.label, a.quota, a.max_online).cmp(&(
+                &b.customer,
+                &b.label,
+                b.quota,
+                b.max_online,
+            ))
+        });
+        list
+    }
+}
+
+impl fmt::Debug for TokenMetaMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.sorted().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_lookup() {
+        let entry: TokenMetaEntry = "token=abc,customer=ACME,label=prod,quota=50,max_online=20"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.token, "abc");
+        assert_eq!(entry.meta.customer, "ACME");
+        assert_eq!(entry.meta.label, "prod");
+        assert_eq!(entry.meta.quota, Some(50));
+        assert_eq!(entry.meta.max_online, Some(20));
+        assert!("token=abc,max_online=0".parse::<TokenMetaEntry>().is_err());
+        assert!(!format!("{:?}", entry).contains("abc"));
+        let bare: TokenMetaEntry = "token=xyz".parse().unwrap();
+        assert_eq!(bare.meta, TokenMeta::default());
+        assert!("customer=ACME".parse::<TokenMetaEntry>().is_err());
+        assert!("token=abc,quota=0".parse::<TokenMetaEntry>().is_err());
+        assert!("token=abc,owner=x".parse::<TokenMetaEntry>().is_err());
+        assert!("token=abc,prod".parse::<TokenMetaEntry>().is_err());
+
+        let map = TokenMetaMap::new(vec![entry, bare]);
+        assert_eq!(map.get("abc").unwrap().customer, "ACME");
+        assert_eq!(map.get("xyz").unwrap().label, "");
+        assert!(map.get("other").is_none());
+        assert!(TokenMetaMap::default().get("abc").is_none());
+        assert_eq!(map.sorted()[0], TokenMeta::default());
+        assert!(!format!("{:?}", map).contains("xyz"));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use sha2::Digest;
+
+/// token白名单，开启--token-hash时只保存token的sha256，内存占用固定且不常驻明文
+#[derive(Clone)]
+pub enum TokenSet {
+    Raw(HashSet<String>),
+    Hashed(HashSet<[u8; 32]>),
+}
+
+pub(super) fn token_hash(token: &str) -> [u8; 32] {
+    sha2::Sha256::digest(token.as_bytes()).into()
+}
+
+impl TokenSet {
+    pub fn new(tokens: Vec<String>, hashed: bool) -> Self {
+        if hashed {
+            TokenSet::Hashed(tokens.iter().map(|token| token_hash(token)).collect())
+        } else {
+            TokenSet::Raw(tokens.into_iter().collect())
+        }
+    }
+    pub fn contains(&self, token: &str) -> bool {
+        match self {
+            TokenSet::Raw(set) => set.contains(token),
+            TokenSet::Hashed(set) => set.contains(&token_hash(token)),
+        }
+    }
+    pub fn len(&self) -> usize {
+        match self {
+            TokenSet::Raw(set) => set.len(),
+            TokenSet::Hashed(set) => set.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// 排序后的token(hash模式下为十六进制的hash)，用于计算配置hash
+    pub fn sorted(&self) -> Vec<String> {
+        let mut list: Vec<String> = match self {
+            TokenSet::Raw(set) => set.iter().cloned().collect(),
+            TokenSet::Hashed(set) => set
+                .iter()
+                .map(|hash| hash.iter().map(|v| format!("{:02x}", v)).collect())
+                .collect(),
+        };
+        list.sort();
+        list
+    }
+}
+
+impl fmt::Debug for TokenSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSet::Raw(set) => set.fmt(f),
+            TokenSet::Hashed(set) => write!(f, "Hashed(len={})", set.len()),
+        }
+    }
+}
+
+/// 运行时可以整体替换的token白名单，克隆后共享同一份数据
+#[derive(Clone)]
+pub struct SharedTokenSet(Arc<RwLock<TokenSet>>);
+
+impl SharedTokenSet {
+    pub fn new(tokens: Vec<String>, hashed: bool) -> Self {
+        Self(Arc::new(RwLock::new(TokenSet::new(tokens, hashed))))
+    }
+    pub fn contains(&self, token: &str) -> bool {
+        self.0.read().contains(token)
+    }
+    pub fn len(&self) -> usize {
+        self.0.read().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.read().is_empty()
+    }
+    pub fn sorted(&self) -> Vec<String> {
+        self.0.read().sorted()
+    }
+    /// 整体替换，沿用原来是否只保存hash，新集合在锁外构建好再交换，不会出现白名单为空的时刻，
+    /// 返回替换后的数量
+    pub fn replace(&self, tokens: Vec<String>) -> usize {
+        let hashed = matches!(*self.0.read(), TokenSet::Hashed(_));
+        let set = TokenSet::new(tokens, hashed);
+        let len = set.len();
+        *self.0.write() = set;
+        len
+    }
+}
+
+impl fmt::Debug for SharedTokenSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.read().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_matches_raw() {
+        let tokens: Vec<String> = ["1234", "abc", "", "很长的token"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let raw = TokenSet::new(tokens.clone(), false);
+        let hashed = TokenSet::new(tokens, true);
+        assert_eq!(raw.sorted().len(), hashed.sorted().len());
+        for token in [
+            "1234",
+            "abc",
+            "",
+            "很长的token",
+            "123",
+            "abcd",
+            "ABC",
+            " 1234",
+        ] {
+            assert_eq!(raw.contains(token), hashed.contains(token), "{}", token);
+        }
+        assert!(hashed.contains("abc"));
+        assert!(!hashed.contains("abcd"));
+        assert_eq!(format!("{:?}", hashed), "Hashed(len=4)");
+    }
+
+    #[test]
+    fn shared_replace() {
+        let shared = SharedTokenSet::new(vec!["a".to_string(), "b".to_string()], true);
+        let other = shared.clone();
+        assert_eq!(
+            shared.replace(vec!["b".to_string(), "c".to_string(), "c".to_string()]),
+            2
+        );
+        assert!(!other.contains("a"));
+        assert!(other.contains("b"));
+        assert!(other.contains("c"));
+        assert_eq!(format!("{:?}", other), "Hashed(len=2)");
+    }
+}
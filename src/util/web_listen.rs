@@ -0,0 +1,85 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// web监听可以访问的接口范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebCapability {
+    /// 全部接口和页面
+    Admin,
+    /// 只读的查询接口和页面，不能修改配置和状态
+    ReadOnly,
+    /// 只有/metrics和/metrics.json
+    Metrics,
+}
+
+impl FromStr for WebCapability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "admin" => Ok(WebCapability::Admin),
+            "readonly" => Ok(WebCapability::ReadOnly),
+            "metrics" => Ok(WebCapability::Metrics),
+            s => Err(format!("{} 不是admin、readonly或metrics", s)),
+        }
+    }
+}
+
+impl fmt::Display for WebCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebCapability::Admin => f.write_str("admin"),
+            WebCapability::ReadOnly => f.write_str("readonly"),
+            WebCapability::Metrics => f.write_str("metrics"),
+        }
+    }
+}
+
+/// 一个web监听，格式为 地址:端口[,权限]，权限默认admin，例如 127.0.0.1:29870,admin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebListen {
+    pub addr: SocketAddr,
+    pub capability: WebCapability,
+}
+
+impl FromStr for WebListen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, capability) = match s.split_once(',') {
+            Some((addr, capability)) => (addr, capability.parse()?),
+            None => (s, WebCapability::Admin),
+        };
+        let addr = addr
+            .trim()
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("{} 地址错误 {}", s, e))?;
+        Ok(WebListen { addr, capability })
+    }
+}
+
+impl fmt::Display for WebListen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.addr, self.capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let listen: WebListen = "127.0.0.1:29870".parse().unwrap();
+        assert_eq!(listen.capability, WebCapability::Admin);
+        let listen: WebListen = "[::]:9100,metrics".parse().unwrap();
+        assert_eq!(listen.addr.port(), 9100);
+        assert_eq!(listen.capability, WebCapability::Metrics);
+        assert_eq!(listen.to_string(), "[::]:9100,metrics");
+        let listen: WebListen = "0.0.0.0:29880,readonly".parse().unwrap();
+        assert_eq!(listen.capability, WebCapability::ReadOnly);
+        assert!("0.0.0.0:29880,root".parse::<WebListen>().is_err());
+        assert!("29880".parse::<WebListen>().is_err());
+    }
+}
@@ -55,6 +55,11 @@ function post(url, data, success, error,) {
             }
         },
         error: function (e) {
+            // 业务错误的http状态码不是200，响应体仍然是{code,message}
+            if (e.responseJSON && e.responseJSON.code) {
+                this.success(e.responseJSON);
+                return;
+            }
             console.log(e)
             if (error) {
                 error(e)
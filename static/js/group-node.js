@@ -441,6 +441,7 @@ const tooltip = new G6.Tooltip({
         <li>IP: ${toString(e.item.getModel().ip)}</li>
         <li>上传: ${toString(e.item.getModel().upStream)}</li>
         <li>下载: ${toString(e.item.getModel().downStream)}</li>
+        <li>延迟/丢包/抖动: ${toString(e.item.getModel().linkQuality)}</li>
         <li>NAT类型: ${toString(e.item.getModel().natType)}</li>
         <li>注册时间: ${toString(e.item.getModel().lastJoinTime)}</li>
       </ul>`